@@ -0,0 +1,197 @@
+use crate::dates::DateResolver;
+use crate::metadata::audio::{extract_audio_metadata, read_audio_tags, AudioTags};
+use crate::metadata::{date_folder, file_metadata, Embedded, Metadata};
+use crate::plan::PlannedAction;
+use crate::traits::processor::Processor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Encoders of phone recorder apps, as found in the tags of their recordings.
+const VOICE_MEMO_ENCODERS: &[&str] = &["com.apple.voicememos", "voice recorder", "sound recorder"];
+/// Name prefixes of untagged recordings, e.g. `New Recording 12.m4a` or the
+/// `PTT-20230114-WA0001.opus` voice messages of WhatsApp.
+const VOICE_MEMO_NAMES: &[&str] = &["new recording", "recording", "voice", "ptt-"];
+
+/// What an audio file holds, which decides its category.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum AudioKind {
+    #[default]
+    Music,
+    Podcast,
+    VoiceMemo,
+}
+
+impl AudioKind {
+    fn of(path: &Path, tags: &AudioTags) -> AudioKind {
+        if tags.podcast {
+            return AudioKind::Podcast;
+        }
+        let encoder = tags.encoder.as_deref().unwrap_or_default().to_lowercase();
+        let untagged = tags.title.is_none() && tags.artist.is_none() && tags.album.is_none();
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_lowercase();
+        if VOICE_MEMO_ENCODERS.iter().any(|app| encoder.contains(app)) || (untagged && VOICE_MEMO_NAMES.iter().any(|prefix| name.starts_with(prefix))) {
+            AudioKind::VoiceMemo
+        } else {
+            AudioKind::Music
+        }
+    }
+}
+
+/// Files music by its tags as
+/// `Music/{albumartist}/{year} - {album}/{disc}-{track:02} {title}.{ext}`,
+/// podcast episodes as `Podcasts/{album}/{title}.{ext}` and voice memos by
+/// date. The tags and the container of recordings are read when the
+/// processor is created for a file.
+#[derive(Default)]
+pub struct AudioProcessor {
+    pub dates: Arc<DateResolver>,
+    pub tags: AudioTags,
+    pub kind: AudioKind,
+    pub embedded: Embedded,
+}
+
+impl AudioProcessor {
+    pub fn for_file(path: &Path, mime: &str, dates: Arc<DateResolver>) -> Self {
+        let tags = read_audio_tags(path).unwrap_or_default();
+        let kind = AudioKind::of(path, &tags);
+        AudioProcessor { dates, tags, kind, embedded: Embedded::read(path, mime) }
+    }
+
+    /// The path of `path` relative to the destination directory.
+    fn relative_path(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        let file_name = path.file_name().unwrap_or_default();
+        let tags = &self.tags;
+        match self.kind {
+            AudioKind::Music => {
+                let album = tags.album.as_deref().unwrap_or("Unknown Album");
+                let album_folder = match tags.year {
+                    Some(year) => format!("{} - {}", year, album),
+                    None => album.to_string(),
+                };
+                let folder = PathBuf::from("Music").join(tags.album_artist().unwrap_or("Unknown Artist")).join(album_folder);
+                match (tags.track, tags.title.as_deref()) {
+                    (Some(track), title) => folder.join(self.tagged_name(path, &format!("{}-{:02} {}", tags.disc.unwrap_or(1), track, title.unwrap_or("Track")))),
+                    (None, Some(title)) => folder.join(self.tagged_name(path, title)),
+                    (None, None) => folder.join(file_name),
+                }
+            }
+            AudioKind::Podcast => {
+                let show = tags.album.as_deref().or(tags.album_artist()).unwrap_or("Unknown Podcast");
+                let folder = PathBuf::from("Podcasts").join(show);
+                match tags.title.as_deref() {
+                    Some(title) => folder.join(self.tagged_name(path, title)),
+                    None => folder.join(file_name),
+                }
+            }
+            AudioKind::VoiceMemo => PathBuf::from("Voice_Memos").join(date_folder(metadata)).join(file_name),
+        }
+    }
+
+    /// A file name made of `stem` and the lowercased extension of `path`.
+    fn tagged_name(&self, path: &Path, stem: &str) -> String {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(extension) => format!("{}.{}", stem, extension.to_lowercase()),
+            None => stem.to_string(),
+        }
+    }
+}
+
+impl Processor for AudioProcessor {
+    fn plan(&self, path: &Path, destination: &Path) -> PlannedAction {
+        let metadata = self.metadata(path);
+        PlannedAction {
+            source: path.to_path_buf(),
+            destination: destination.join(self.relative_path(path, &metadata)),
+            reason: format!("{} by audio tags", self.category()),
+            metadata,
+            processor: self.name().to_string(),
+            category: self.category().to_string(),
+            rule: None,
+            notes: Vec::new(),
+            follows: None,
+        }
+    }
+
+    fn get_destination_subfolder(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        self.relative_path(path, metadata).parent().map(Path::to_path_buf).unwrap_or_default()
+    }
+
+    fn category(&self) -> &str {
+        match self.kind {
+            AudioKind::Music => "Music",
+            AudioKind::Podcast => "Podcasts",
+            AudioKind::VoiceMemo => "Voice_Memos",
+        }
+    }
+
+    fn dates(&self) -> &DateResolver {
+        &self.dates
+    }
+
+    fn metadata(&self, path: &Path) -> Metadata {
+        let mut metadata = file_metadata(path, &self.dates, &self.embedded);
+        let mut tag_fields = extract_audio_metadata(&self.tags);
+        if self.kind == AudioKind::VoiceMemo {
+            // Recordings are filed by their date, which `{year}` has to agree with
+            tag_fields.remove("year");
+        }
+        metadata.extend(tag_fields);
+        metadata
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dates::DateOptions;
+
+    fn tags(title: &str, artist: &str, album: &str) -> AudioTags {
+        AudioTags {
+            title: Some(title.to_string()),
+            artist: Some(artist.to_string()),
+            album: Some(album.to_string()),
+            ..AudioTags::default()
+        }
+    }
+
+    #[test]
+    fn test_music_is_filed_by_tags() {
+        let path = Path::new("/downloads/track03.MP3");
+        let mut processor = AudioProcessor {
+            tags: AudioTags { album_artist: Some("Various Artists".to_string()), year: Some(1997), track: Some(3), disc: Some(2), ..tags("Karma Police", "Radiohead", "OK Computer") },
+            ..AudioProcessor::default()
+        };
+        assert_eq!(processor.plan(path, Path::new("dest")).destination, Path::new("dest/Music/Various Artists/1997 - OK Computer/2-03 Karma Police.mp3"));
+        assert_eq!(processor.metadata(path).get("albumartist").map(String::as_str), Some("Various Artists"));
+
+        processor.tags = AudioTags::default();
+        assert_eq!(
+            processor.plan(path, Path::new("dest")).destination,
+            Path::new("dest/Music/Unknown Artist/Unknown Album/track03.MP3"),
+            "Untagged files must keep their name."
+        );
+    }
+
+    #[test]
+    fn test_podcasts_and_voice_memos_are_kept_apart() {
+        let episode = AudioTags { podcast: true, ..tags("Episode 12", "Host", "Tech Talk") };
+        let path = Path::new("/downloads/ep12.mp3");
+        assert_eq!(AudioKind::of(path, &episode), AudioKind::Podcast);
+        let processor = AudioProcessor { tags: episode, kind: AudioKind::Podcast, ..AudioProcessor::default() };
+        assert_eq!(processor.plan(path, Path::new("dest")).destination, Path::new("dest/Podcasts/Tech Talk/Episode 12.mp3"));
+        assert_eq!(processor.category(), "Podcasts");
+
+        let memo = AudioTags { encoder: Some("com.apple.VoiceMemos (iPhone Version 16.0)".to_string()), ..tags("Home", "", "") };
+        assert_eq!(AudioKind::of(Path::new("Home.m4a"), &memo), AudioKind::VoiceMemo);
+        assert_eq!(AudioKind::of(Path::new("New Recording 3.m4a"), &AudioTags::default()), AudioKind::VoiceMemo);
+        assert_eq!(AudioKind::of(Path::new("Recording of a band.mp3"), &tags("Live", "Band", "Tour")), AudioKind::Music);
+
+        let path = Path::new("/recordings/Recording_20230114_101530.m4a");
+        let processor = AudioProcessor { tags: AudioTags { year: Some(1999), ..memo }, kind: AudioKind::VoiceMemo, ..AudioProcessor::default() };
+        let dates = DateResolver::new(&DateOptions { filename_patterns: vec!["Recording_{year}{month}{day}_{hour}{minute}{second}".to_string()], ..DateOptions::default() }).unwrap();
+        let processor = AudioProcessor { dates: Arc::new(dates), ..processor };
+        let plan = processor.plan(path, Path::new("dest"));
+        assert_eq!(plan.destination, Path::new("dest/Voice_Memos/2023/01 - January/Recording_20230114_101530.m4a"));
+        assert_eq!(plan.metadata.get("year").map(String::as_str), Some("2023"), "A voice memo's year is that of its date.");
+    }
+}
@@ -1,4 +1,11 @@
-use clap::{Arg, Command, ArgAction};
+use clap::{builder::PossibleValuesParser, Arg, Command, ArgAction};
+
+use crate::conflict::ConflictPolicy;
+use crate::dedupe::DuplicateAction;
+use crate::overlap::NestedDestination;
+use crate::report::ReportFormat;
+use crate::sniff::TypePrecedence;
+use crate::transfer::TransferMode;
 
 pub fn build_cli() -> Command {
     Command::new("Plexisort")
@@ -10,26 +17,141 @@ pub fn build_cli() -> Command {
             .long("config")
             .value_name("FILE")
             .help("Sets a custom config file")
+            .global(true)
             .action(ArgAction::Set)
             .num_args(1))
         .arg(Arg::new("source")
             .long("source")
             .value_name("SOURCE_DIR")
             .help("Sets the source directory(s)")
+            .global(true)
             .action(ArgAction::Append)
             .num_args(1..))
         .arg(Arg::new("destination")
             .long("destination")
             .value_name("DEST_DIR")
             .help("Sets the destination directory")
+            .global(true)
             .action(ArgAction::Set)
             .num_args(1))
+        .arg(Arg::new("include")
+            .long("include")
+            .value_name("GLOB")
+            .help("Only organizes files matching this glob, in addition to the config file's include list")
+            .global(true)
+            .action(ArgAction::Append))
+        .arg(Arg::new("exclude")
+            .long("exclude")
+            .value_name("GLOB")
+            .help("Leaves files and directories matching this glob alone, in addition to the config file's exclude list")
+            .global(true)
+            .action(ArgAction::Append))
         .arg(Arg::new("dry-run")
             .long("dry-run")
             .help("Runs the organizer without making any changes")
             .action(ArgAction::Set))
+        .arg(Arg::new("on-conflict")
+            .long("on-conflict")
+            .value_name("POLICY")
+            .help("What to do when a file already exists at the destination (default: rename)")
+            .value_parser(PossibleValuesParser::new(ConflictPolicy::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("on-duplicate")
+            .long("on-duplicate")
+            .value_name("ACTION")
+            .help("What to do with files whose contents already exist in a source or the destination (default: off)")
+            .value_parser(PossibleValuesParser::new(DuplicateAction::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("transfer")
+            .long("transfer")
+            .value_name("MODE")
+            .help("How files get to the destination (default: move)")
+            .value_parser(PossibleValuesParser::new(TransferMode::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("type-precedence")
+            .long("type-precedence")
+            .value_name("SOURCE")
+            .help("Whether a file's contents or its extension decide its type when they disagree (default: content)")
+            .value_parser(PossibleValuesParser::new(TypePrecedence::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("nested-destination")
+            .long("nested-destination")
+            .value_name("HANDLING")
+            .help("What to do when the destination lies inside a source directory (default: error)")
+            .value_parser(PossibleValuesParser::new(NestedDestination::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("quiet-period")
+            .long("quiet-period")
+            .value_name("SECONDS")
+            .help("Leaves alone files modified less than this many seconds ago (default: 0)")
+            .value_parser(clap::value_parser!(u64))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("jobs")
+            .short('j')
+            .long("jobs")
+            .value_name("N")
+            .help("Number of threads that inspect files (default: one per CPU)")
+            .value_parser(clap::value_parser!(usize))
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("report")
+            .long("report")
+            .value_name("FILE")
+            .help("Writes a report of what happened to each file")
+            .global(true)
+            .action(ArgAction::Set))
+        .arg(Arg::new("report-format")
+            .long("report-format")
+            .value_name("FORMAT")
+            .help("Format of the report (default: from the file's extension, otherwise json)")
+            .value_parser(PossibleValuesParser::new(ReportFormat::NAMES))
+            .global(true)
+            .action(ArgAction::Set))
         .arg(Arg::new("undo")
             .long("undo")
-            .help("Reverts the last set of file movements")
+            .value_name("SESSION")
+            .help("Reverts the file movements of a session (default: the latest one that was not undone)")
+            .num_args(0..=1)
+            .default_missing_value("latest")
             .action(ArgAction::Set))
+        .subcommand(Command::new("history")
+            .about("Lists past sessions that can be undone")
+            .arg(Arg::new("forget")
+                .long("forget")
+                .value_name("SESSION")
+                .help("Removes a session from the history and deletes the files it replaced, which were kept for undo")
+                .action(ArgAction::Set)))
+        .subcommand(Command::new("recover")
+            .about("Completes actions that were interrupted by a crash, so that their sessions can be undone"))
+        .subcommand(Command::new("plan")
+            .about("Writes the moves a run would make to a plan file for review, instead of making them")
+            .arg(Arg::new("output")
+                .short('o')
+                .long("output")
+                .value_name("FILE")
+                .help("The plan file to write")
+                .required(true)
+                .action(ArgAction::Set)))
+        .subcommand(Command::new("apply")
+            .about("Carries out a plan file, leaving alone files that changed since it was written")
+            .arg(Arg::new("plan")
+                .value_name("PLAN")
+                .help("The plan file written by `plexisort plan`")
+                .required(true)
+                .action(ArgAction::Set)))
+        .subcommand(Command::new("watch")
+            .about("Organizes the source directories, then keeps organizing new files as they arrive until stopped")
+            .arg(Arg::new("debounce")
+                .long("debounce")
+                .value_name("SECONDS")
+                .help("How long a new file must stay unchanged before it is organized")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("2")
+                .action(ArgAction::Set)))
 }
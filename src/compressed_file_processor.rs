@@ -1,47 +1,26 @@
-use crate::organizer::organize_file;
-use crate::processing_mode::ProcessingMode;
+use crate::dates::DateResolver;
+use crate::metadata::Metadata;
 use crate::traits::processor::Processor;
-use std::fs;
-use std::path::PathBuf;
-use log::{debug, error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 
-pub struct CompressedFileProcessor;
+#[derive(Default)]
+pub struct CompressedFileProcessor {
+    pub dates: Arc<DateResolver>,
+}
 
 impl Processor for CompressedFileProcessor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode) {
-        // Determine the destination directory for compressed files
-        let destination_dir = destination.join(self.get_destination_subfolder(path));
-
-
-        // Correctly specify the destination path for the file
-        let destination_path = destination_dir.join(path.file_name().unwrap());
+    fn get_destination_subfolder(&self, _path: &Path, _metadata: &Metadata) -> PathBuf {
+        PathBuf::from("Compressed_Files")
+    }
 
-        match mode {
-            ProcessingMode::DryRun(virtual_directory) => {
-                // In DryRun mode, just simulate the action
-                debug!("Would move {} to {}", path.display(), destination_path.display());
-                let path_parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-                virtual_directory.add_path(&path_parts);
-            },
-            ProcessingMode::Live => {
-                // In Live mode, actually create the directory and move the file
-                // Ensure the directory structure exists
-                if let Err(e) = fs::create_dir_all(&destination_dir) {
-                    error!("Error creating destination directory: {}", e);
-                    return;
-                }
-                if let Err(e) = organize_file(path, &destination_path, mode) {
-                    error!("Failed to organize file: {}", e);
-                } else {
-                    debug!("Successfully moved file from {} to {}", path.display(), destination_path.display());
-                }
-            }
-        }
+    fn category(&self) -> &str {
+        "Compressed_Files"
     }
 
-    fn get_destination_subfolder(&self, _path: &PathBuf) -> PathBuf {
-        PathBuf::from("Compressed_Files")
+    fn dates(&self) -> &DateResolver {
+        &self.dates
     }
 }
 
@@ -51,6 +30,9 @@ impl Processor for CompressedFileProcessor {
 #[cfg(test)]
 mod compressed_file_processor_tests {
     use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
     use std::fs::{self, File};
     use tempfile::tempdir;
 
@@ -63,10 +45,10 @@ mod compressed_file_processor_tests {
         let compressed_file_path = source_dir.join("archive.zip");
         File::create(&compressed_file_path).unwrap();
 
-        let processor = CompressedFileProcessor {};
+        let processor = CompressedFileProcessor::default();
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&compressed_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&compressed_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
         let expected_destination = destination_dir.join("Compressed_Files").join("archive.zip");
         assert!(expected_destination.exists(), "Compressed file was not moved to the correct destination in Live mode.");
@@ -1,13 +1,56 @@
-use log;
 use serde::Deserialize;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 use std::error::Error;
 
+use crate::conflict::ConflictPolicy;
+use crate::dates::DateOptions;
+use crate::dedupe::DuplicateAction;
+use crate::filter::PathFilter;
+use crate::overlap::NestedDestination;
+use crate::rules::Rule;
+use crate::sniff::TypePrecedence;
+use crate::sidecar::SidecarOptions;
+use crate::stability::StabilityOptions;
+use crate::template::PathTemplate;
+use crate::transfer::TransferMode;
+use crate::traits::CATEGORIES;
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub source_directories: Vec<String>, // List of source directories as strings
     pub destination: String, // Single destination directory as string
+    #[serde(default)]
+    pub rules: Vec<Rule>, // Routing rules, evaluated in order before the built-in processors
+    #[serde(default)]
+    pub templates: HashMap<String, String>, // Destination path template per category
+    #[serde(default = "default_unknown_token")]
+    pub unknown_token: String, // Replaces template fields that a file has no value for
+    #[serde(default)]
+    pub on_conflict: ConflictPolicy, // What to do when the destination file already exists
+    #[serde(default)]
+    pub on_duplicate: DuplicateAction, // What to do with files whose contents already exist
+    #[serde(default)]
+    pub transfer: TransferMode, // Whether files are moved, copied or linked to the destination
+    #[serde(default)]
+    pub type_precedence: TypePrecedence, // Whether a file's contents or its extension decide its type
+    #[serde(default)]
+    pub nested_destination: NestedDestination, // What to do when the destination lies inside a source directory
+    #[serde(default)]
+    pub include: Vec<String>, // If set, only files matching one of these globs are organized
+    #[serde(default)]
+    pub exclude: Vec<String>, // Files and directories matching these globs are left alone
+    #[serde(default)]
+    pub stability: StabilityOptions, // When files count as still being written and are left alone
+    #[serde(default)]
+    pub sidecars: SidecarOptions, // Which files travel with the photo or video they belong to
+    #[serde(default)]
+    pub dates: DateOptions, // Where the date that files are filed by comes from
+}
+
+fn default_unknown_token() -> String {
+    "Unknown".to_string()
 }
 
 impl Config {
@@ -32,6 +75,29 @@ impl Config {
             }
         }
 
+        for (index, rule) in self.rules.iter().enumerate() {
+            rule.validate()
+                .map_err(|e| format!("Invalid rule #{} ({}): {}", index + 1, rule.label(), e))?;
+        }
+
+        for (category, template) in &self.templates {
+            if !CATEGORIES.iter().any(|c| c.eq_ignore_ascii_case(category)) {
+                return Err(format!(
+                    "Unknown template category '{}', expected one of: {}",
+                    category,
+                    CATEGORIES.join(", ")
+                ).into());
+            }
+            PathTemplate::parse(template)
+                .map_err(|e| format!("Invalid template for '{}': {}", category, e))?;
+        }
+
+        PathFilter::new(&self.include, &self.exclude)?;
+
+        if self.unknown_token.trim().is_empty() {
+            return Err("'unknown_token' must not be empty.".into());
+        }
+
         if !Path::new(&self.destination).exists() {
             log::warn!("Destination directory does not exist and will be created: {}", self.destination);
         }
@@ -45,6 +111,19 @@ impl Default for Config {
         Config {
             source_directories: vec![], // No default source directories
             destination: String::new(), // An empty string as the default destination
+            rules: vec![],
+            templates: HashMap::new(),
+            unknown_token: default_unknown_token(),
+            on_conflict: ConflictPolicy::default(),
+            on_duplicate: DuplicateAction::default(),
+            transfer: TransferMode::default(),
+            type_precedence: TypePrecedence::default(),
+            nested_destination: NestedDestination::default(),
+            include: vec![],
+            exclude: vec![],
+            stability: StabilityOptions::default(),
+            sidecars: SidecarOptions::default(),
+            dates: DateOptions::default(),
         }
     }
 }
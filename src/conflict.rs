@@ -0,0 +1,177 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// What to do when a file already exists at the destination path.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ConflictPolicy {
+    /// Leave the source file where it is.
+    Skip,
+    /// Move the file under a free name such as `photo (1).jpg`.
+    #[default]
+    Rename,
+    /// Replace the existing file.
+    Overwrite,
+    /// Replace the existing file only if the source was modified more recently.
+    KeepNewer,
+    /// Drop the source if its contents are identical to the existing file,
+    /// otherwise rename it.
+    Dedupe,
+}
+
+impl ConflictPolicy {
+    pub const NAMES: &'static [&'static str] = &["skip", "rename", "overwrite", "keep_newer", "dedupe"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "skip" => Some(ConflictPolicy::Skip),
+            "rename" => Some(ConflictPolicy::Rename),
+            "overwrite" => Some(ConflictPolicy::Overwrite),
+            "keep_newer" => Some(ConflictPolicy::KeepNewer),
+            "dedupe" => Some(ConflictPolicy::Dedupe),
+            _ => None,
+        }
+    }
+}
+
+/// The outcome of applying a `ConflictPolicy` to a planned move.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolution {
+    /// Move the source to this path. It differs from the planned destination
+    /// when the file was renamed, and may replace an existing file when the
+    /// policy allows it.
+    Move(PathBuf),
+    /// Leave the source in place.
+    Skip(String),
+    /// The source is an exact duplicate of this existing file and can be dropped.
+    DropDuplicate(PathBuf),
+}
+
+/// Applies `policy` to a move of `source` to `destination`.
+///
+/// `is_taken` tells whether a path is already occupied, and `existing` is the
+/// file whose contents currently occupy `destination`, if any. Both are
+/// parameters so that dry runs can take planned but not yet performed moves
+/// into account.
+pub fn resolve(
+    policy: ConflictPolicy,
+    source: &Path,
+    destination: &Path,
+    existing: Option<&Path>,
+    is_taken: &dyn Fn(&Path) -> bool,
+) -> io::Result<Resolution> {
+    if !is_taken(destination) {
+        return Ok(Resolution::Move(destination.to_path_buf()));
+    }
+
+    match policy {
+        ConflictPolicy::Skip => Ok(Resolution::Skip("destination already exists".to_string())),
+        ConflictPolicy::Rename => Ok(Resolution::Move(free_path(destination, is_taken))),
+        ConflictPolicy::Overwrite => Ok(Resolution::Move(destination.to_path_buf())),
+        ConflictPolicy::KeepNewer => {
+            let existing = match existing {
+                Some(existing) => existing,
+                None => return Ok(Resolution::Move(destination.to_path_buf())),
+            };
+            let source_modified = fs::metadata(source)?.modified()?;
+            let existing_modified = fs::metadata(existing)?.modified()?;
+            if source_modified > existing_modified {
+                Ok(Resolution::Move(destination.to_path_buf()))
+            } else {
+                Ok(Resolution::Skip("destination is newer or as new".to_string()))
+            }
+        }
+        ConflictPolicy::Dedupe => match existing {
+            Some(existing) if files_identical(source, existing)? => Ok(Resolution::DropDuplicate(existing.to_path_buf())),
+            _ => Ok(Resolution::Move(free_path(destination, is_taken))),
+        },
+    }
+}
+
+/// Returns the first of `name (1).ext`, `name (2).ext`, ... that is not taken.
+pub fn free_path(destination: &Path, is_taken: &dyn Fn(&Path) -> bool) -> PathBuf {
+    let stem = destination.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let extension = destination.extension().map(|e| e.to_string_lossy().to_string());
+
+    let mut counter = 1;
+    loop {
+        let file_name = match &extension {
+            Some(extension) => format!("{} ({}).{}", stem, counter, extension),
+            None => format!("{} ({})", stem, counter),
+        };
+        let candidate = destination.with_file_name(file_name);
+        if !is_taken(&candidate) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Compares two files byte by byte, after a cheap size check.
+pub fn files_identical(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+
+    let mut reader_a = BufReader::new(File::open(a)?);
+    let mut reader_b = BufReader::new(File::open(b)?);
+    let mut buffer_a = [0u8; 8192];
+    let mut buffer_b = [0u8; 8192];
+    loop {
+        let read = reader_a.read(&mut buffer_a)?;
+        if read == 0 {
+            return Ok(true);
+        }
+        reader_b.read_exact(&mut buffer_b[..read])?;
+        if buffer_a[..read] != buffer_b[..read] {
+            return Ok(false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_free_path_adds_numeric_suffix() {
+        let taken = |path: &Path| {
+            let name = path.file_name().unwrap().to_str().unwrap();
+            name == "photo.jpg" || name == "photo (1).jpg"
+        };
+        assert_eq!(free_path(Path::new("dest/photo.jpg"), &taken), PathBuf::from("dest/photo (2).jpg"));
+        assert_eq!(free_path(Path::new("dest/README"), &taken), PathBuf::from("dest/README (1)"));
+    }
+
+    #[test]
+    fn test_resolve_policies() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source.txt");
+        let existing = temp_dir.path().join("existing.txt");
+        writeln!(File::create(&source).unwrap(), "same").unwrap();
+        writeln!(File::create(&existing).unwrap(), "same").unwrap();
+        let taken = |path: &Path| path == existing;
+
+        let skip = resolve(ConflictPolicy::Skip, &source, &existing, Some(&existing), &taken).unwrap();
+        assert!(matches!(skip, Resolution::Skip(_)));
+
+        let overwrite = resolve(ConflictPolicy::Overwrite, &source, &existing, Some(&existing), &taken).unwrap();
+        assert_eq!(overwrite, Resolution::Move(existing.clone()));
+
+        let dedupe = resolve(ConflictPolicy::Dedupe, &source, &existing, Some(&existing), &taken).unwrap();
+        assert_eq!(dedupe, Resolution::DropDuplicate(existing.clone()));
+
+        writeln!(File::create(&source).unwrap(), "different").unwrap();
+        let dedupe = resolve(ConflictPolicy::Dedupe, &source, &existing, Some(&existing), &taken).unwrap();
+        assert_eq!(dedupe, Resolution::Move(temp_dir.path().join("existing (1).txt")));
+
+        let free = temp_dir.path().join("free.txt");
+        let no_conflict = resolve(ConflictPolicy::Skip, &source, &free, None, &taken).unwrap();
+        assert_eq!(no_conflict, Resolution::Move(free));
+    }
+}
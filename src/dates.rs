@@ -0,0 +1,298 @@
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::sync::LazyLock;
+use std::time::SystemTime;
+
+use crate::metadata::Embedded;
+
+/// Where the date of a file can come from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DateSource {
+    /// The EXIF `DateTimeOriginal` tag: when the photo was taken.
+    ExifOriginal,
+    /// The EXIF `DateTimeDigitized` tag, which ExifTool calls `CreateDate`:
+    /// when the image was stored digitally, e.g. when a film was scanned.
+    #[serde(alias = "exif_create_date")]
+    ExifDigitized,
+    /// The creation time in the metadata of a movie container.
+    Container,
+    /// A date in the file name, see `DateOptions::filename_patterns`.
+    Filename,
+    /// The time the file was last modified.
+    Modified,
+    /// The time the file was created on this file system, where the file
+    /// system and the kernel report it (`statx` on Linux).
+    Birth,
+    /// The time the file's inode last changed (Unix `ctime`).
+    Changed,
+}
+
+impl DateSource {
+    pub fn name(&self) -> &'static str {
+        match self {
+            DateSource::ExifOriginal => "exif_original",
+            DateSource::ExifDigitized => "exif_digitized",
+            DateSource::Container => "container",
+            DateSource::Filename => "filename",
+            DateSource::Modified => "modified",
+            DateSource::Birth => "birth",
+            DateSource::Changed => "changed",
+        }
+    }
+}
+
+/// The sources tried by default, from the most to the least telling.
+pub const DEFAULT_SOURCES: &[DateSource] = &[
+    DateSource::ExifOriginal,
+    DateSource::ExifDigitized,
+    DateSource::Container,
+    DateSource::Filename,
+    DateSource::Modified,
+];
+
+/// File name patterns of cameras, phones, messengers and screenshot tools
+/// that embed the capture date.
+pub const DEFAULT_FILENAME_PATTERNS: &[&str] = &[
+    "IMG_{year}{month}{day}_{hour}{minute}{second}",
+    "VID_{year}{month}{day}_{hour}{minute}{second}",
+    "PXL_{year}{month}{day}_{hour}{minute}{second}",
+    "IMG-{year}{month}{day}-WA",
+    "VID-{year}{month}{day}-WA",
+    "Screenshot {year}-{month}-{day} at {hour}.{minute}.{second}",
+    "Screenshot_{year}{month}{day}-{hour}{minute}{second}",
+    "signal-{year}-{month}-{day}-{hour}{minute}{second}",
+    "{year}{month}{day}_{hour}{minute}{second}",
+];
+
+/// Fields a file name pattern can use, with the digits each one matches.
+const PATTERN_FIELDS: &[(&str, usize)] = &[("year", 4), ("month", 2), ("day", 2), ("hour", 2), ("minute", 2), ("second", 2)];
+
+/// The `[dates]` config section.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct DateOptions {
+    /// The sources of a file's date, tried in order until one has a date.
+    pub sources: Vec<DateSource>,
+    /// Patterns of file names that contain a date, tried in order.
+    pub filename_patterns: Vec<String>,
+}
+
+impl Default for DateOptions {
+    fn default() -> Self {
+        DateOptions {
+            sources: DEFAULT_SOURCES.to_vec(),
+            filename_patterns: DEFAULT_FILENAME_PATTERNS.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+}
+
+/// A file name pattern such as `"IMG_{year}{month}{day}_{hour}{minute}{second}"`.
+///
+/// The pattern has to match the start of the file name, ignoring case, and
+/// whatever follows it is ignored. `{year}` takes four digits and the other
+/// fields two; `year`, `month` and `day` are required. `*` stands for any
+/// text.
+#[derive(Debug, Clone)]
+pub struct FilenamePattern {
+    regex: Regex,
+}
+
+impl FilenamePattern {
+    pub fn parse(pattern: &str) -> Result<Self, String> {
+        let mut regex = String::from("(?i)^");
+        let mut fields = Vec::new();
+        let mut chars = pattern.chars();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let field: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let Some((name, digits)) = PATTERN_FIELDS.iter().find(|(name, _)| *name == field) else {
+                        let names: Vec<&str> = PATTERN_FIELDS.iter().map(|(name, _)| *name).collect();
+                        return Err(format!("unknown field '{{{}}}' in date pattern '{}', expected one of: {}", field, pattern, names.join(", ")));
+                    };
+                    if fields.contains(name) {
+                        return Err(format!("field '{{{}}}' appears twice in date pattern '{}'", name, pattern));
+                    }
+                    fields.push(*name);
+                    regex.push_str(&format!(r"(?P<{}>\d{{{}}})", name, digits));
+                }
+                '*' => regex.push_str(".*?"),
+                c => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        if let Some(missing) = ["year", "month", "day"].iter().find(|name| !fields.contains(name)) {
+            return Err(format!("date pattern '{}' lacks the '{{{}}}' field", pattern, missing));
+        }
+
+        let regex = Regex::new(&regex).map_err(|e| format!("invalid date pattern '{}': {}", pattern, e))?;
+        Ok(FilenamePattern { regex })
+    }
+
+    /// The date in `file_name`, if the pattern matches it and the date exists.
+    pub fn date(&self, file_name: &str) -> Option<NaiveDateTime> {
+        let captures = self.regex.captures(file_name)?;
+        let field = |name: &str| captures.name(name).and_then(|m| m.as_str().parse::<u32>().ok());
+        let date = NaiveDate::from_ymd_opt(field("year")? as i32, field("month")?, field("day")?)?;
+        date.and_hms_opt(field("hour").unwrap_or(0), field("minute").unwrap_or(0), field("second").unwrap_or(0))
+    }
+}
+
+/// A file's date and the source it was taken from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResolvedDate {
+    /// The date in local time.
+    pub date: NaiveDateTime,
+    pub source: DateSource,
+}
+
+/// Decides which date a file is filed by, shared by all processors. The
+/// configured sources are tried in order; by default that is the order of
+/// how well they tell when a photo or recording was made, ending with the
+/// modification time, which is often just the time the file was copied.
+#[derive(Debug, Clone)]
+pub struct DateResolver {
+    sources: Vec<DateSource>,
+    filename_patterns: Vec<FilenamePattern>,
+}
+
+impl DateResolver {
+    pub fn new(options: &DateOptions) -> Result<Self, String> {
+        if options.sources.is_empty() {
+            return Err("the list of date sources must not be empty".to_string());
+        }
+        let filename_patterns = options.filename_patterns.iter().map(|p| FilenamePattern::parse(p)).collect::<Result<_, _>>()?;
+        Ok(DateResolver { sources: options.sources.clone(), filename_patterns })
+    }
+
+    /// The date of `path` from the first source that has one. The EXIF and
+    /// container dates are taken from `embedded`, which has been read for
+    /// the file.
+    pub fn resolve(&self, path: &Path, embedded: &Embedded) -> Option<ResolvedDate> {
+        self.sources.iter().find_map(|&source| {
+            let date = match source {
+                DateSource::ExifOriginal => embedded.image.as_ref().and_then(|image| image.taken).map(|date| date.local),
+                DateSource::ExifDigitized => embedded.image.as_ref().and_then(|image| image.digitized).map(|date| date.local),
+                DateSource::Container => embedded.video.as_ref().and_then(|video| video.created),
+                DateSource::Filename => self.filename_date(path),
+                DateSource::Modified => file_time(path, |metadata| metadata.modified().ok()),
+                DateSource::Birth => file_time(path, |metadata| metadata.created().ok()),
+                DateSource::Changed => file_time(path, changed_time),
+            };
+            date.map(|date| ResolvedDate { date, source })
+        })
+    }
+
+    /// The date in the file name, by the first pattern that matches it.
+    pub fn filename_date(&self, path: &Path) -> Option<NaiveDateTime> {
+        let file_name = path.file_name()?.to_str()?;
+        self.filename_patterns.iter().find_map(|pattern| pattern.date(file_name))
+    }
+}
+
+impl Default for DateResolver {
+    fn default() -> Self {
+        // Compiled once, since processors that are not given a resolver each make one
+        static DEFAULT: LazyLock<DateResolver> =
+            LazyLock::new(|| DateResolver::new(&DateOptions::default()).expect("The default date patterns are valid"));
+        DEFAULT.clone()
+    }
+}
+
+/// One of the times the file system keeps for `path`, in local time.
+fn file_time(path: &Path, time: impl Fn(&fs::Metadata) -> Option<SystemTime>) -> Option<NaiveDateTime> {
+    let time: DateTime<Utc> = time(&fs::metadata(path).ok()?)?.into();
+    Some(time.with_timezone(&Local).naive_local())
+}
+
+#[cfg(unix)]
+fn changed_time(metadata: &fs::Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    let since_epoch = std::time::Duration::new(u64::try_from(metadata.ctime()).ok()?, metadata.ctime_nsec() as u32);
+    SystemTime::UNIX_EPOCH.checked_add(since_epoch)
+}
+
+#[cfg(not(unix))]
+fn changed_time(_metadata: &fs::Metadata) -> Option<SystemTime> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    fn date(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    #[test]
+    fn test_default_patterns_read_common_names() {
+        let resolver = DateResolver::default();
+        let cases = [
+            ("IMG_20230114_101530.jpg", Some(date("2023-01-14 10:15:30"))),
+            ("Screenshot 2023-01-14 at 10.15.30.png", Some(date("2023-01-14 10:15:30"))),
+            ("PXL_20230114_101530123.MP.jpg", Some(date("2023-01-14 10:15:30"))),
+            ("VID-20230114-WA0001.mp4", Some(date("2023-01-14 00:00:00"))),
+            ("img_20230114_101530~2.JPG", Some(date("2023-01-14 10:15:30"))),
+            ("IMG_20231399_101530.jpg", None),
+            ("holiday IMG_20230114_101530.jpg", None),
+            ("IMG_0001.jpg", None),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(resolver.filename_date(Path::new(name)), expected, "Wrong date for {}", name);
+        }
+    }
+
+    #[test]
+    fn test_resolve_prefers_the_file_name_over_the_modification_time() {
+        let temp_dir = tempdir().unwrap();
+        let named = temp_dir.path().join("signal-2022-06-30-184512.jpg");
+        let unnamed = temp_dir.path().join("photo.jpg");
+        fs::write(&named, "not an image").unwrap();
+        fs::write(&unnamed, "not an image").unwrap();
+
+        let resolver = DateResolver::default();
+        let resolved = resolver.resolve(&named, &Embedded::default()).unwrap();
+        assert_eq!((resolved.date, resolved.source), (date("2022-06-30 18:45:12"), DateSource::Filename));
+        assert_eq!(resolver.resolve(&unnamed, &Embedded::default()).unwrap().source, DateSource::Modified);
+
+        let options = DateOptions { sources: vec![DateSource::Birth, DateSource::Modified, DateSource::Filename], ..DateOptions::default() };
+        let modified_first = DateResolver::new(&options).unwrap();
+        let source = modified_first.resolve(&named, &Embedded::default()).unwrap().source;
+        assert!(matches!(source, DateSource::Birth | DateSource::Modified), "The configured order must be followed, got {:?}", source);
+    }
+
+    #[test]
+    fn test_resolve_reads_the_movie_creation_time() {
+        let created = Utc.with_ymd_and_hms(2023, 1, 14, 10, 15, 30).unwrap();
+        let mut movie = Vec::new();
+        movie.extend_from_slice(b"\0\0\0\x10ftypisom\0\0\0\0");
+        movie.extend_from_slice(b"\0\0\0\x20moov\0\0\0\x18mvhd\0\0\0\0");
+        movie.extend_from_slice(&((created.timestamp() + 2_082_844_800) as u32).to_be_bytes());
+        movie.extend_from_slice(&[0; 8]);
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("clip.mp4");
+        fs::write(&path, movie).unwrap();
+
+        let resolved = DateResolver::default().resolve(&path, &Embedded::read(&path, "video/mp4")).unwrap();
+        assert_eq!(resolved.source, DateSource::Container);
+        assert_eq!(resolved.date, created.with_timezone(&Local).naive_local());
+
+        let as_text = DateResolver::default().resolve(&path, &Embedded::read(&path, "text/plain")).unwrap();
+        assert_eq!(as_text.source, DateSource::Modified, "Only files that can be movies have a container date.");
+    }
+
+    #[test]
+    fn test_invalid_patterns_are_rejected() {
+        assert!(FilenamePattern::parse("DSC_{year}{month}").unwrap_err().contains("'{day}'"));
+        assert!(FilenamePattern::parse("{year}{month}{day}_{week}").unwrap_err().contains("unknown field"));
+        let custom = FilenamePattern::parse("Scan*{day}.{month}.{year}").unwrap();
+        assert_eq!(custom.date("Scan of invoice 14.01.2023.pdf"), Some(date("2023-01-14 00:00:00")));
+    }
+}
@@ -0,0 +1,286 @@
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::file_processor::{source_files, ScanOptions};
+use crate::journal::{is_temporary_path, temporary_path, HARDLINK_DUPLICATE_ACTION, LINK_SUFFIX};
+use crate::error::PlexisortError;
+use crate::organizer::{journaled, organize_file, OrganizeOptions, Outcome};
+use crate::processing_mode::ProcessingMode;
+use crate::summary::{RunSummary, DUPLICATES_CATEGORY};
+
+/// Number of leading bytes hashed by the quick hash that narrows down
+/// candidates before whole files are hashed.
+const QUICK_HASH_BYTES: u64 = 64 * 1024;
+
+/// Folder of the destination directory that duplicates are moved into.
+const DUPLICATES_FOLDER: &str = "Duplicates";
+
+/// What to do with a source file whose contents already exist elsewhere in
+/// the sources or in the destination directory.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateAction {
+    /// Do not look for duplicates.
+    #[default]
+    Off,
+    /// Print the duplicates and organize them like any other file.
+    Report,
+    /// Leave duplicates in the source directory.
+    Skip,
+    /// Replace duplicates with a hard link to the copy that is kept.
+    Hardlink,
+    /// Move duplicates into the `Duplicates/` folder of the destination.
+    Move,
+}
+
+impl DuplicateAction {
+    pub const NAMES: &'static [&'static str] = &["off", "report", "skip", "hardlink", "move"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "off" => Some(DuplicateAction::Off),
+            "report" => Some(DuplicateAction::Report),
+            "skip" => Some(DuplicateAction::Skip),
+            "hardlink" => Some(DuplicateAction::Hardlink),
+            "move" => Some(DuplicateAction::Move),
+            _ => None,
+        }
+    }
+}
+
+/// A source file with the same contents as `original`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    pub original: PathBuf,
+}
+
+/// Finds source files whose contents also exist in the destination directory
+/// or in another source file.
+///
+/// Files are compared by size first, then by a hash of their first bytes and
+/// finally by a BLAKE3 hash of their whole contents. Of each group of
+/// identical files the one in the destination is kept, or else the first one
+/// in source order; every other source file in the group is a duplicate.
+/// Empty files are never duplicates, and files that cannot be read are
+/// left out of the comparison.
+pub fn find_duplicates(sources: &[PathBuf], destination: &Path, scan: &ScanOptions) -> Vec<Duplicate> {
+    let mut candidates: Vec<PathBuf> = Vec::new();
+    let mut seen = HashSet::new();
+
+    let destination_files = collect_files(destination);
+    let in_destination: HashSet<PathBuf> = destination_files.iter().cloned().collect();
+    for path in destination_files.into_iter().chain(sources.iter().flat_map(|source| sorted(source_files(source, scan)))) {
+        if seen.insert(path.clone()) {
+            candidates.push(path);
+        }
+    }
+
+    let mut by_size: HashMap<u64, Vec<&PathBuf>> = HashMap::new();
+    for path in &candidates {
+        match fs::metadata(path) {
+            // Empty files are placeholders, such as `.keep` files, not copies
+            Ok(metadata) if metadata.len() == 0 => {}
+            Ok(metadata) => by_size.entry(metadata.len()).or_default().push(path),
+            Err(e) => debug!("Skipping {} during duplicate detection: {}", path.display(), e),
+        }
+    }
+
+    let mut groups: Vec<Vec<&PathBuf>> = Vec::new();
+    for same_size in by_size.into_values().filter(|paths| paths.len() > 1) {
+        for same_start in group_by(same_size, quick_hash) {
+            groups.extend(group_by(same_start, full_hash));
+        }
+    }
+
+    let order: HashMap<&PathBuf, usize> = candidates.iter().enumerate().map(|(i, p)| (p, i)).collect();
+    let mut duplicates = Vec::new();
+    for mut group in groups {
+        group.sort_by_key(|path| order[path]);
+        let original = group[0];
+        for path in &group[1..] {
+            if !in_destination.contains(*path) {
+                duplicates.push(Duplicate { path: (*path).clone(), original: original.clone() });
+            }
+        }
+    }
+    duplicates.sort_by_key(|duplicate| order[&duplicate.path]);
+
+    duplicates
+}
+
+/// Carries out `action` for every duplicate and returns the files that must
+/// not be organized afterwards.
+pub fn handle_duplicates(
+    duplicates: &[Duplicate],
+    action: DuplicateAction,
+    destination: &Path,
+    mode: &mut ProcessingMode,
+    options: &OrganizeOptions,
+    summary: &mut RunSummary,
+) -> HashSet<PathBuf> {
+    let mut handled = HashSet::new();
+    let dry_run = matches!(mode, ProcessingMode::DryRun(_));
+
+    for duplicate in duplicates {
+        let path = duplicate.path.display();
+        let original = duplicate.original.display();
+        match action {
+            DuplicateAction::Off => {}
+            DuplicateAction::Report => println!("Duplicate: {} is identical to {}", path, original),
+            DuplicateAction::Skip => {
+                println!("Duplicate: {} is identical to {}, skipping", path, original);
+                summary.record(&duplicate.path, DUPLICATES_CATEGORY, Ok(Outcome::Skipped(format!("duplicate of {}", original))));
+                handled.insert(duplicate.path.clone());
+            }
+            DuplicateAction::Hardlink => {
+                let result = if dry_run {
+                    println!("Duplicate: {} would be replaced by a hard link to {}", path, original);
+                    Ok(Outcome::HardLinked(duplicate.original.clone()))
+                } else {
+                    replace_with_hard_link(&duplicate.path, &duplicate.original, options)
+                        .map(|()| {
+                            info!("Replaced {} with a hard link to {}", path, original);
+                            Outcome::HardLinked(duplicate.original.clone())
+                        })
+                        .map_err(|e| PlexisortError::from_io(&duplicate.path, e))
+                };
+                summary.record(&duplicate.path, DUPLICATES_CATEGORY, result);
+                handled.insert(duplicate.path.clone());
+            }
+            DuplicateAction::Move => {
+                println!("Duplicate: {} is identical to {}, moving it to {}", path, original, DUPLICATES_FOLDER);
+                let destination_path = destination.join(DUPLICATES_FOLDER).join(duplicate.path.file_name().unwrap());
+                let result = organize_file(&duplicate.path, &destination_path, mode, options)
+                    .map_err(|e| PlexisortError::from_io(&duplicate.path, e));
+                summary.record(&duplicate.path, DUPLICATES_CATEGORY, result);
+                handled.insert(duplicate.path.clone());
+            }
+        }
+    }
+
+    handled
+}
+
+/// Atomically replaces `duplicate` with a hard link to `original` and records
+/// the change in the session's journal.
+fn replace_with_hard_link(duplicate: &Path, original: &Path, options: &OrganizeOptions) -> io::Result<()> {
+    journaled(options, HARDLINK_DUPLICATE_ACTION, duplicate, original, || {
+        let link_path = temporary_path(duplicate, LINK_SUFFIX);
+        fs::hard_link(original, &link_path)?;
+        if let Err(e) = fs::rename(&link_path, duplicate) {
+            let _ = fs::remove_file(&link_path);
+            return Err(e);
+        }
+        Ok(())
+    })
+}
+
+fn collect_files(directory: &Path) -> Vec<PathBuf> {
+    sorted(WalkDir::new(directory)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file() && !is_temporary_path(e.path()))
+        .map(|e| e.into_path())
+        .collect())
+}
+
+fn sorted(mut files: Vec<PathBuf>) -> Vec<PathBuf> {
+    files.sort();
+    files
+}
+
+/// Groups `paths` by their `hash`, dropping files that cannot be read and
+/// groups of a single file.
+fn group_by(paths: Vec<&PathBuf>, hash: fn(&Path) -> io::Result<blake3::Hash>) -> Vec<Vec<&PathBuf>> {
+    let mut groups: HashMap<blake3::Hash, Vec<&PathBuf>> = HashMap::new();
+    for path in paths {
+        match hash(path) {
+            Ok(digest) => groups.entry(digest).or_default().push(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => debug!("{} disappeared during duplicate detection", path.display()),
+            Err(e) => warn!("Skipping {} during duplicate detection: {}", path.display(), e),
+        }
+    }
+    groups.into_values().filter(|group| group.len() > 1).collect()
+}
+
+fn quick_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?.take(QUICK_HASH_BYTES), &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+pub fn full_hash(path: &Path) -> io::Result<blake3::Hash> {
+    let mut hasher = blake3::Hasher::new();
+    io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_file(path: &Path, contents: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(path).unwrap().write_all(contents.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_find_duplicates_across_sources_and_destination() {
+        let temp_dir = tempdir().unwrap();
+        let backup_a = temp_dir.path().join("backup_a");
+        let backup_b = temp_dir.path().join("backup_b");
+        let destination = temp_dir.path().join("library");
+
+        write_file(&destination.join("Images").join("beach.jpg"), "beach");
+        write_file(&backup_a.join("beach.jpg"), "beach");
+        write_file(&backup_a.join("forest.jpg"), "forest");
+        write_file(&backup_b.join("copy of forest.jpg"), "forest");
+        write_file(&backup_b.join("mountain.jpg"), "mounta"); // same size, different contents
+        write_file(&destination.join("Images").join(".sunset.jpg.plexisort-replaced"), "sunset"); // kept for undo
+        write_file(&backup_b.join("sunset.jpg"), "sunset");
+
+        let duplicates = find_duplicates(&[backup_a.clone(), backup_b.clone()], &destination, &ScanOptions::default());
+
+        assert_eq!(duplicates, vec![
+            Duplicate { path: backup_a.join("beach.jpg"), original: destination.join("Images").join("beach.jpg") },
+            Duplicate { path: backup_b.join("copy of forest.jpg"), original: backup_a.join("forest.jpg") },
+        ]);
+    }
+
+    #[test]
+    fn test_skipped_duplicates_are_left_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source");
+        write_file(&source.join("a.txt"), "same");
+        write_file(&source.join("b.txt"), "same");
+
+        let duplicates = find_duplicates(std::slice::from_ref(&source), &temp_dir.path().join("destination"), &ScanOptions::default());
+        let mut mode = ProcessingMode::Live;
+        let mut summary = RunSummary::default();
+        let handled = handle_duplicates(&duplicates, DuplicateAction::Skip, temp_dir.path(), &mut mode, &OrganizeOptions::default(), &mut summary);
+
+        assert_eq!(handled, HashSet::from([source.join("b.txt")]));
+        assert!(source.join("b.txt").exists());
+    }
+
+    #[test]
+    fn test_empty_files_are_not_duplicates() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source");
+        let destination = temp_dir.path().join("library");
+        write_file(&destination.join(".keep"), "");
+        write_file(&source.join(".keep"), "");
+        write_file(&source.join("Photos").join(".keep"), "");
+        write_file(&source.join("lock"), "");
+
+        assert_eq!(find_duplicates(std::slice::from_ref(&source), &destination, &ScanOptions::default()), Vec::new());
+    }
+}
@@ -1,69 +1,62 @@
-use crate::organizer::organize_file;
-use crate::processing_mode::ProcessingMode;
+use crate::dates::DateResolver;
+use crate::metadata::{file_metadata, Embedded, Metadata};
 use crate::traits::processor::Processor;
-use std::fs;
-use std::path::PathBuf;
-use log::{debug, error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 
 
-pub struct DocumentProcessor;
+#[derive(Default)]
+pub struct DocumentProcessor {
+    pub dates: Arc<DateResolver>,
+    /// The extension of the file's detected type, e.g. `pdf` for a PDF saved
+    /// as `.txt`. The file's own extension is used if it is `None`.
+    pub extension: Option<String>,
+}
 
-impl Processor for DocumentProcessor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode) {
-        let file_extension = path.extension()
-            .unwrap_or_default()
-            .to_str()
-            .unwrap_or("")
-            .to_lowercase();
-
-        let destination_dir = self.determine_destination_dir(&file_extension, destination);
-        let destination_path = destination_dir.join(path.file_name().unwrap());
-
-        match mode {
-            ProcessingMode::DryRun(virtual_directory) => {
-                // In DryRun mode, just simulate the action
-                debug!("Would move {} to {}", path.display(), destination_path.display());
-                let path_parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-                virtual_directory.add_path(&path_parts);
-
-
-            },
-            ProcessingMode::Live => {
-                // In Live mode, actually create the directory and move the file
-                if let Err(e) = fs::create_dir_all(&destination_dir) {
-                    error!("Error creating destination directory: {}", e);
-                    return;
-                }
-                if let Err(e) = organize_file(path, &destination_path, mode) {
-                    error!("Failed to organize file: {}", e);
-                } else {
-                    debug!("Successfully moved file from {} to {}", path.display(), destination_path.display());
-                }
-            }
+impl DocumentProcessor {
+    fn extension(&self, path: &Path) -> String {
+        match &self.extension {
+            Some(extension) => extension.clone(),
+            None => path.extension().unwrap_or_default().to_str().unwrap_or("").to_lowercase(),
         }
     }
+}
+
+impl Processor for DocumentProcessor {
+    fn get_destination_subfolder(&self, path: &Path, _metadata: &Metadata) -> PathBuf {
+        PathBuf::from("Documents").join(document_subfolder(&self.extension(path)))
+    }
+
+    fn category(&self) -> &str {
+        "Documents"
+    }
 
-    fn get_destination_subfolder(&self, _path: &PathBuf) -> PathBuf {
-        PathBuf::new()
+    fn dates(&self) -> &DateResolver {
+        &self.dates
+    }
+
+    fn metadata(&self, path: &Path) -> Metadata {
+        let mut metadata = file_metadata(path, &self.dates, &Embedded::default());
+        let file_extension = self.extension(path);
+        metadata.insert("doc_type".to_string(), document_subfolder(&file_extension).to_string());
+        metadata.insert("ext".to_string(), file_extension);
+        metadata
     }
 }
 
-impl DocumentProcessor {
-    /// Determine the destination directory based on the file extension.
-    fn determine_destination_dir(&self, extension: &str, base_dest: &PathBuf) -> PathBuf {
-        let subfolder = match extension {
-            "doc" | "docx" => "Word_Documents",
-            "xls" | "xlsx" => "Excel_Spreadsheets",
-            "ppt" | "pptx" => "PowerPoint_Presentations",
-            "csv"  => "CSV_Files",
-            "json" | "yaml" | "yml" => "Config_Files",
-            "pdf" => "PDFs",
-            "html" => "Web_Pages",
-            "txt" => "Text_Files",
-            _ => "Uncategorized_Documents",
-        };
-        base_dest.join("Documents").join(subfolder)
+/// Determine the documents subfolder based on the file extension.
+fn document_subfolder(extension: &str) -> &'static str {
+    match extension {
+        "doc" | "docx" => "Word_Documents",
+        "xls" | "xlsx" => "Excel_Spreadsheets",
+        "ppt" | "pptx" => "PowerPoint_Presentations",
+        "csv"  => "CSV_Files",
+        "json" | "yaml" | "yml" => "Config_Files",
+        "pdf" => "PDFs",
+        "html" => "Web_Pages",
+        "txt" => "Text_Files",
+        _ => "Uncategorized_Documents",
     }
 }
 
@@ -71,6 +64,9 @@ impl DocumentProcessor {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
     use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
@@ -85,10 +81,10 @@ mod tests {
         let mut file = File::create(&document_file_path).unwrap();
         writeln!(file, "Test content").unwrap();
 
-        let processor = DocumentProcessor {};
+        let processor = DocumentProcessor::default();
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&document_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&document_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
         // Update the expected destination to include "Documents"
         let expected_destination = destination_dir.join("Documents").join("Text_Files").join("test_document.txt");
@@ -98,7 +94,7 @@ mod tests {
 
     #[test]
     fn test_document_processor_logic() {
-        let processor = DocumentProcessor {};
+        let processor = DocumentProcessor::default();
         let temp_dir = tempdir().unwrap();
         let source_dir = temp_dir.path().join("source");
         let destination_dir = temp_dir.path().join("destination");
@@ -109,7 +105,7 @@ mod tests {
 
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&document_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&document_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
         // Update the expected destination to include "Documents"
         let expected_destination = destination_dir.join("Documents").join("Text_Files").join("test_document.txt");
@@ -0,0 +1,100 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Why a file could not be organized, or why a run could not start.
+#[derive(Debug)]
+pub enum PlexisortError {
+    /// Reading or writing a file failed.
+    Io { path: PathBuf, source: io::Error },
+    /// The file, its directory or the destination may not be changed.
+    Permission { path: PathBuf, source: io::Error },
+    /// Something other than a file is in the way at the destination.
+    Collision { path: PathBuf, source: io::Error },
+    /// The file could not be inspected to decide where it goes.
+    Metadata { path: PathBuf, source: io::Error },
+    /// The file changed after it was planned, so the plan no longer applies.
+    Stale { path: PathBuf },
+    /// The config file or command line is invalid.
+    Config(String),
+}
+
+impl PlexisortError {
+    /// Classifies an I/O error that occurred while organizing `path`.
+    pub fn from_io(path: &Path, source: io::Error) -> Self {
+        let path = path.to_path_buf();
+        match source.kind() {
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => PlexisortError::Permission { path, source },
+            io::ErrorKind::AlreadyExists
+            | io::ErrorKind::IsADirectory
+            | io::ErrorKind::NotADirectory
+            | io::ErrorKind::DirectoryNotEmpty => PlexisortError::Collision { path, source },
+            _ => PlexisortError::Io { path, source },
+        }
+    }
+
+    /// Short name of the kind of error, e.g. for reports.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PlexisortError::Io { .. } => "io",
+            PlexisortError::Permission { .. } => "permission",
+            PlexisortError::Collision { .. } => "collision",
+            PlexisortError::Metadata { .. } => "metadata",
+            PlexisortError::Stale { .. } => "stale",
+            PlexisortError::Config(_) => "config",
+        }
+    }
+
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            PlexisortError::Io { path, .. }
+            | PlexisortError::Permission { path, .. }
+            | PlexisortError::Collision { path, .. }
+            | PlexisortError::Metadata { path, .. }
+            | PlexisortError::Stale { path } => Some(path),
+            PlexisortError::Config(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for PlexisortError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PlexisortError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            PlexisortError::Permission { path, source } => write!(f, "{}: not permitted ({})", path.display(), source),
+            PlexisortError::Collision { path, source } => write!(f, "{}: the destination is in the way ({})", path.display(), source),
+            PlexisortError::Metadata { path, source } => write!(f, "{}: cannot be inspected ({})", path.display(), source),
+            PlexisortError::Stale { path } => write!(f, "{}: changed since it was planned", path.display()),
+            PlexisortError::Config(message) => write!(f, "Invalid configuration: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for PlexisortError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PlexisortError::Io { source, .. }
+            | PlexisortError::Permission { source, .. }
+            | PlexisortError::Collision { source, .. }
+            | PlexisortError::Metadata { source, .. } => Some(source),
+            PlexisortError::Stale { .. } | PlexisortError::Config(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_are_classified() {
+        let path = Path::new("/photos/a.jpg");
+        let error = |kind: io::ErrorKind| PlexisortError::from_io(path, io::Error::from(kind));
+
+        assert_eq!(error(io::ErrorKind::PermissionDenied).kind(), "permission");
+        assert_eq!(error(io::ErrorKind::IsADirectory).kind(), "collision");
+        assert_eq!(error(io::ErrorKind::StorageFull).kind(), "io");
+        assert_eq!(error(io::ErrorKind::NotFound).path(), Some(path));
+    }
+}
@@ -0,0 +1,132 @@
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use crate::error::PlexisortError;
+use crate::organizer::{organize_file, OrganizeOptions, Outcome};
+use crate::plan::PlannedAction;
+use crate::processing_mode::ProcessingMode;
+use crate::sidecar::sidecar_destination;
+use crate::summary::RunSummary;
+
+/// Carries out a planned action. In DryRun mode it is only recorded in the
+/// virtual directory, so a preview shows exactly what a live run would do.
+pub fn execute(action: &PlannedAction, mode: &mut ProcessingMode, options: &OrganizeOptions) -> Result<Outcome, PlexisortError> {
+    debug!("{} -> {} ({})", action.source.display(), action.destination.display(), action.reason);
+    for note in &action.notes {
+        warn!("{}: {}", action.source.display(), note);
+    }
+    organize_file(&action.source, &action.destination, mode, options)
+        .map_err(|e| PlexisortError::from_io(&action.source, e))
+}
+
+/// Where the files of a plan ended up so far, by their source path.
+pub type Placements = HashMap<PathBuf, PathBuf>;
+
+/// Carries out a planned action and records its outcome. A file that follows
+/// another one goes next to wherever that file ended up, under the name it
+/// was given there, and stays where it is if that file was not organized.
+pub fn execute_grouped(action: &PlannedAction, mode: &mut ProcessingMode, options: &OrganizeOptions, placements: &mut Placements, summary: &mut RunSummary) {
+    let following = match &action.follows {
+        Some(primary) => match placements.get(primary) {
+            Some(target) => Some(PlannedAction { destination: sidecar_destination(&action.source, primary, target), ..action.clone() }),
+            None => {
+                summary.record_planned(action, Ok(Outcome::Skipped(format!("{} was not organized", primary.display()))));
+                return;
+            }
+        },
+        None => None,
+    };
+    let action = following.as_ref().unwrap_or(action);
+    let result = execute(action, mode, options);
+    if let Ok(Outcome::Transferred(target) | Outcome::DroppedDuplicate(target)) = &result {
+        placements.insert(action.source.clone(), target.clone());
+    }
+    summary.record_planned(action, result);
+}
+
+/// Carries out a plan in order and records the outcome of each action. A
+/// failed action does not stop the ones after it, except for the files that
+/// follow it.
+pub fn execute_plan(plan: &[PlannedAction], mode: &mut ProcessingMode, options: &OrganizeOptions, summary: &mut RunSummary) {
+    let mut placements = Placements::new();
+    for action in plan {
+        execute_grouped(action, mode, options, &mut placements, summary);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conflict::ConflictPolicy;
+    use crate::generic_processor::GenericProcessor;
+    use crate::journal::Journal;
+    use crate::organizer::undo_actions;
+    use crate::sidecar::{group_sidecars, SidecarOptions};
+    use crate::traits::processor::Processor;
+    use crate::virtual_directory::VirtualDirectory;
+    use std::fs;
+    use std::sync::Arc;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dry_run_and_live_follow_the_same_plan() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        let source = source_dir.join("setup.bin");
+        fs::write(&source, b"\0\x01").unwrap();
+        let plan = vec![GenericProcessor::default().plan(&source, &destination_dir)];
+
+        let mut dry_run = ProcessingMode::DryRun(VirtualDirectory::default());
+        let mut summary = RunSummary::default();
+        execute_plan(&plan, &mut dry_run, &OrganizeOptions::default(), &mut summary);
+        let ProcessingMode::DryRun(virtual_dir) = &dry_run else { unreachable!() };
+        let parts: Vec<String> = plan[0].destination.iter().map(|part| part.to_string_lossy().into_owned()).collect();
+        assert!(virtual_dir.contains_file(&parts), "The preview must contain the planned destination.");
+        assert!(source.exists(), "A dry run must not move files.");
+
+        execute_plan(&plan, &mut ProcessingMode::Live, &OrganizeOptions::default(), &mut summary);
+        assert_eq!(summary.files[0].result.as_ref().unwrap(), summary.files[1].result.as_ref().unwrap());
+        assert!(plan[0].destination.exists(), "The live run must move the file to the planned destination.");
+    }
+
+    #[test]
+    fn test_sidecars_move_and_undo_with_their_photo() {
+        let temp_dir = tempdir().unwrap();
+        let card = temp_dir.path().join("card");
+        let library = temp_dir.path().join("library");
+        fs::create_dir_all(&card).unwrap();
+        for name in ["IMG_0001.JPG", "IMG_0001.xmp", "IMG_0002.JPG", "IMG_0002.xmp"] {
+            fs::write(card.join(name), name).unwrap();
+        }
+        let plan = ["IMG_0001.JPG", "IMG_0001.xmp", "IMG_0002.JPG", "IMG_0002.xmp"]
+            .map(|name| Ok(GenericProcessor::default().plan(&card.join(name), &library)))
+            .into_iter()
+            .collect();
+        let plan: Vec<PlannedAction> = group_sidecars(plan, &SidecarOptions::default()).into_iter().map(Result::unwrap).collect();
+        let photos = plan[0].destination.parent().unwrap().to_path_buf();
+        fs::create_dir_all(&photos).unwrap();
+        fs::write(photos.join("IMG_0001.JPG"), "an older photo").unwrap();
+        fs::write(photos.join("IMG_0002.JPG"), "another older photo").unwrap();
+
+        // The first photo is renamed, the second one is skipped
+        let log_path = temp_dir.path().join("session.jsonl");
+        let options = OrganizeOptions { journal: Some(Arc::new(Journal::open(&log_path).unwrap())), ..OrganizeOptions::default() };
+        let mut summary = RunSummary::default();
+        execute_plan(&plan[..2], &mut ProcessingMode::Live, &options, &mut summary);
+        let options = OrganizeOptions { conflict_policy: ConflictPolicy::Skip, ..options };
+        execute_plan(&plan[2..], &mut ProcessingMode::Live, &options, &mut summary);
+
+        assert_eq!(fs::read_to_string(photos.join("IMG_0001 (1).xmp")).unwrap(), "IMG_0001.xmp", "The sidecar must keep the name of its photo.");
+        assert!(card.join("IMG_0002.xmp").exists(), "A sidecar stays when its photo does.");
+        assert!(matches!(summary.files[3].result, Ok(Outcome::Skipped(_))));
+
+        undo_actions(&log_path, &library).unwrap();
+        for name in ["IMG_0001.JPG", "IMG_0001.xmp"] {
+            assert_eq!(fs::read_to_string(card.join(name)).unwrap(), name);
+        }
+        assert!(!photos.join("IMG_0001 (1).xmp").exists());
+    }
+}
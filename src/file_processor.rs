@@ -1,29 +1,204 @@
+use crate::error::PlexisortError;
+use crate::executor::{execute_grouped, Placements};
+use crate::journal::is_temporary_path;
+use crate::organizer::OrganizeOptions;
+use crate::plan::PlannedAction;
+use crate::organizer::Outcome;
+use crate::sidecar::{follow_placed, group_sidecars, SidecarOptions};
+use crate::sniff::detect_type;
+use crate::stability::{StabilityGate, StabilityOptions};
+use crate::summary::{RunSummary, UNSETTLED_CATEGORY};
 use crate::traits::ProcessorFactory;
 
 use crate::processing_mode::ProcessingMode;
 
+use crate::filter::{PathFilter, IGNORE_FILE_NAME};
+
+use ignore::gitignore::GitignoreBuilder;
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs;
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::sync::Arc;
+use std::time::SystemTime;
 
 
 
-pub fn process_directory(
-    directory: &Path,
-    base_dest: &PathBuf,
-    mode: &mut ProcessingMode,
-    factory: &dyn ProcessorFactory
-) {
-    let paths: Vec<PathBuf> = WalkDir::new(directory)
-        .into_iter()
+/// Decides which files of a source directory are organized.
+#[derive(Debug, Default)]
+pub struct ScanOptions {
+    pub skip: HashSet<PathBuf>,  // Files that were already handled, e.g. duplicates
+    pub prune: Vec<PathBuf>,     // Directories that are not descended into, e.g. a nested destination
+    pub filter: Arc<PathFilter>, // The configured include and exclude globs
+    pub stability: StabilityOptions, // When files count as still being written
+    pub sidecars: SidecarOptions, // Which files travel with the photo or video they belong to
+}
+
+/// Walks a source directory and returns the files to organize. Directories
+/// that are excluded, pruned or ignored by a `.plexisortignore` file are not
+/// descended into.
+pub fn source_files(directory: &Path, scan: &ScanOptions) -> Vec<PathBuf> {
+    let root = directory.to_path_buf();
+    let prune = scan.prune.clone();
+    let filter = Arc::clone(&scan.filter);
+
+    WalkBuilder::new(directory)
+        .standard_filters(false)
+        .add_custom_ignore_filename(IGNORE_FILE_NAME)
+        .filter_entry(move |entry| {
+            if entry.depth() == 0 {
+                return true;
+            }
+            let relative = entry.path().strip_prefix(&root).unwrap_or(entry.path());
+            if entry.file_type().is_some_and(|t| t.is_dir()) {
+                !prune.iter().any(|pruned| entry.path() == pruned) && filter.walks_directory(relative)
+            } else {
+                entry.file_name() != IGNORE_FILE_NAME && !is_temporary_path(entry.path()) && filter.accepts_file(relative)
+            }
+        })
+        .build()
         .filter_map(Result::ok)
-        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.file_type().is_some_and(|t| t.is_file()))
         .map(|e| e.into_path())
+        .filter(|path| !scan.skip.contains(path))
+        .collect()
+}
+
+/// Whether a single file of a source directory is organized, by the same
+/// rules `source_files` applies while walking. Used for files that are
+/// reported one at a time, e.g. by `plexisort watch`.
+pub fn is_source_file(directory: &Path, path: &Path, scan: &ScanOptions) -> bool {
+    let Ok(relative) = path.strip_prefix(directory) else {
+        return false;
+    };
+    if scan.skip.contains(path) || path.file_name().is_none_or(|name| name == IGNORE_FILE_NAME) || is_temporary_path(path) || !path.is_file() {
+        return false;
+    }
+
+    let mut ancestor = directory.to_path_buf();
+    let components: Vec<_> = relative.components().collect();
+    for (depth, component) in components.iter().enumerate() {
+        if ignored_by(&ancestor, path) {
+            return false;
+        }
+        ancestor.push(component);
+        if depth + 1 == components.len() {
+            break;
+        }
+        let relative_dir = ancestor.strip_prefix(directory).unwrap_or(&ancestor);
+        if scan.prune.contains(&ancestor) || !scan.filter.walks_directory(relative_dir) {
+            return false;
+        }
+    }
+    scan.filter.accepts_file(relative)
+}
+
+/// Whether the `.plexisortignore` file of `directory`, if any, ignores `path`.
+fn ignored_by(directory: &Path, path: &Path) -> bool {
+    let ignore_file = directory.join(IGNORE_FILE_NAME);
+    if !ignore_file.is_file() {
+        return false;
+    }
+    let mut builder = GitignoreBuilder::new(directory);
+    if let Some(e) = builder.add(&ignore_file) {
+        log::warn!("Invalid {}: {}", ignore_file.display(), e);
+    }
+    builder.build().is_ok_and(|ignore| ignore.matched_path_or_any_parents(path, false).is_ignore())
+}
+
+/// Leaves out the files that are still being written, recording them as
+/// skipped with the reason.
+pub fn settled_files(paths: Vec<PathBuf>, stability: &StabilityOptions, summary: &mut RunSummary) -> Vec<PathBuf> {
+    let gate = StabilityGate::new(stability);
+    let now = SystemTime::now();
+    paths
+        .into_iter()
+        .filter(|path| match gate.check(path, now) {
+            Some(unsettled) => {
+                summary.record(path, UNSETTLED_CATEGORY, Ok(Outcome::Skipped(unsettled.to_string())));
+                false
+            }
+            None => true,
+        })
+        .collect()
+}
+
+/// Plans where each file goes. Files are inspected on the threads of the
+/// current rayon pool; the plan is in the order of `paths`, except that
+/// sidecars are planned right after the file they travel with. Files that can
+/// no longer be inspected, e.g. because they were deleted since the scan,
+/// are planned as errors.
+pub fn plan_actions(paths: &[PathBuf], base_dest: &Path, factory: &dyn ProcessorFactory, sidecars: &SidecarOptions) -> Vec<Result<PlannedAction, PlexisortError>> {
+    let planned = paths
+        .par_iter()
+        .map(|path| {
+            fs::metadata(path).map_err(|source| PlexisortError::Metadata { path: path.clone(), source })?;
+            let file_type = detect_type(path, factory.type_precedence());
+            let mut action = factory.create_processor(path, &file_type).plan(path, base_dest); // Use the factory
+            action.notes.extend(file_type.mismatch.map(|mismatch| format!("Type mismatch: {}", mismatch)));
+            Ok(action)
+        })
         .collect();
+    group_sidecars(planned, sidecars)
+}
+
+/// Plans where the settled files of a source directory go, in path order.
+pub fn plan_directory(
+    directory: &Path,
+    base_dest: &Path,
+    factory: &dyn ProcessorFactory,
+    scan: &ScanOptions,
+    summary: &mut RunSummary,
+) -> Vec<Result<PlannedAction, PlexisortError>> {
+    let mut paths = source_files(directory, scan);
+    paths.sort(); // The walk order depends on the file system
+    plan_actions(&settled_files(paths, &scan.stability, summary), base_dest, factory, &scan.sidecars)
+}
+
+/// Plans where the given files go and then moves them one by one in the
+/// order of the plan, recording the outcome of each. `placements` holds
+/// where the files organized earlier in the session went, so that sidecars
+/// arriving after their file follow it; the new placements are added to it.
+#[allow(clippy::too_many_arguments)]
+pub fn organize_files(
+    paths: &[PathBuf],
+    base_dest: &Path,
+    mode: &mut ProcessingMode,
+    factory: &dyn ProcessorFactory,
+    options: &OrganizeOptions,
+    scan: &ScanOptions,
+    placements: &mut Placements,
+    summary: &mut RunSummary,
+) {
+    let planned = plan_actions(paths, base_dest, factory, &scan.sidecars);
+    for planned in follow_placed(planned, placements, &scan.sidecars) {
+        match planned {
+            Ok(action) => execute_grouped(&action, mode, options, placements, summary),
+            Err(e) => summary.record_failure(e),
+        }
+    }
+}
 
-    paths.iter().for_each(|path| {
-        let processor = factory.create_processor(path); // Use the factory
-        processor.process(path, base_dest, mode);
-    });
+/// Organizes a source directory in three steps: the settled files are
+/// listed, their destinations are decided in parallel, and then they are
+/// moved one by one in path order, so that the outcome does not depend on the
+/// number of threads. Where the files went is added to `placements`.
+#[allow(clippy::too_many_arguments)]
+pub fn process_directory(
+    directory: &Path,
+    base_dest: &Path,
+    mode: &mut ProcessingMode,
+    factory: &dyn ProcessorFactory,
+    options: &OrganizeOptions,
+    scan: &ScanOptions,
+    placements: &mut Placements,
+    summary: &mut RunSummary,
+) {
+    let mut paths = source_files(directory, scan);
+    paths.sort(); // The walk order depends on the file system
+    let paths = settled_files(paths, &scan.stability, summary);
+    organize_files(&paths, base_dest, mode, factory, options, scan, placements, summary);
 
     // Debugging or DryRun mode output
     if let ProcessingMode::DryRun(virtual_dir) = mode {
@@ -36,6 +211,7 @@ pub fn process_directory(
 #[cfg(test)]
 mod tests {
 
+    use super::*;
     use crate::traits::{ProcessorFactory, TestProcessorFactory};
     use crate::virtual_directory::VirtualDirectory;
 
@@ -72,6 +248,34 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_source_files_honours_filters_and_ignore_files() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        for file in ["photo.jpg", "video.mp4.crdownload", "node_modules/lib.js", "Scans/a.pdf", "Scans/raw/b.tif", "Scans/.plexisortignore"] {
+            let path = source_dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+        fs::write(source_dir.join("Scans").join(".plexisortignore"), "raw/\n").unwrap();
+
+        let scan = ScanOptions {
+            filter: Arc::new(PathFilter::new(&[], &["node_modules".to_string(), "*.crdownload".to_string()]).unwrap()),
+            ..ScanOptions::default()
+        };
+        let mut files = source_files(&source_dir, &scan);
+        files.sort();
+
+        assert_eq!(files, vec![source_dir.join("Scans").join("a.pdf"), source_dir.join("photo.jpg")]);
+
+        let walked: Vec<PathBuf> = ["photo.jpg", "video.mp4.crdownload", "node_modules/lib.js", "Scans/a.pdf", "Scans/raw/b.tif", "Scans/.plexisortignore"]
+            .iter()
+            .map(|file| source_dir.join(file))
+            .filter(|path| is_source_file(&source_dir, path, &scan))
+            .collect();
+        assert_eq!(walked, vec![source_dir.join("photo.jpg"), source_dir.join("Scans").join("a.pdf")], "Single files must be filtered like walked ones.");
+    }
+
     #[test]
     fn test_processor_selection() {
         let factory = TestProcessorFactory {
@@ -87,15 +291,46 @@ mod tests {
         // Add other file types as needed
 
         // Document Processor test
-        factory.create_processor(&document_file);
+        factory.create_processor(&document_file, &detect_type(&document_file, factory.type_precedence()));
         assert_eq!(*factory.last_processor_type.lock().unwrap(), Some("DocumentProcessor".to_string()), "DocumentProcessor was not selected for .docx files.");
 
         // Image Processor test
-        factory.create_processor(&image_file);
+        factory.create_processor(&image_file, &detect_type(&image_file, factory.type_precedence()));
         assert_eq!(*factory.last_processor_type.lock().unwrap(), Some("ImageProcessor".to_string()), "ImageProcessor was not selected for .png files.");
 
         // Add tests for other processor types as needed
     }
+
+    #[test]
+    fn test_results_do_not_depend_on_thread_count() {
+        let factory = TestProcessorFactory {
+            last_processor_type: Mutex::new(None),
+        };
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let paths: Vec<PathBuf> = (0..40).map(|i| source_dir.join(format!("dir{}", i / 10)).join(format!("file{}.txt", i % 10))).collect();
+        for (index, path) in paths.iter().enumerate() {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, format!("file {}", index)).unwrap();
+        }
+
+        let destination = temp_dir.path().join("destination");
+        let plan = |threads: usize| {
+            let pool = rayon::ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| plan_actions(&paths, &destination, &factory, &SidecarOptions::default())).into_iter().map(Result::unwrap).collect::<Vec<_>>()
+        };
+        let sequential = plan(1);
+        assert_eq!(sequential, plan(8));
+        assert!(sequential.iter().zip(&paths).all(|(action, path)| &action.source == path), "The plan must keep the order of the files.");
+
+        // Files with the same name are renamed in path order
+        let mut mode = ProcessingMode::Live;
+        let mut summary = RunSummary::default();
+        process_directory(&source_dir, &destination, &mut mode, &factory, &OrganizeOptions::default(), &ScanOptions::default(), &mut Placements::new(), &mut summary);
+        assert_eq!(summary.files.len(), paths.len());
+        let first = fs::read_to_string(destination.join("Documents").join("Text_Files").join("file0.txt")).unwrap();
+        assert_eq!(first, "file 0");
+    }
 }
 
 
@@ -0,0 +1,101 @@
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use std::path::Path;
+
+/// Name of the gitignore-syntax files that exclude paths from organizing.
+/// They are honoured in every directory of a source tree.
+pub const IGNORE_FILE_NAME: &str = ".plexisortignore";
+
+/// The `include` and `exclude` globs of the config file and command line.
+///
+/// A glob without a `/` is matched against the name of a file or directory,
+/// one with a `/` against its path relative to the source directory.
+#[derive(Debug, Default)]
+pub struct PathFilter {
+    include: Patterns,
+    exclude: Patterns,
+}
+
+#[derive(Debug, Default)]
+struct Patterns {
+    names: GlobSet,
+    paths: GlobSet,
+    len: usize,
+}
+
+impl Patterns {
+    fn new(globs: &[String]) -> Result<Self, String> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in globs {
+            let glob = GlobBuilder::new(pattern.trim_start_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("invalid glob '{}': {}", pattern, e))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        Ok(Patterns {
+            names: names.build().map_err(|e| e.to_string())?,
+            paths: paths.build().map_err(|e| e.to_string())?,
+            len: globs.len(),
+        })
+    }
+
+    fn matches(&self, relative_path: &Path) -> bool {
+        relative_path.file_name().is_some_and(|name| self.names.is_match(name)) || self.paths.is_match(relative_path)
+    }
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, String> {
+        Ok(PathFilter {
+            include: Patterns::new(include).map_err(|e| format!("Invalid include pattern: {}", e))?,
+            exclude: Patterns::new(exclude).map_err(|e| format!("Invalid exclude pattern: {}", e))?,
+        })
+    }
+
+    /// Whether a directory is walked into. Only `exclude` applies to
+    /// directories, so that `include` patterns can match files at any depth.
+    pub fn walks_directory(&self, relative_path: &Path) -> bool {
+        !self.exclude.matches(relative_path)
+    }
+
+    /// Whether a file is organized.
+    pub fn accepts_file(&self, relative_path: &Path) -> bool {
+        (self.include.len == 0 || self.include.matches(relative_path)) && !self.exclude.matches(relative_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn globs(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_include_and_exclude() {
+        let filter = PathFilter::new(
+            &globs(&["*.jpg", "*.mp4"]),
+            &globs(&[".*", "node_modules", "*.crdownload", "Camera/thumbs/*"]),
+        ).unwrap();
+
+        assert!(filter.accepts_file(Path::new("Camera/IMG_0001.jpg")));
+        assert!(!filter.accepts_file(Path::new("notes.txt")), "Files must match an include pattern.");
+        assert!(!filter.accepts_file(Path::new("Camera/thumbs/IMG_0001.jpg")));
+        assert!(!filter.accepts_file(Path::new(".hidden.jpg")));
+        assert!(!filter.walks_directory(Path::new("project/node_modules")));
+        assert!(!filter.walks_directory(Path::new(".git")));
+        assert!(filter.walks_directory(Path::new("Camera")));
+    }
+
+    #[test]
+    fn test_invalid_glob_is_reported() {
+        let error = PathFilter::new(&[], &globs(&["[unclosed"])).unwrap_err();
+        assert!(error.starts_with("Invalid exclude pattern"), "{}", error);
+    }
+}
@@ -1,47 +1,27 @@
-use crate::organizer::organize_file;
-use crate::processing_mode::ProcessingMode;
+use crate::dates::DateResolver;
+use crate::metadata::Metadata;
 use crate::traits::processor::Processor;
-use std::fs;
-use std::path::PathBuf;
-use log::{debug, error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 
-pub struct GenericProcessor;
+#[derive(Default)]
+pub struct GenericProcessor {
+    pub dates: Arc<DateResolver>,
+}
 
 impl Processor for GenericProcessor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode) {
-        // Define the destination directory based on the subfolder and ensure it exists
-        let destination_dir = destination.join(self.get_destination_subfolder(path));
-
-        // Specify the destination path for the file, correctly appending the filename
-        let destination_path = destination_dir.join(path.file_name().unwrap());
+    fn get_destination_subfolder(&self, _path: &Path, _metadata: &Metadata) -> PathBuf {
+        // Adjust the returned subfolder name as needed
+        PathBuf::from("Other_Files")
+    }
 
-        match mode {
-            ProcessingMode::DryRun(virtual_directory) => {
-                // In DryRun mode, simulate the action without making changes
-                debug!("Would move {} to {}", path.display(), destination_path.display());
-                let path_parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-                virtual_directory.add_path(&path_parts);
-            },
-            ProcessingMode::Live => {
-                // In Live mode, actually create the directory and move the file
-                // Ensure the directory structure exists
-                if let Err(e) = fs::create_dir_all(&destination_dir) {
-                    error!("Error creating destination directory: {}", e);
-                    return;
-                }
-                if let Err(e) = organize_file(path, &destination_path, mode) {
-                    error!("Failed to organize file: {}", e);
-                } else {
-                    debug!("Successfully moved file from {} to {}", path.display(), destination_path.display());
-                }
-            }
-        }
+    fn category(&self) -> &str {
+        "Other_Files"
     }
 
-    fn get_destination_subfolder(&self, _path: &PathBuf) -> PathBuf {
-        // Adjust the returned subfolder name as needed
-        PathBuf::from("Other_Files")
+    fn dates(&self) -> &DateResolver {
+        &self.dates
     }
 }
 
@@ -49,6 +29,9 @@ impl Processor for GenericProcessor {
 #[cfg(test)]
 mod generic_processor_tests {
     use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
     use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
@@ -64,10 +47,10 @@ mod generic_processor_tests {
         let mut file = File::create(&generic_file_path).unwrap();
         writeln!(file, "Generic file content").unwrap();
 
-        let processor = GenericProcessor {};
+        let processor = GenericProcessor::default();
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&generic_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&generic_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
         // The expected destination is within the "Other_Files" directory
         let expected_destination = destination_dir.join("Other_Files").join("generic_file.txt");
@@ -0,0 +1,208 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+
+use crate::journal::{completed_entries, read_journal, EntryState};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Environment variable that overrides where the history is stored.
+const STATE_DIR_ENV: &str = "PLEXISORT_STATE_DIR";
+
+/// Describes one live run of the organizer. Stored as `<id>.json` next to the
+/// run's undo log `<id>.jsonl`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionInfo {
+    pub id: String,
+    pub started_at: DateTime<Local>,
+    pub source_directories: Vec<String>,
+    pub destination: String,
+    pub command_line: Vec<String>,
+    #[serde(default)]
+    pub undone_at: Option<DateTime<Local>>,
+}
+
+/// The directory holding the sessions of past runs, by default
+/// `$XDG_STATE_HOME/plexisort/sessions`.
+pub struct HistoryStore {
+    root: PathBuf,
+}
+
+impl HistoryStore {
+    pub fn new(root: PathBuf) -> Self {
+        HistoryStore { root }
+    }
+
+    /// Opens the history in `$PLEXISORT_STATE_DIR`, or in the platform's state
+    /// directory (`~/.local/state/plexisort` on Linux).
+    pub fn open_default() -> io::Result<Self> {
+        let base = match std::env::var_os(STATE_DIR_ENV) {
+            Some(dir) => PathBuf::from(dir),
+            None => dirs::state_dir()
+                .or_else(dirs::data_local_dir)
+                .map(|dir| dir.join("plexisort"))
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Could not determine the state directory"))?,
+        };
+        Ok(HistoryStore::new(base.join("sessions")))
+    }
+
+    /// Starts a new session and returns its description.
+    pub fn create_session(&self, source_directories: &[String], destination: &str) -> io::Result<SessionInfo> {
+        fs::create_dir_all(&self.root)?;
+        let started_at = Local::now();
+        let session = SessionInfo {
+            id: format!("{}-{}", started_at.format("%Y%m%d-%H%M%S"), std::process::id()),
+            started_at,
+            source_directories: source_directories.iter().map(|dir| absolute_display(dir)).collect(),
+            destination: absolute_display(destination),
+            command_line: std::env::args().collect(),
+            undone_at: None,
+        };
+        self.save(&session)?;
+        Ok(session)
+    }
+
+    pub fn save(&self, session: &SessionInfo) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(session)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(self.info_path(&session.id), json)
+    }
+
+    /// Removes a session, e.g. because the run did not change anything.
+    pub fn remove(&self, session: &SessionInfo) -> io::Result<()> {
+        let log_path = self.undo_log_path(&session.id);
+        if log_path.exists() {
+            fs::remove_file(log_path)?;
+        }
+        fs::remove_file(self.info_path(&session.id))
+    }
+
+    /// All sessions, newest first.
+    pub fn list(&self) -> io::Result<Vec<SessionInfo>> {
+        if !self.root.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut sessions = Vec::new();
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match fs::read_to_string(&path).map(|contents| serde_json::from_str::<SessionInfo>(&contents)) {
+                Ok(Ok(session)) => sessions.push(session),
+                Ok(Err(e)) => log::warn!("Ignoring unreadable session {}: {}", path.display(), e),
+                Err(e) => log::warn!("Ignoring unreadable session {}: {}", path.display(), e),
+            }
+        }
+        sessions.sort_by(|a, b| b.started_at.cmp(&a.started_at).then_with(|| b.id.cmp(&a.id)));
+        Ok(sessions)
+    }
+
+    /// Finds the session to undo: the newest one that has not been undone yet
+    /// for `"latest"`, otherwise the session whose ID starts with `id`.
+    pub fn find(&self, id: &str) -> io::Result<SessionInfo> {
+        let sessions = self.list()?;
+        if id == "latest" {
+            return sessions
+                .into_iter()
+                .find(|session| session.undone_at.is_none())
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "No session to undo"));
+        }
+
+        let mut matching: Vec<SessionInfo> = sessions.into_iter().filter(|session| session.id.starts_with(id)).collect();
+        match matching.len() {
+            0 => Err(io::Error::new(io::ErrorKind::NotFound, format!("No session matches '{}'", id))),
+            1 => Ok(matching.remove(0)),
+            n => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("'{}' matches {} sessions, use a longer ID", id, n))),
+        }
+    }
+
+    /// Path of the undo log that a session's moves are recorded in.
+    pub fn undo_log_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}.jsonl", id))
+    }
+
+    /// Number of completed actions recorded in a session's undo log.
+    pub fn action_count(&self, id: &str) -> usize {
+        completed_entries(&self.undo_log_path(id)).map(|entries| entries.len()).unwrap_or(0)
+    }
+
+    /// Whether a session's journal has actions between intent and outcome.
+    pub fn has_interrupted_actions(&self, id: &str) -> bool {
+        read_journal(&self.undo_log_path(id))
+            .map(|records| records.iter().any(|record| record.state == EntryState::Pending))
+            .unwrap_or(false)
+    }
+
+    fn info_path(&self, id: &str) -> PathBuf {
+        self.root.join(format!("{}.json", id))
+    }
+}
+
+/// `path` as an absolute path, for storing in files that outlive the working directory.
+pub fn absolute_display(path: &str) -> String {
+    std::path::absolute(Path::new(path))
+        .map(|absolute| absolute.display().to_string())
+        .unwrap_or_else(|_| path.to_string())
+}
+
+/// Prints the sessions of `store` as a table, newest first.
+pub fn print_history(store: &HistoryStore) -> io::Result<()> {
+    let sessions = store.list()?;
+    if sessions.is_empty() {
+        println!("No sessions recorded.");
+        return Ok(());
+    }
+
+    println!("{:<24} {:<20} {:>7}  {:<8} SOURCE -> DESTINATION", "SESSION", "STARTED", "ACTIONS", "STATUS");
+    for session in sessions {
+        let status = if session.undone_at.is_some() { "undone" } else { "applied" };
+        println!(
+            "{:<24} {:<20} {:>7}  {:<8} {} -> {}",
+            session.id,
+            session.started_at.format("%Y-%m-%d %H:%M:%S"),
+            store.action_count(&session.id),
+            status,
+            session.source_directories.join(", "),
+            session.destination,
+        );
+        println!("{:<24} $ {}", "", session.command_line.join(" "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn session(id: &str, hours_ago: i64, undone: bool) -> SessionInfo {
+        SessionInfo {
+            id: id.to_string(),
+            started_at: Local::now() - chrono::Duration::hours(hours_ago),
+            source_directories: vec!["/source".to_string()],
+            destination: "/destination".to_string(),
+            command_line: vec!["plexisort".to_string()],
+            undone_at: undone.then(Local::now),
+        }
+    }
+
+    #[test]
+    fn test_find_latest_skips_undone_sessions() {
+        let temp_dir = tempdir().unwrap();
+        let store = HistoryStore::new(temp_dir.path().join("sessions"));
+        fs::create_dir_all(temp_dir.path().join("sessions")).unwrap();
+
+        store.save(&session("20240101-080000-1", 3, false)).unwrap();
+        store.save(&session("20240101-090000-2", 2, false)).unwrap();
+        store.save(&session("20240101-100000-3", 1, true)).unwrap();
+
+        let ids: Vec<String> = store.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, vec!["20240101-100000-3", "20240101-090000-2", "20240101-080000-1"]);
+        assert_eq!(store.find("latest").unwrap().id, "20240101-090000-2");
+        assert_eq!(store.find("20240101-08").unwrap().id, "20240101-080000-1");
+        assert!(store.find("20240101").is_err(), "An ambiguous prefix must not match");
+        assert!(store.find("1999").is_err());
+    }
+}
@@ -1,94 +1,44 @@
-use crate::metadata::extract_date_from_image;
-use crate::organizer::organize_file;
-use crate::processing_mode::ProcessingMode;
+use crate::dates::DateResolver;
+use crate::metadata::image::{read_image_metadata, ImageMetadata};
+use crate::metadata::{date_folder, file_metadata, Embedded, Metadata};
 use crate::traits::processor::Processor;
-use chrono::prelude::*;
-use std::{fs, io};
-use std::path::PathBuf;
-use std::time::SystemTime;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub struct ImageProcessor;
+#[derive(Default)]
+pub struct ImageProcessor {
+    pub dates: Arc<DateResolver>,
+}
 
 impl Processor for ImageProcessor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode) {
-        let date_based_dir = self.get_destination_subfolder(path);
-        let full_destination_dir = destination.join(&date_based_dir);
-        if let Err(e) = move_image(path, &full_destination_dir, mode) {
-            println!("Error moving image: {}", e);
-        }
-    }
-
-    fn get_destination_subfolder(&self, path: &PathBuf) -> PathBuf {
-        let date_based_subfolder = if let Some(date_str) = extract_date_from_image(path) {
-            format_date_to_path(&date_str)
-        } else {
-            match fs::metadata(path).and_then(|metadata| metadata.modified()) {
-                Ok(modified) => {
-                    let datetime = system_time_to_date_time(modified);
-                    format!("{}/{}", datetime.year(), format!("{:02} - {}", datetime.month(), datetime.format("%B")))
-                },
-                Err(_) => String::from("Unknown"),
-            }
-        };
+    fn get_destination_subfolder(&self, _path: &Path, metadata: &Metadata) -> PathBuf {
         // Prepend "Photos" directory to the date-based subfolder
-        PathBuf::from("Images").join(date_based_subfolder)
+        PathBuf::from("Images").join(date_folder(metadata))
     }
 
-}
-
-fn move_image(path: &PathBuf, destination_dir: &PathBuf, mode: &mut ProcessingMode) -> Result<(), io::Error> {
-    let destination_path = destination_dir.join(path.file_name().unwrap());
+    fn category(&self) -> &str {
+        "Images"
+    }
 
-    match mode {
-        ProcessingMode::DryRun(virtual_dir) => {
-            let path_parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-            virtual_dir.add_path(&path_parts);
-            Ok(())
-        },
-        ProcessingMode::Live => {
-            fs::create_dir_all(destination_dir)?;
-            organize_file(path, &destination_path, mode)
-        }
+    fn dates(&self) -> &DateResolver {
+        &self.dates
     }
-}
 
-fn format_date_to_path(date_str: &str) -> String {
-    // Assuming date_str is in "YYYY:MM:DD HH:MM:SS" format
-    let parts: Vec<&str> = date_str
-        .split_whitespace()
-        .next()
-        .unwrap()
-        .split(':')
-        .collect();
-    let year = parts.get(0).unwrap_or(&"UnknownYear");
-    let month_num = parts.get(1).unwrap_or(&"00");
-    let month = match &**month_num {
-        "01" => "01 - January",
-        "02" => "02 - February",
-        "03" => "03 - March",
-        "04" => "04 - April",
-        "05" => "05 - May",
-        "06" => "06 - June",
-        "07" => "07 - July",
-        "08" => "08 - August",
-        "09" => "09 - September",
-        "10" => "10 - October",
-        "11" => "11 - November",
-        "12" => "12 - December",
-        _ => "UnknownMonth",
-    };
-    format!("{}/{}", year, month)
-}
+    fn metadata(&self, path: &Path) -> Metadata {
+        let embedded = Embedded { image: read_image_metadata(path), ..Embedded::default() };
+        let mut metadata = file_metadata(path, &self.dates, &embedded);
+        metadata.extend(embedded.image.as_ref().map(ImageMetadata::fields).unwrap_or_default());
+        metadata
+    }
 
-// Utility function to convert SystemTime to DateTime<Local>
-fn system_time_to_date_time(local_time: SystemTime) -> DateTime<Local> {
-    let datetime: DateTime<Utc> = local_time.into();
-    datetime.with_timezone(&Local)
 }
 
 #[cfg(test)]
 mod image_processor_tests {
     use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
     use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
@@ -105,16 +55,27 @@ mod image_processor_tests {
         writeln!(file, "Dummy image content").unwrap();
 
         // Simulate an ImageProcessor instance and its processing
-        let processor = ImageProcessor {};
+        let processor = ImageProcessor::default();
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&image_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&image_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
         // Dynamically determine the current year and month for the expected path
         let now = Local::now();
-        let expected_date_dir = format!("{}/{} - {}", now.year(), format!("{:02}", now.month()), now.format("%B"));
+        let expected_date_dir = format!("{}/{:02} - {}", now.year(), now.month(), now.format("%B"));
         let expected_destination = destination_dir.join("Images").join(expected_date_dir).join("photo1.png");
 
         assert!(expected_destination.exists(), "Image was not moved to the correct destination in Live mode.");
     }
+
+    #[test]
+    fn test_image_processor_uses_the_date_in_the_file_name() {
+        let temp_dir = tempdir().unwrap();
+        let screenshot = temp_dir.path().join("Screenshot 2023-01-14 at 10.15.30.png");
+        File::create(&screenshot).unwrap();
+
+        let plan = ImageProcessor::default().plan(&screenshot, Path::new("destination"));
+        assert_eq!(plan.destination, Path::new("destination/Images/2023/01 - January").join(screenshot.file_name().unwrap()));
+        assert_eq!(plan.metadata.get("day").map(String::as_str), Some("14"));
+    }
 }
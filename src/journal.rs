@@ -0,0 +1,562 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::conflict::files_identical;
+use crate::transfer::PARTIAL_SUFFIX;
+
+/// Undo journal action for a file that was moved to `destination_path`.
+pub const MOVE_ACTION: &str = "move";
+/// Undo journal action for a source that was removed because
+/// `destination_path` already held identical contents.
+pub const DROP_DUPLICATE_ACTION: &str = "drop_duplicate";
+/// Undo journal action for a duplicate that was replaced in place by a hard
+/// link to `destination_path`.
+pub const HARDLINK_DUPLICATE_ACTION: &str = "hardlink_duplicate";
+
+/// Undo journal actions for a file that was copied, hard linked, symlinked
+/// or reflinked to `destination_path`, leaving the original in place.
+pub const COPY_ACTION: &str = "copy";
+pub const HARDLINK_ACTION: &str = "hardlink";
+pub const SYMLINK_ACTION: &str = "symlink";
+pub const REFLINK_ACTION: &str = "reflink";
+
+/// Suffix of the temporary hard link that replaces a duplicate.
+pub const LINK_SUFFIX: &str = "plexisort-link";
+/// Suffix of the hidden name under which a file replaced at the destination
+/// is kept, so that undo can bring it back.
+pub const REPLACED_SUFFIX: &str = "plexisort-replaced";
+/// Suffix of the temporary copy that turns a hard link back into a file on undo.
+pub const COPY_SUFFIX: &str = "plexisort-copy";
+
+/// One action on the file system, as recorded in the journal.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    #[serde(default)]
+    pub action: String,
+    pub original_path: PathBuf,
+    pub destination_path: PathBuf,
+    /// Where the file that was at `destination_path` before the action was
+    /// set aside, if the action replaced one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replaced_path: Option<PathBuf>,
+}
+
+/// How far an entry of the journal got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    /// The intent was recorded, but neither completion nor failure was.
+    Pending,
+    /// The action was performed.
+    Done,
+    /// The action failed or was rolled back; the file system is unchanged.
+    Aborted,
+}
+
+/// A journal entry together with its sequence number and state.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub seq: u64,
+    pub entry: JournalEntry,
+    pub state: EntryState,
+}
+
+/// A line of the journal file: either the intent to perform an action, or the
+/// outcome of an earlier intent.
+#[derive(Serialize, Deserialize)]
+struct Line {
+    #[serde(default)]
+    seq: Option<u64>,
+    #[serde(default)]
+    state: Option<String>,
+    #[serde(flatten)]
+    entry: Option<JournalEntry>,
+}
+
+const INTENT: &str = "intent";
+const DONE: &str = "done";
+const ABORTED: &str = "aborted";
+
+/// Points at which a test can make the journal fail as if the process had
+/// crashed.
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// After the intent is written, before the action is performed.
+    AfterIntent,
+    /// After the action is performed, before it is marked as done.
+    AfterAction,
+}
+
+/// A write-ahead journal of the actions of one session.
+///
+/// Every action is written as an intent and synced to disk before it is
+/// performed, and marked as done or aborted afterwards. An intent without an
+/// outcome means the process stopped in between; `recover` finds out from the
+/// file system whether the action happened and completes the entry.
+#[derive(Debug)]
+pub struct Journal {
+    file: Mutex<File>,
+    next_seq: Mutex<u64>,
+    #[cfg(test)]
+    fault: Option<Fault>,
+}
+
+impl Journal {
+    /// Opens the journal at `path` for appending, creating it if needed.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let next_seq = if path.exists() {
+            read_journal(path)?.iter().map(|record| record.seq + 1).max().unwrap_or(0)
+        } else {
+            0
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            file: Mutex::new(file),
+            next_seq: Mutex::new(next_seq),
+            #[cfg(test)]
+            fault: None,
+        })
+    }
+
+    #[cfg(test)]
+    pub fn with_fault(mut self, fault: Fault) -> Self {
+        self.fault = Some(fault);
+        self
+    }
+
+    /// Records the intent to perform `action`, performs it and records its
+    /// outcome. Paths are stored as absolute paths, so that the session can
+    /// be undone or recovered from any directory.
+    pub fn record(
+        &self,
+        action: &str,
+        original_path: &Path,
+        destination_path: &Path,
+        perform: impl FnOnce() -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.record_replacing(action, original_path, destination_path, None, perform)
+    }
+
+    /// Like `record`, for an action that first sets the file at
+    /// `destination_path` aside at `replaced_path`. If the action fails,
+    /// `perform` must put that file back.
+    pub fn record_replacing(
+        &self,
+        action: &str,
+        original_path: &Path,
+        destination_path: &Path,
+        replaced_path: Option<&Path>,
+        perform: impl FnOnce() -> io::Result<()>,
+    ) -> io::Result<()> {
+        let entry = JournalEntry {
+            action: action.to_string(),
+            original_path: std::path::absolute(original_path)?,
+            destination_path: std::path::absolute(destination_path)?,
+            replaced_path: replaced_path.map(std::path::absolute).transpose()?,
+        };
+
+        let seq = {
+            let mut next_seq = self.next_seq.lock().unwrap();
+            let seq = *next_seq;
+            *next_seq += 1;
+            seq
+        };
+        self.append(&Line { seq: Some(seq), state: Some(INTENT.to_string()), entry: Some(entry) })?;
+        #[cfg(test)]
+        self.inject(Fault::AfterIntent)?;
+
+        if let Err(e) = perform() {
+            self.mark(seq, ABORTED)?;
+            return Err(e);
+        }
+        #[cfg(test)]
+        self.inject(Fault::AfterAction)?;
+
+        self.mark(seq, DONE)
+    }
+
+    #[cfg(test)]
+    fn inject(&self, fault: Fault) -> io::Result<()> {
+        if self.fault == Some(fault) {
+            return Err(io::Error::other(format!("injected fault: {:?}", fault)));
+        }
+        Ok(())
+    }
+
+    fn mark(&self, seq: u64, state: &str) -> io::Result<()> {
+        self.append(&Line { seq: Some(seq), state: Some(state.to_string()), entry: None })
+    }
+
+    /// Appends a line and syncs it to disk before returning.
+    fn append(&self, line: &Line) -> io::Result<()> {
+        let json = serde_json::to_string(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", json)?;
+        file.sync_data()
+    }
+}
+
+/// Reads all entries of a journal in the order they were recorded.
+///
+/// Lines without a sequence number were written by older versions, which
+/// only logged completed moves, and count as done.
+pub fn read_journal(path: &Path) -> io::Result<Vec<Record>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut records: BTreeMap<u64, Record> = BTreeMap::new();
+    let mut legacy_seq = 0;
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let parsed: Line = match serde_json::from_str(&line) {
+            Ok(parsed) => parsed,
+            // A torn last line is what a crash in the middle of a write leaves behind
+            Err(e) => {
+                log::warn!("Ignoring unreadable line {} of {}: {}", number + 1, path.display(), e);
+                continue;
+            }
+        };
+
+        match (parsed.seq, parsed.state.as_deref(), parsed.entry) {
+            (None, _, Some(mut entry)) => {
+                if entry.action.is_empty() {
+                    entry.action = MOVE_ACTION.to_string();
+                }
+                records.insert(legacy_seq, Record { seq: legacy_seq, entry, state: EntryState::Done });
+                legacy_seq += 1;
+            }
+            (Some(seq), Some(INTENT), Some(entry)) => {
+                records.insert(seq, Record { seq, entry, state: EntryState::Pending });
+            }
+            (Some(seq), Some(state @ (DONE | ABORTED)), _) => match records.get_mut(&seq) {
+                Some(record) => record.state = if state == DONE { EntryState::Done } else { EntryState::Aborted },
+                None => log::warn!("Ignoring outcome of unknown entry {} in {}", seq, path.display()),
+            },
+            _ => log::warn!("Ignoring malformed line {} of {}", number + 1, path.display()),
+        }
+    }
+
+    Ok(records.into_values().collect())
+}
+
+/// Entries whose action was performed, in the order they were recorded.
+pub fn completed_entries(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    Ok(read_journal(path)?
+        .into_iter()
+        .filter(|record| record.state == EntryState::Done)
+        .map(|record| record.entry)
+        .collect())
+}
+
+/// What `recover` did with an incomplete entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// The action had happened and is now marked as done, so it can be undone.
+    RolledForward,
+    /// The action had not happened; leftovers were cleaned up and the entry
+    /// is marked as aborted.
+    RolledBack,
+    /// The file is neither at its original path nor at its destination, so
+    /// the action cannot be completed or undone. The entry is marked as
+    /// aborted, so that the rest of the session can still be undone.
+    Lost,
+}
+
+/// Completes the entries of a journal that were interrupted between intent
+/// and outcome, and returns what was done with each of them.
+pub fn recover(path: &Path) -> io::Result<Vec<(JournalEntry, Recovery)>> {
+    let pending: Vec<Record> = read_journal(path)?
+        .into_iter()
+        .filter(|record| record.state == EntryState::Pending)
+        .collect();
+    if pending.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let journal = Journal::open(path)?;
+    let mut recovered = Vec::new();
+    for record in pending {
+        let recovery = recover_entry(&record.entry)?;
+        if recovery != Recovery::RolledForward {
+            restore_replaced(&record.entry)?;
+        }
+        if recovery == Recovery::Lost {
+            log::warn!("{} is neither at {} nor at {}", record.entry.action, record.entry.original_path.display(), record.entry.destination_path.display());
+        }
+        journal.mark(record.seq, if recovery == Recovery::RolledForward { DONE } else { ABORTED })?;
+        recovered.push((record.entry, recovery));
+    }
+    Ok(recovered)
+}
+
+fn recover_entry(entry: &JournalEntry) -> io::Result<Recovery> {
+    let original = entry.original_path.as_path();
+    match entry.action.as_str() {
+        HARDLINK_DUPLICATE_ACTION => {
+            // The duplicate is replaced by renaming a temporary link over it,
+            // so a leftover link means the replacement did not happen
+            let link_path = temporary_path(original, LINK_SUFFIX);
+            if link_path.exists() {
+                fs::remove_file(&link_path)?;
+                Ok(Recovery::RolledBack)
+            } else if original.exists() && same_file(original, &entry.destination_path) {
+                Ok(Recovery::RolledForward)
+            } else {
+                Ok(Recovery::RolledBack)
+            }
+        }
+        MOVE_ACTION => {
+            // A move across file systems copies into a temporary file first
+            let partial = temporary_path(&entry.destination_path, PARTIAL_SUFFIX);
+            if partial.exists() {
+                fs::remove_file(&partial)?;
+            }
+            if !original.exists() {
+                Ok(if entry.destination_path.exists() { Recovery::RolledForward } else { Recovery::Lost })
+            } else if entry.destination_path.exists() && files_identical(original, &entry.destination_path)? {
+                // The copy is complete, only the removal of the source is missing
+                fs::remove_file(original)?;
+                Ok(Recovery::RolledForward)
+            } else {
+                Ok(Recovery::RolledBack)
+            }
+        }
+        DROP_DUPLICATE_ACTION if original.exists() => Ok(Recovery::RolledBack),
+        DROP_DUPLICATE_ACTION if entry.destination_path.exists() => Ok(Recovery::RolledForward),
+        DROP_DUPLICATE_ACTION => Ok(Recovery::Lost),
+        // Copies and links are created under a temporary name and renamed into place
+        _ => {
+            let partial = temporary_path(&entry.destination_path, PARTIAL_SUFFIX);
+            if partial.symlink_metadata().is_ok() {
+                fs::remove_file(&partial)?;
+            }
+            if transferred(entry)? {
+                Ok(Recovery::RolledForward)
+            } else {
+                Ok(Recovery::RolledBack)
+            }
+        }
+    }
+}
+
+/// Puts the file that an action set aside back at the destination, if the
+/// action stopped before anything took its place.
+fn restore_replaced(entry: &JournalEntry) -> io::Result<()> {
+    match &entry.replaced_path {
+        Some(replaced) if replaced.exists() && entry.destination_path.symlink_metadata().is_err() => {
+            fs::rename(replaced, &entry.destination_path)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Whether the copy or link that `entry` describes is at its destination.
+fn transferred(entry: &JournalEntry) -> io::Result<bool> {
+    let original = entry.original_path.as_path();
+    let destination = entry.destination_path.as_path();
+    if destination.symlink_metadata().is_err() {
+        return Ok(false);
+    }
+    match entry.action.as_str() {
+        SYMLINK_ACTION => Ok(fs::read_link(destination).is_ok_and(|target| target == original)),
+        HARDLINK_ACTION => Ok(same_file(original, destination)),
+        _ => Ok(original.exists() && files_identical(original, destination)?),
+    }
+}
+
+/// Path of a temporary file next to `path`, e.g. `.photo.jpg.plexisort-link`.
+pub fn temporary_path(path: &Path, suffix: &str) -> PathBuf {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{}.{}", file_name, suffix))
+}
+
+/// Whether `path` is one of the files that Plexisort keeps next to the files
+/// it organizes: a transfer in progress or a replaced file kept for undo.
+/// They are never organized or compared themselves.
+pub fn is_temporary_path(path: &Path) -> bool {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    file_name.starts_with('.')
+        && [PARTIAL_SUFFIX, LINK_SUFFIX, REPLACED_SUFFIX, COPY_SUFFIX].iter().any(|suffix| {
+            file_name.strip_suffix(suffix).and_then(|name| name.strip_suffix('.')).is_some_and(|name| name.len() > 1)
+        })
+}
+
+/// Deletes the replaced files that the completed entries of a journal kept
+/// for undo, and returns how many there were. The session can no longer be
+/// undone afterwards.
+pub fn discard_replaced(path: &Path) -> io::Result<usize> {
+    let mut discarded = 0;
+    for entry in completed_entries(path)? {
+        if let Some(replaced) = entry.replaced_path.filter(|replaced| replaced.exists()) {
+            fs::remove_file(&replaced)?;
+            discarded += 1;
+        }
+    }
+    Ok(discarded)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_file(a: &Path, b: &Path) -> bool {
+    files_identical(a, b).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_interrupted_actions_are_recovered() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let source = temp_dir.path().join("a.txt");
+        let destination = temp_dir.path().join("out").join("a.txt");
+        fs::write(&source, "a").unwrap();
+        fs::create_dir_all(destination.parent().unwrap()).unwrap();
+
+        // Crash after the move: the entry is rolled forward
+        let journal = Journal::open(&journal_path).unwrap().with_fault(Fault::AfterAction);
+        let result = journal.record(MOVE_ACTION, &source, &destination, || fs::rename(&source, &destination));
+        assert!(result.is_err());
+        assert!(completed_entries(&journal_path).unwrap().is_empty());
+
+        // Crash before the move: the entry is rolled back
+        let other = temp_dir.path().join("b.txt");
+        fs::write(&other, "b").unwrap();
+        let journal = Journal::open(&journal_path).unwrap().with_fault(Fault::AfterIntent);
+        let other_destination = temp_dir.path().join("out").join("b.txt");
+        let result = journal.record(MOVE_ACTION, &other, &other_destination, || fs::rename(&other, &other_destination));
+        assert!(result.is_err());
+
+        let recovered = recover(&journal_path).unwrap();
+        assert_eq!(recovered.iter().map(|(_, recovery)| *recovery).collect::<Vec<_>>(), vec![Recovery::RolledForward, Recovery::RolledBack]);
+
+        let states: Vec<EntryState> = read_journal(&journal_path).unwrap().iter().map(|record| record.state).collect();
+        assert_eq!(states, vec![EntryState::Done, EntryState::Aborted]);
+        assert_eq!(completed_entries(&journal_path).unwrap()[0].destination_path, destination);
+        assert!(recover(&journal_path).unwrap().is_empty(), "Recovering twice must not change anything.");
+    }
+
+    #[test]
+    fn test_interrupted_copy_is_recovered() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let source = temp_dir.path().join("clip.mp4");
+        let destination = temp_dir.path().join("clip (copied).mp4");
+        fs::write(&source, "clip").unwrap();
+
+        // The copy is in place, but the source was not removed yet
+        let journal = Journal::open(&journal_path).unwrap().with_fault(Fault::AfterIntent);
+        assert!(journal.record(MOVE_ACTION, &source, &destination, || Ok(())).is_err());
+        fs::copy(&source, &destination).unwrap();
+
+        let recovered = recover(&journal_path).unwrap();
+        assert_eq!(recovered[0].1, Recovery::RolledForward);
+        assert!(!source.exists());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "clip");
+    }
+
+    #[test]
+    fn test_vanished_files_are_not_rolled_forward() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let source = temp_dir.path().join("a.txt");
+        let destination = temp_dir.path().join("out").join("a.txt");
+        fs::write(&source, "a").unwrap();
+
+        // Crash before the move, after which the file was deleted
+        let journal = Journal::open(&journal_path).unwrap().with_fault(Fault::AfterIntent);
+        assert!(journal.record(MOVE_ACTION, &source, &destination, || fs::rename(&source, &destination)).is_err());
+        fs::remove_file(&source).unwrap();
+
+        let recovered = recover(&journal_path).unwrap();
+        assert_eq!(recovered[0].1, Recovery::Lost);
+        assert_eq!(read_journal(&journal_path).unwrap()[0].state, EntryState::Aborted);
+        assert!(completed_entries(&journal_path).unwrap().is_empty(), "Undo must not try to move back a file that is gone.");
+    }
+
+    #[test]
+    fn test_interrupted_replacement_is_rolled_back() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let source = temp_dir.path().join("new.txt");
+        let destination = temp_dir.path().join("notes.txt");
+        let replaced = temporary_path(&destination, REPLACED_SUFFIX);
+        fs::write(&source, "new").unwrap();
+        fs::write(&destination, "old").unwrap();
+
+        // The existing file was set aside, but the move did not happen
+        let journal = Journal::open(&journal_path).unwrap().with_fault(Fault::AfterIntent);
+        assert!(journal.record_replacing(MOVE_ACTION, &source, &destination, Some(&replaced), || Ok(())).is_err());
+        fs::rename(&destination, &replaced).unwrap();
+
+        let recovered = recover(&journal_path).unwrap();
+        assert_eq!(recovered[0].1, Recovery::RolledBack);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "old");
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new");
+        assert!(!replaced.exists());
+    }
+
+    #[test]
+    fn test_replaced_files_can_be_discarded() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let source = temp_dir.path().join("new.txt");
+        let destination = temp_dir.path().join("notes.txt");
+        let replaced = temporary_path(&destination, REPLACED_SUFFIX);
+        fs::write(&source, "new").unwrap();
+        fs::write(&destination, "old").unwrap();
+
+        let journal = Journal::open(&journal_path).unwrap();
+        journal.record_replacing(MOVE_ACTION, &source, &destination, Some(&replaced), || {
+            fs::rename(&destination, &replaced)?;
+            fs::rename(&source, &destination)
+        }).unwrap();
+        assert!(is_temporary_path(&replaced));
+        assert!(!is_temporary_path(&destination) && !is_temporary_path(Path::new(".plexisort-replaced")));
+
+        assert_eq!(discard_replaced(&journal_path).unwrap(), 1);
+        assert!(!replaced.exists());
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_failed_action_is_aborted() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("session.jsonl");
+        let journal = Journal::open(&journal_path).unwrap();
+        let missing = temp_dir.path().join("missing.txt");
+
+        let result = journal.record(MOVE_ACTION, &missing, &temp_dir.path().join("out.txt"), || fs::rename(&missing, temp_dir.path().join("out.txt")));
+        assert!(result.is_err());
+        assert_eq!(read_journal(&journal_path).unwrap()[0].state, EntryState::Aborted);
+    }
+
+    #[test]
+    fn test_legacy_log_lines_count_as_done() {
+        let temp_dir = tempdir().unwrap();
+        let journal_path = temp_dir.path().join("undo_log.jsonl");
+        fs::write(&journal_path, "{\"original_path\":\"/a/x.jpg\",\"destination_path\":\"/b/x.jpg\"}\n{\"action\":\"drop_duplicate\",\"original_path\":\"/a/y.jpg\",\"destination_path\":\"/b/x.jpg\"}\n").unwrap();
+
+        let entries = completed_entries(&journal_path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].action, MOVE_ACTION);
+        assert_eq!(entries[1].action, DROP_DUPLICATE_ACTION);
+    }
+}
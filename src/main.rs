@@ -3,6 +3,7 @@ mod document_processor;
 mod file_processor; // Ensure this module is correctly defined and accessible
 mod image_processor;
 mod video_processor;
+mod audio_processor;
 mod metadata;
 mod organizer;
 mod processing_mode;
@@ -11,33 +12,78 @@ mod compressed_file_processor;
 mod generic_processor;
 mod cli;
 mod traits;
+mod conflict;
+mod dedupe;
+mod rules;
+mod rule_processor;
+mod template;
+mod template_processor;
+mod history;
+mod journal;
+mod transfer;
+mod sniff;
+mod overlap;
+mod filter;
+mod plan;
+mod executor;
+mod error;
+mod summary;
+mod report;
+mod watch;
+mod stability;
+mod dates;
+mod sidecar;
 
 use config::Config;
-use file_processor::process_directory;
+use dates::DateResolver;
+use error::PlexisortError;
+use executor::{execute_plan, Placements};
+use file_processor::{plan_directory, process_directory, ScanOptions};
 use log::LevelFilter;
 use processing_mode::ProcessingMode;
 
 use simplelog::SimpleLogger;
 use virtual_directory::VirtualDirectory;
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use std::{fs, process};
 
-use organizer::undo_last_actions;
-use crate::organizer::{clear_undo_log, print_current_structure};
-use crate::traits::DefaultProcessorFactory;
+use conflict::ConflictPolicy;
+use dedupe::{find_duplicates, handle_duplicates, DuplicateAction};
+use history::{print_history, HistoryStore, SessionInfo};
+use journal::{discard_replaced, Journal, Recovery};
+use filter::PathFilter;
+use overlap::{nested_destinations, NestedDestination};
+use plan::PlanFile;
+use report::{Report, ReportFormat};
+use summary::{RunSummary, EXIT_FATAL, EXIT_SUCCESS};
+use sniff::TypePrecedence;
+use transfer::TransferMode;
+use watch::watch;
+use organizer::{undo_actions, OrganizeOptions};
+use crate::organizer::print_current_structure;
+use crate::traits::{DefaultProcessorFactory, ProcessorFactory, RuleProcessorFactory, TemplateProcessorFactory};
 
 fn main() {
     init_logging();
     log::info!("Application starting up");
     let matches = cli::build_cli().get_matches();
 
-    if let Err(e) = run_app(&matches) {
-        // This is where the error gets logged, providing a single, clear error message.
-        log::error!("Application error: {}", e);
-        process::exit(1);
-    } else {
-        log::info!("Folder structure organized successfully.");
+    match run_app(&matches) {
+        Ok(summary) => {
+            if summary.exit_code() == EXIT_SUCCESS {
+                log::info!("Folder structure organized successfully.");
+            }
+            process::exit(summary.exit_code());
+        }
+        Err(e) => {
+            // This is where the error gets logged, providing a single, clear error message.
+            log::error!("Application error: {}", e);
+            process::exit(EXIT_FATAL);
+        }
     }
 }
 
@@ -46,12 +92,37 @@ fn init_logging() {
     SimpleLogger::init(LevelFilter::Warn, simplelog::Config::default()).expect("Failed to initialize logging");
 }
 
-fn run_app(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+fn run_app(matches: &clap::ArgMatches) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    if let Some(session_id) = matches.subcommand_matches("history").and_then(|history| history.get_one::<String>("forget")) {
+        handle_forget(session_id)?;
+        return Ok(RunSummary::default());
+    }
+    if matches.subcommand_matches("history").is_some() {
+        let store = HistoryStore::open_default()?;
+        print_history(&store).map_err(|e| format!("Failed to read the history: {}", e))?;
+        return Ok(RunSummary::default());
+    }
+    if matches.subcommand_matches("recover").is_some() {
+        handle_recover()?;
+        return Ok(RunSummary::default());
+    }
+    if let Some(session_id) = matches.get_one::<String>("undo") {
+        handle_undo(session_id)?;
+        return Ok(RunSummary::default());
+    }
+
+    if let Some(plan_matches) = matches.subcommand_matches("plan") {
+        return handle_plan(plan_matches);
+    }
+    if let Some(apply_matches) = matches.subcommand_matches("apply") {
+        return handle_apply(apply_matches);
+    }
+
     let config = load_or_build_config(matches)?;
+    configure_jobs(matches)?;
     let mut mode = determine_processing_mode(matches.contains_id("dry-run"));
-
-    // Create an instance of the default processor factory
-    let factory = DefaultProcessorFactory;
+    let mut options = build_organize_options(matches, &config);
+    let factory = build_processor_factory(matches, &config)?;
 
     println!("Original Directory Structure:");
     for source_directory in &config.source_directories {
@@ -60,23 +131,225 @@ fn run_app(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>>
         print_current_structure(path, "");
     }
 
-    check_source_directories(&config)?;
+    check_source_directories(matches, &config)?;
+    let mut scan = build_scan_options(matches, &config)?;
+
+    // Live runs are recorded as a session so that they can be undone later
+    let session = match mode {
+        ProcessingMode::Live => Some(start_session(&config.source_directories, &config.destination, &mut options)?),
+        ProcessingMode::DryRun(_) => None,
+    };
+
+    let dest_path = PathBuf::from(&config.destination);
+    let mut summary = RunSummary::default();
+    scan.skip = handle_duplicate_files(matches, &config, &dest_path, &mut mode, &options, &scan, &mut summary);
 
     // Now pass the factory when processing directories
+    let mut placements = Placements::new();
     for source_directory in &config.source_directories {
         let source_path = PathBuf::from(source_directory);
-        let dest_path = PathBuf::from(&config.destination);
         println!("Processing '{}'", source_path.display());
-        process_directory(&source_path, &dest_path, &mut mode, &factory); // Adjusted to include factory
+        process_directory(&source_path, &dest_path, &mut mode, factory.as_ref(), &options, &scan, &mut placements, &mut summary); // Adjusted to include factory
     }
 
-    handle_undo(matches)?;
+    // In watch mode the session stays open until the watch is stopped
+    let watched = match matches.subcommand_matches("watch") {
+        Some(watch_matches) => {
+            let quiet = Duration::from_secs(*watch_matches.get_one::<u64>("debounce").expect("The debounce has a default"));
+            let sources: Vec<PathBuf> = config.source_directories.iter().map(PathBuf::from).collect();
+            watch(&sources, &dest_path, &mut mode, factory.as_ref(), &options, &scan, quiet, placements, &mut summary)
+        }
+        None => Ok(()),
+    };
+
+    let session_id = match session {
+        Some((store, session)) => finish_session(&store, &session, &mut options)?,
+        None => None,
+    };
+    watched?;
 
+    summary.print();
+    write_report(matches, &summary, matches!(mode, ProcessingMode::DryRun(_)), session_id)?;
+    Ok(summary)
+}
+
+// Write the moves of a run to a plan file for review instead of carrying them out
+fn handle_plan(matches: &clap::ArgMatches) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let output = matches.get_one::<String>("output").expect("The plan file is required");
+    let config = load_or_build_config(matches)?;
+    configure_jobs(matches)?;
+    let options = build_organize_options(matches, &config);
+    let factory = build_processor_factory(matches, &config)?;
+    check_source_directories(matches, &config)?;
+    let scan = build_scan_options(matches, &config)?;
+
+    let duplicates = matches.get_one::<String>("on-duplicate")
+        .and_then(|name| DuplicateAction::from_name(name))
+        .unwrap_or(config.on_duplicate);
+    if duplicates != DuplicateAction::Off {
+        log::warn!("Duplicate handling is not part of plans and is skipped");
+    }
+
+    let dest_path = PathBuf::from(&config.destination);
+    let mut actions = Vec::new();
+    let mut summary = RunSummary::default();
+    for source_directory in &config.source_directories {
+        for planned in plan_directory(Path::new(source_directory), &dest_path, factory.as_ref(), &scan, &mut summary) {
+            match planned {
+                Ok(action) => actions.push(action),
+                Err(e) => summary.record_failure(e),
+            }
+        }
+    }
+
+    let plan = PlanFile::new(&config.source_directories, &config.destination, &options, actions);
+    plan.write(Path::new(output))
+        .map_err(|e| format!("Failed to write the plan to {}: {}", output, e))?;
+    println!("Planned {} file(s) in {}, carry the plan out with `plexisort apply {}`", plan.entries.len(), output, output);
+    if !summary.files.is_empty() {
+        // Only the files that were left out of the plan are in the summary
+        summary.print();
+    }
+    Ok(summary)
+}
+
+// Carry out a plan file, leaving alone the files that changed since it was written
+fn handle_apply(matches: &clap::ArgMatches) -> Result<RunSummary, Box<dyn std::error::Error>> {
+    let plan_path = matches.get_one::<String>("plan").expect("The plan file is required");
+    let plan = PlanFile::read(Path::new(plan_path))?;
+    let (actions, refused) = plan.verify();
+    let mut summary = RunSummary::default();
+    refused.into_iter().for_each(|error| summary.record_failure(error));
+
+    let mut options = OrganizeOptions {
+        conflict_policy: plan.on_conflict,
+        transfer: plan.transfer,
+        ..OrganizeOptions::default()
+    };
+    let (store, session) = start_session(&plan.source_directories, &plan.destination, &mut options)?;
+    execute_plan(&actions, &mut ProcessingMode::Live, &options, &mut summary);
+    let session_id = finish_session(&store, &session, &mut options)?;
+
+    summary.print();
+    write_report(matches, &summary, false, session_id)?;
+    Ok(summary)
+}
+
+// Start a session and its journal, which the moves of a live run are recorded in
+fn start_session(
+    source_directories: &[String],
+    destination: &str,
+    options: &mut OrganizeOptions,
+) -> Result<(HistoryStore, SessionInfo), Box<dyn std::error::Error>> {
+    let store = HistoryStore::open_default()?;
+    warn_about_interrupted_sessions(&store);
+    let session = store.create_session(source_directories, destination)
+        .map_err(|e| format!("Failed to start a session: {}", e))?;
+    let journal = Journal::open(&store.undo_log_path(&session.id))
+        .map_err(|e| format!("Failed to open the journal of session {}: {}", session.id, e))?;
+    options.journal = Some(Arc::new(journal));
+    Ok((store, session))
+}
+
+// Close the session's journal and return its ID, or remove it if nothing was changed
+fn finish_session(store: &HistoryStore, session: &SessionInfo, options: &mut OrganizeOptions) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    options.journal = None; // Close the journal before the session may be removed
+    if store.action_count(&session.id) == 0 {
+        store.remove(session)?;
+        return Ok(None);
+    }
+    println!("Session {} recorded, revert it with --undo {}", session.id, session.id);
+    Ok(Some(session.id.clone()))
+}
+
+// Write the run report requested with --report, in the format named by --report-format or the file's extension
+fn write_report(matches: &clap::ArgMatches, summary: &RunSummary, dry_run: bool, session_id: Option<String>) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = matches.get_one::<String>("report").map(Path::new) else {
+        return Ok(());
+    };
+    let format = matches.get_one::<String>("report-format")
+        .and_then(|name| ReportFormat::from_name(name))
+        .or_else(|| ReportFormat::from_path(path))
+        .unwrap_or(ReportFormat::Json);
+
+    Report::new(summary, dry_run, session_id).write(path, format)
+        .map_err(|e| format!("Failed to write the report to {}: {}", path.display(), e))?;
+    println!("Report written to {}", path.display());
     Ok(())
 }
 
+fn configure_jobs(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(&jobs) = matches.get_one::<usize>("jobs") {
+        rayon::ThreadPoolBuilder::new().num_threads(jobs).build_global()
+            .map_err(|e| format!("Failed to start {} worker threads: {}", jobs, e))?;
+    }
+    Ok(())
+}
+
+// Rules from the config file take precedence over the default processor factory,
+// and category templates decide where the built-in processors put the other files
+fn build_processor_factory(matches: &clap::ArgMatches, config: &Config) -> Result<Box<dyn ProcessorFactory>, Box<dyn std::error::Error>> {
+    let type_precedence = matches.get_one::<String>("type-precedence")
+        .and_then(|name| TypePrecedence::from_name(name))
+        .unwrap_or(config.type_precedence);
+
+    let dates = Arc::new(DateResolver::new(&config.dates).map_err(PlexisortError::Config)?);
+    let built_in = DefaultProcessorFactory { type_precedence, dates: Arc::clone(&dates) };
+    let templated = TemplateProcessorFactory::new(&config.templates, &config.unknown_token, built_in).map_err(PlexisortError::Config)?;
+    let rules = RuleProcessorFactory::new(&config.rules, &config.unknown_token, dates, templated)
+        .map_err(PlexisortError::Config)?;
+    Ok(Box::new(rules))
+}
+
+fn nested_destination_handling(matches: &clap::ArgMatches, config: &Config) -> NestedDestination {
+    matches.get_one::<String>("nested-destination")
+        .and_then(|name| NestedDestination::from_name(name))
+        .unwrap_or(config.nested_destination)
+}
+
+// Destination subtrees inside a source are left out of the walk; `check_source_directories`
+// has already refused the overlaps that are not allowed
+fn build_scan_options(matches: &clap::ArgMatches, config: &Config) -> Result<ScanOptions, Box<dyn std::error::Error>> {
+    let nested_destination = nested_destination_handling(matches, config);
+    let mut stability = config.stability.clone();
+    if let Some(&quiet_period) = matches.get_one::<u64>("quiet-period") {
+        stability.quiet_period = quiet_period;
+    }
+    Ok(ScanOptions {
+        prune: nested_destinations(&config.source_directories, &config.destination, nested_destination).map_err(PlexisortError::Config)?,
+        filter: Arc::new(build_path_filter(matches, config)?),
+        stability,
+        sidecars: config.sidecars.clone(),
+        ..ScanOptions::default()
+    })
+}
+
 
-fn check_source_directories(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+// Find files whose contents already exist and handle them before anything else is organized
+fn handle_duplicate_files(
+    matches: &clap::ArgMatches,
+    config: &Config,
+    dest_path: &Path,
+    mode: &mut ProcessingMode,
+    options: &OrganizeOptions,
+    scan: &ScanOptions,
+    summary: &mut RunSummary,
+) -> HashSet<PathBuf> {
+    let action = matches.get_one::<String>("on-duplicate")
+        .and_then(|name| DuplicateAction::from_name(name))
+        .unwrap_or(config.on_duplicate);
+    if action == DuplicateAction::Off {
+        return HashSet::new();
+    }
+
+    let sources: Vec<PathBuf> = config.source_directories.iter().map(PathBuf::from).collect();
+    let duplicates = find_duplicates(&sources, dest_path, scan);
+    println!("Found {} duplicate file(s)", duplicates.len());
+    handle_duplicates(&duplicates, action, dest_path, mode, options, summary)
+}
+
+
+fn check_source_directories(matches: &clap::ArgMatches, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
     for source_directory in &config.source_directories {
         let source_path = Path::new(source_directory);
         if !source_path.exists() {
@@ -90,32 +363,82 @@ fn check_source_directories(config: &Config) -> Result<(), Box<dyn std::error::E
             return Err(format!("Error: No permission to read source directory '{}': {}", source_directory, e).into());
         }
     }
+    nested_destinations(&config.source_directories, &config.destination, nested_destination_handling(matches, config))
+        .map_err(PlexisortError::Config)?;
     Ok(())
 }
 
 
-fn handle_undo(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
-    if matches.contains_id("undo") {
-        match undo_last_actions() {
-            Ok(_) => {
-                println!("Undo actions completed successfully.");
-                clear_undo_log().map_err(|e| format!("Failed to clear undo log: {}", e))?;
-            }
-            Err(e) => {
-                return Err(format!("Error undoing actions: {}", e).into());
-            }
+// Drop a session that will not be undone, together with the replaced files kept for its undo
+fn handle_forget(session_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let store = HistoryStore::open_default()?;
+    let session = store.find(session_id)?;
+    if store.has_interrupted_actions(&session.id) {
+        return Err(format!("Session {} has interrupted actions, run `plexisort recover` first", session.id).into());
+    }
+
+    let discarded = discard_replaced(&store.undo_log_path(&session.id))
+        .map_err(|e| format!("Failed to delete the files replaced in session {}: {}", session.id, e))?;
+    store.remove(&session)?;
+    println!("Forgot session {}, deleted {} replaced file(s).", session.id, discarded);
+    Ok(())
+}
+
+fn handle_undo(session_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let store = HistoryStore::open_default()?;
+    let mut session = store.find(session_id)?;
+    if let Some(undone_at) = session.undone_at {
+        return Err(format!("Session {} was already undone on {}", session.id, undone_at.format("%Y-%m-%d %H:%M:%S")).into());
+    }
+
+    undo_actions(&store.undo_log_path(&session.id), Path::new(&session.destination))
+        .map_err(|e| format!("Error undoing session {}: {}", session.id, e))?;
+
+    session.undone_at = Some(chrono::Local::now());
+    store.save(&session)?;
+    println!("Undo of session {} completed successfully.", session.id);
+    Ok(())
+}
+
+// Complete the actions that a crashed run left between intent and outcome
+fn handle_recover() -> Result<(), Box<dyn std::error::Error>> {
+    let store = HistoryStore::open_default()?;
+    let mut recovered_any = false;
+    for session in store.list()? {
+        let recovered = journal::recover(&store.undo_log_path(&session.id))
+            .map_err(|e| format!("Failed to recover session {}: {}", session.id, e))?;
+        for (entry, recovery) in &recovered {
+            let outcome = match recovery {
+                Recovery::RolledForward => "completed",
+                Recovery::RolledBack => "rolled back",
+                Recovery::Lost => "abandoned, the file is gone",
+            };
+            println!("Session {}: {} of {} {}", session.id, entry.action, entry.original_path.display(), outcome);
         }
+        recovered_any |= !recovered.is_empty();
+    }
+    if !recovered_any {
+        println!("No interrupted actions found.");
     }
     Ok(())
 }
 
+fn warn_about_interrupted_sessions(store: &HistoryStore) {
+    if let Ok(sessions) = store.list() {
+        for session in sessions.iter().filter(|session| store.has_interrupted_actions(&session.id)) {
+            log::warn!("Session {} was interrupted, run `plexisort recover` before undoing it", session.id);
+        }
+    }
+}
+
 // Load or build config based on CLI arguments or config file
 fn load_or_build_config(matches: &clap::ArgMatches) -> Result<Config, Box<dyn std::error::Error>> {
-    if let Some(config_path) = matches.get_one::<String>("config") {
+    let config = if let Some(config_path) = matches.get_one::<String>("config") {
         Config::from_file(config_path)
     } else {
         build_config_from_cli_args(matches)
-    }
+    };
+    config.map_err(|e| PlexisortError::Config(e.to_string()).into())
 }
 
 // Build Config from CLI arguments
@@ -132,9 +455,33 @@ fn build_config_from_cli_args(matches: &clap::ArgMatches) -> Result<Config, Box<
     Ok(Config {
         source_directories,
         destination,
+        ..Config::default()
     })
 }
 
+// Combine the config file settings with their command-line overrides
+fn build_organize_options(matches: &clap::ArgMatches, config: &Config) -> OrganizeOptions {
+    let conflict_policy = matches.get_one::<String>("on-conflict")
+        .and_then(|name| ConflictPolicy::from_name(name))
+        .unwrap_or(config.on_conflict);
+
+    let transfer = matches.get_one::<String>("transfer")
+        .and_then(|name| TransferMode::from_name(name))
+        .unwrap_or(config.transfer);
+
+    OrganizeOptions { conflict_policy, transfer, ..OrganizeOptions::default() }
+}
+
+// Command-line include and exclude globs add to those of the config file
+fn build_path_filter(matches: &clap::ArgMatches, config: &Config) -> Result<PathFilter, Box<dyn std::error::Error>> {
+    let mut include = config.include.clone();
+    include.extend(matches.get_many::<String>("include").unwrap_or_default().cloned());
+    let mut exclude = config.exclude.clone();
+    exclude.extend(matches.get_many::<String>("exclude").unwrap_or_default().cloned());
+
+    Ok(PathFilter::new(&include, &exclude).map_err(PlexisortError::Config)?)
+}
+
 // Determine the processing mode based on CLI arguments
 fn determine_processing_mode(dry_run: bool) -> ProcessingMode {
     if dry_run {
@@ -0,0 +1,284 @@
+//! Reads the tags of audio files: ID3v2 in MP3s, Vorbis comments in FLAC and
+//! Ogg files, and the `ilst` atoms of MP4 audio (M4A).
+
+use std::fs::File;
+use std::path::Path;
+
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag, Value};
+use symphonia::core::probe::Hint;
+
+use super::Metadata;
+
+/// Longest tag value kept for a path component, in characters.
+const MAX_NAME_LENGTH: usize = 100;
+
+/// The tags of an audio file that decide where it is filed. Text values are
+/// already made safe to use as file and directory names.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AudioTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album_artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub encoder: Option<String>,
+    /// The release year.
+    pub year: Option<i32>,
+    pub track: Option<u32>,
+    pub disc: Option<u32>,
+    /// Whether the tags mark the file as a podcast episode.
+    pub podcast: bool,
+}
+
+impl AudioTags {
+    /// Takes the values of `tags` for the fields that are still empty, so that
+    /// tags read earlier win.
+    fn add(&mut self, tags: &[Tag]) {
+        for tag in tags {
+            let Some(key) = tag.std_key else {
+                continue;
+            };
+            let text = tag.value.to_string();
+            match key {
+                StandardTagKey::TrackTitle => set_name(&mut self.title, &text),
+                StandardTagKey::Artist => set_name(&mut self.artist, &text),
+                StandardTagKey::AlbumArtist => set_name(&mut self.album_artist, &text),
+                StandardTagKey::Album => set_name(&mut self.album, &text),
+                StandardTagKey::Genre => set_name(&mut self.genre, &text),
+                StandardTagKey::Encoder => set_name(&mut self.encoder, &text),
+                StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate => {
+                    self.year = self.year.or_else(|| parse_year(&text));
+                }
+                StandardTagKey::TrackNumber => self.track = self.track.or_else(|| parse_position(&text)),
+                StandardTagKey::DiscNumber => self.disc = self.disc.or_else(|| parse_position(&text)),
+                StandardTagKey::Podcast => self.podcast |= !matches!(tag.value, Value::Boolean(false)),
+                StandardTagKey::IdentPodcast | StandardTagKey::UrlPodcast | StandardTagKey::PodcastCategory => self.podcast = true,
+                _ => {}
+            }
+        }
+        if self.genre.as_deref().is_some_and(|genre| genre.eq_ignore_ascii_case("podcast") || genre.eq_ignore_ascii_case("podcasts")) {
+            self.podcast = true;
+        }
+    }
+
+    /// The artist the album is filed under: the album artist, or else the
+    /// track's artist.
+    pub fn album_artist(&self) -> Option<&str> {
+        self.album_artist.as_deref().or(self.artist.as_deref())
+    }
+}
+
+/// Reads the tags of an audio file, or returns `None` if it is not an audio
+/// file that can be read.
+pub fn read_audio_tags(path: &Path) -> Option<AudioTags> {
+    let file = File::open(path).ok()?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mut probed = symphonia::default::get_probe()
+        .format(&hint, stream, &FormatOptions::default(), &MetadataOptions::default())
+        .ok()?;
+
+    // Tags of the container itself come first, then tags in front of it such as ID3v2
+    let mut tags = AudioTags::default();
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        tags.add(revision.tags());
+    }
+    if let Some(revision) = probed.metadata.get().as_mut().and_then(|metadata| metadata.skip_to_latest()) {
+        tags.add(revision.tags());
+    }
+    Some(tags)
+}
+
+/// Collects the template fields of an audio file: `title`, `artist`,
+/// `albumartist` (the track's artist if there is none), `album`, `genre`,
+/// `track` and `disc`. The release year replaces the `year` of the file date,
+/// so that `{year}` in a music template means the year of the album.
+pub fn extract_audio_metadata(tags: &AudioTags) -> Metadata {
+    let mut metadata = Metadata::new();
+    let text_fields = [
+        ("title", tags.title.as_deref()),
+        ("artist", tags.artist.as_deref()),
+        ("albumartist", tags.album_artist()),
+        ("album", tags.album.as_deref()),
+        ("genre", tags.genre.as_deref()),
+    ];
+    for (name, value) in text_fields {
+        if let Some(value) = value {
+            metadata.insert(name.to_string(), value.to_string());
+        }
+    }
+    for (name, value) in [("track", tags.track), ("disc", tags.disc)] {
+        if let Some(value) = value {
+            metadata.insert(name.to_string(), value.to_string());
+        }
+    }
+    if let Some(year) = tags.year {
+        metadata.insert("year".to_string(), year.to_string());
+    }
+    metadata
+}
+
+fn set_name(field: &mut Option<String>, value: &str) {
+    if field.is_none() {
+        *field = Some(sanitize_name(value)).filter(|name| !name.is_empty());
+    }
+}
+
+/// Makes a tag value safe to use as a file or directory name on any common
+/// file system: characters that Windows forbids become `_`, runs of
+/// whitespace become one space, and leading dots and trailing dots and spaces
+/// are dropped. Long values are cut short.
+pub fn sanitize_name(value: &str) -> String {
+    let replaced: String = value
+        .chars()
+        .map(|c| if c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*') { '_' } else { c })
+        .collect();
+    let collapsed = replaced.split_whitespace().collect::<Vec<_>>().join(" ");
+    let shortened: String = collapsed.chars().take(MAX_NAME_LENGTH).collect();
+    shortened.trim_start_matches('.').trim_end_matches(['.', ' ']).trim_start().to_string()
+}
+
+/// Parses the year of a date tag such as `2019`, `2019-05-03` or
+/// `2019-05-03T10:00:00`.
+fn parse_year(text: &str) -> Option<i32> {
+    let digits: String = text.trim().chars().take_while(char::is_ascii_digit).collect();
+    (digits.len() == 4).then(|| digits.parse().ok()).flatten()
+}
+
+/// Parses a track or disc number, which ID3 writes as `3/12`.
+fn parse_position(text: &str) -> Option<u32> {
+    let number = text.split('/').next()?.trim().parse().ok()?;
+    (number > 0).then_some(number)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    /// A FLAC file of silence, holding `comments` as Vorbis comments.
+    fn flac_file(comments: &[&str]) -> Vec<u8> {
+        let mut stream_info = Vec::new();
+        stream_info.extend_from_slice(&192u16.to_be_bytes()); // Block sizes
+        stream_info.extend_from_slice(&192u16.to_be_bytes());
+        stream_info.extend_from_slice(&[0; 6]); // Frame sizes
+        // 44.1 kHz, stereo, 16 bits per sample, no sample count
+        stream_info.extend_from_slice(&[0x0A, 0xC4, 0x42, 0xF0, 0, 0, 0, 0]);
+        stream_info.extend_from_slice(&[0; 16]); // MD5 of the audio
+
+        let vendor = "plexisort";
+        let mut comment_block = (vendor.len() as u32).to_le_bytes().to_vec();
+        comment_block.extend_from_slice(vendor.as_bytes());
+        comment_block.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            comment_block.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            comment_block.extend_from_slice(comment.as_bytes());
+        }
+
+        let mut file = b"fLaC".to_vec();
+        file.push(0x00); // STREAMINFO
+        file.extend_from_slice(&(stream_info.len() as u32).to_be_bytes()[1..]);
+        file.extend_from_slice(&stream_info);
+        file.push(0x84); // The last block, VORBIS_COMMENT
+        file.extend_from_slice(&(comment_block.len() as u32).to_be_bytes()[1..]);
+        file.extend_from_slice(&comment_block);
+        for frame_number in 0..2 {
+            file.extend_from_slice(&flac_frame(frame_number));
+        }
+        file
+    }
+
+    /// A frame of 192 silent stereo samples, in constant subframes.
+    fn flac_frame(frame_number: u8) -> Vec<u8> {
+        // Sync code, 192 samples at 44.1 kHz, stereo at 16 bits per sample
+        let mut frame = vec![0xFF, 0xF8, 0x19, 0x18, frame_number];
+        frame.push(crc(&frame, 8, 0x07) as u8);
+        frame.extend_from_slice(&[0, 0, 0, 0, 0, 0]);
+        let crc16 = crc(&frame, 16, 0x8005) as u16;
+        frame.extend_from_slice(&crc16.to_be_bytes());
+        frame
+    }
+
+    /// The CRC that FLAC frames use, of `width` bits with the polynomial `poly`.
+    fn crc(bytes: &[u8], width: u32, poly: u32) -> u32 {
+        let top = 1 << (width - 1);
+        let mask = (1u32 << width) - 1;
+        bytes.iter().fold(0, |mut crc, byte| {
+            crc ^= (*byte as u32) << (width - 8);
+            for _ in 0..8 {
+                crc = if crc & top != 0 { (crc << 1) ^ poly } else { crc << 1 } & mask;
+            }
+            crc
+        })
+    }
+
+    /// An MP3 file with an ID3v2.3 tag holding the text `frames`, followed by
+    /// a few silent MPEG frames.
+    fn mp3_file(frames: &[(&[u8; 4], &str)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (id, text) in frames {
+            body.extend_from_slice(*id);
+            body.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+            body.extend_from_slice(&[0, 0, 0]); // Flags, then ISO-8859-1 text
+            body.extend_from_slice(text.as_bytes());
+        }
+        let size = body.len() as u32;
+        let synchsafe = [(size >> 21) as u8 & 0x7F, (size >> 14) as u8 & 0x7F, (size >> 7) as u8 & 0x7F, size as u8 & 0x7F];
+
+        let mut file = b"ID3\x03\x00\x00".to_vec();
+        file.extend_from_slice(&synchsafe);
+        file.extend_from_slice(&body);
+        // MPEG-1 Layer III, 128 kbit/s, 44.1 kHz: 417 bytes per frame
+        for _ in 0..4 {
+            file.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x64]);
+            file.extend_from_slice(&[0; 413]);
+        }
+        file
+    }
+
+    fn read(bytes: &[u8], name: &str) -> Option<AudioTags> {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        read_audio_tags(&path)
+    }
+
+    #[test]
+    fn test_reads_vorbis_comments() {
+        let flac = flac_file(&["TITLE=So What?", "ARTIST=Miles Davis", "ALBUM=Kind of Blue", "DATE=1959-08-17", "TRACKNUMBER=1", "DISCNUMBER=1/1"]);
+        let tags = read(&flac, "01.flac").unwrap();
+        assert_eq!(tags.title.as_deref(), Some("So What_"));
+        assert_eq!(tags.album_artist(), Some("Miles Davis"), "Without an album artist the artist must be used.");
+        assert_eq!((tags.year, tags.track, tags.disc), (Some(1959), Some(1), Some(1)));
+        assert!(!tags.podcast);
+
+        let metadata = extract_audio_metadata(&tags);
+        assert_eq!(metadata.get("albumartist").map(String::as_str), Some("Miles Davis"));
+        assert_eq!(metadata.get("year").map(String::as_str), Some("1959"));
+    }
+
+    #[test]
+    fn test_reads_id3v2_tags() {
+        let mp3 = mp3_file(&[(b"TIT2", "Episode 12: Rust"), (b"TALB", "Tech Talk"), (b"TCON", "Podcast"), (b"TRCK", "12/40"), (b"TYER", "2023")]);
+        let tags = read(&mp3, "episode.mp3").unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Episode 12_ Rust"));
+        assert_eq!(tags.album.as_deref(), Some("Tech Talk"));
+        assert_eq!((tags.year, tags.track), (Some(2023), Some(12)));
+        assert!(tags.podcast, "The Podcast genre must mark an episode.");
+
+        assert_eq!(read(b"plain text", "notes.mp3"), None);
+    }
+
+    #[test]
+    fn test_sanitize_name() {
+        assert_eq!(sanitize_name("AC/DC"), "AC_DC");
+        assert_eq!(sanitize_name("  What's   Going On?  "), "What's Going On_");
+        assert_eq!(sanitize_name("...Baby One More Time."), "Baby One More Time");
+        assert_eq!(sanitize_name(&"x".repeat(300)).len(), MAX_NAME_LENGTH);
+    }
+}
@@ -0,0 +1,354 @@
+//! Reads the EXIF data of images into typed values: the dates with their
+//! UTC offsets, the camera and lens, the GPS position, the orientation and
+//! the dimensions.
+
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use exif::{Exif, Field, In, Reader, Tag, Value};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use super::{isobmff, Metadata};
+
+/// Magic numbers of Olympus ORF and Panasonic RW2 raw files, which are TIFF
+/// files under another magic number.
+const TIFF_LIKE_MAGICS: &[&[u8; 4]] = &[b"IIRO", b"IIRS", b"MMOR", b"IIU\0"];
+/// The magic number of Fujifilm RAF raw files.
+const RAF_MAGIC: &[u8] = b"FUJIFILMCCD-RAW";
+
+/// Reads the EXIF data of a file, if it has any. Besides JPEG and TIFF this
+/// handles the other containers `kamadak-exif` knows, such as PNG, WebP,
+/// HEIF and AVIF, and the camera raw formats: the TIFF-based ones (CR2, NEF,
+/// ARW, DNG, PEF, SRW, ORF, RW2), Canon CR3 and Fujifilm RAF.
+pub fn read_exif(path: &Path) -> Option<Exif> {
+    let mut buf_reader = BufReader::new(File::open(path).ok()?);
+    let mut header = [0u8; 92];
+    let header_length = buf_reader.read(&mut header).ok()?;
+    let header = &header[..header_length];
+    buf_reader.rewind().ok()?;
+
+    if header.get(4..8) == Some(b"ftyp") && header.get(8..12) == Some(b"crx ") {
+        Reader::new().read_raw(isobmff::read_cr3_exif(&mut buf_reader)?).ok()
+    } else if TIFF_LIKE_MAGICS.iter().any(|magic| header.starts_with(*magic)) {
+        let mut tiff = Vec::new();
+        buf_reader.read_to_end(&mut tiff).ok()?;
+        // Only the magic number differs, so the TIFF one lets it be read
+        let magic: &[u8] = if tiff.starts_with(b"MM") { b"MM\0*" } else { b"II*\0" };
+        tiff[..4].copy_from_slice(magic);
+        Reader::new().read_raw(tiff).ok()
+    } else if header.starts_with(RAF_MAGIC) {
+        // RAF files embed a JPEG preview that carries the EXIF data
+        let offset = u32::from_be_bytes(header.get(84..88)?.try_into().ok()?) as u64;
+        let length = u32::from_be_bytes(header.get(88..92)?.try_into().ok()?) as u64;
+        let mut jpeg = Vec::new();
+        buf_reader.seek(SeekFrom::Start(offset)).ok()?;
+        buf_reader.take(length).read_to_end(&mut jpeg).ok()?;
+        Reader::new().read_from_container(&mut Cursor::new(jpeg)).ok()
+    } else {
+        Reader::new().read_from_container(&mut buf_reader).ok()
+    }
+}
+
+/// A date from one of the EXIF date tags. EXIF dates are in the local time of
+/// the camera; newer cameras and phones record its UTC offset, too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExifDateTime {
+    pub local: NaiveDateTime,
+    pub offset: Option<FixedOffset>,
+}
+
+impl ExifDateTime {
+    /// The date as a point in time, if the offset is known.
+    pub fn with_offset(&self) -> Option<DateTime<FixedOffset>> {
+        self.offset?.from_local_datetime(&self.local).single()
+    }
+}
+
+/// Where a photo was taken, in decimal degrees north and east, and meters
+/// above sea level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub altitude: Option<f64>,
+}
+
+/// The EXIF data of an image that is useful for filing it.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImageMetadata {
+    /// When the photo was taken (`DateTimeOriginal`).
+    pub taken: Option<ExifDateTime>,
+    /// When the image was stored digitally (`DateTimeDigitized`), which
+    /// differs from `taken` for scanned film.
+    pub digitized: Option<ExifDateTime>,
+    /// When the image was last changed (`DateTime`).
+    pub modified: Option<ExifDateTime>,
+    /// The time of the GPS fix, which is always in UTC.
+    pub gps_time: Option<DateTime<Utc>>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens_model: Option<String>,
+    pub software: Option<String>,
+    pub gps: Option<GpsPosition>,
+    /// How the image has to be turned to be shown upright, from 1 (as
+    /// stored) to 8, as defined by EXIF.
+    pub orientation: Option<u16>,
+    /// The size of the image as stored, before applying the orientation.
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+impl ImageMetadata {
+    pub fn from_exif(exif: &Exif) -> Self {
+        let field = |tag| exif.get_field(tag, In::PRIMARY);
+        let number = |tag| field(tag).and_then(|f| f.value.get_uint(0));
+
+        ImageMetadata {
+            taken: exif_date(exif, Tag::DateTimeOriginal, Tag::SubSecTimeOriginal, Tag::OffsetTimeOriginal),
+            digitized: exif_date(exif, Tag::DateTimeDigitized, Tag::SubSecTimeDigitized, Tag::OffsetTimeDigitized),
+            modified: exif_date(exif, Tag::DateTime, Tag::SubSecTime, Tag::OffsetTime),
+            gps_time: gps_time(exif),
+            make: field(Tag::Make).and_then(text),
+            model: field(Tag::Model).and_then(text),
+            lens_model: field(Tag::LensModel).and_then(text),
+            software: field(Tag::Software).and_then(text),
+            gps: gps_position(exif),
+            orientation: number(Tag::Orientation).and_then(|o| u16::try_from(o).ok()).filter(|o| (1..=8).contains(o)),
+            width: number(Tag::PixelXDimension).or_else(|| number(Tag::ImageWidth)),
+            height: number(Tag::PixelYDimension).or_else(|| number(Tag::ImageLength)),
+        }
+    }
+
+    /// The template fields of the image: `camera_make`, `camera_model`,
+    /// `lens_model`, `software`, `orientation`, `width`, `height`,
+    /// `resolution`, `gps_latitude`, `gps_longitude`, `gps_altitude`,
+    /// `utc_offset` (e.g. `+01:00`) and `utc_time` (e.g. `20230115T043005Z`),
+    /// for those the image has.
+    pub fn fields(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        let mut insert = |name: &str, value: Option<String>| {
+            if let Some(value) = value {
+                metadata.insert(name.to_string(), value);
+            }
+        };
+        insert("camera_make", self.make.clone());
+        insert("camera_model", self.model.clone());
+        insert("lens_model", self.lens_model.clone());
+        insert("software", self.software.clone());
+        insert("orientation", self.orientation.map(|o| o.to_string()));
+        insert("width", self.width.map(|w| w.to_string()));
+        insert("height", self.height.map(|h| h.to_string()));
+        insert("resolution", self.width.zip(self.height).map(|(w, h)| format!("{}x{}", w, h)));
+        insert("gps_latitude", self.gps.map(|gps| format!("{:.6}", gps.latitude)));
+        insert("gps_longitude", self.gps.map(|gps| format!("{:.6}", gps.longitude)));
+        insert("gps_altitude", self.gps.and_then(|gps| gps.altitude).map(|altitude| format!("{:.1}", altitude)));
+        insert("utc_offset", self.taken.and_then(|taken| taken.offset).map(|offset| offset.to_string()));
+        insert("utc_time", self.taken_utc().map(|time| time.format("%Y%m%dT%H%M%SZ").to_string()));
+        metadata
+    }
+
+    /// When the photo was taken in UTC, from the offset of the capture date
+    /// or else from the time of the GPS fix.
+    pub fn taken_utc(&self) -> Option<DateTime<Utc>> {
+        let from_offset = self.taken.and_then(|taken| taken.with_offset()).map(|time| time.with_timezone(&Utc));
+        from_offset.or(self.gps_time)
+    }
+}
+
+/// Reads the EXIF data of an image, or returns `None` if it has none.
+pub fn read_image_metadata(path: &Path) -> Option<ImageMetadata> {
+    read_exif(path).map(|exif| ImageMetadata::from_exif(&exif))
+}
+
+/// The first text value of a field, without padding.
+fn text(field: &Field) -> Option<String> {
+    match &field.value {
+        Value::Ascii(values) => {
+            let value = String::from_utf8_lossy(values.first()?).trim_matches(|c: char| c == '\0' || c.is_whitespace()).to_string();
+            Some(value).filter(|value| !value.is_empty())
+        }
+        _ => None,
+    }
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<&[u8]> {
+    match &exif.get_field(tag, In::PRIMARY)?.value {
+        Value::Ascii(values) => values.first().map(Vec::as_slice),
+        _ => None,
+    }
+}
+
+/// Parses one of the date tags together with the tags that hold its fraction
+/// of a second and its UTC offset, e.g. `SubSecTimeOriginal` and
+/// `OffsetTimeOriginal` for `DateTimeOriginal`.
+fn exif_date(exif: &Exif, date_tag: Tag, subsec_tag: Tag, offset_tag: Tag) -> Option<ExifDateTime> {
+    let mut date = exif::DateTime::from_ascii(ascii(exif, date_tag)?).ok()?;
+    if let Some(subsec) = ascii(exif, subsec_tag) {
+        let _ = date.parse_subsec(subsec);
+    }
+    if let Some(offset) = ascii(exif, offset_tag) {
+        let _ = date.parse_offset(offset);
+    }
+
+    let local = NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())?
+        .and_hms_nano_opt(date.hour.into(), date.minute.into(), date.second.into(), date.nanosecond.unwrap_or(0))?;
+    let offset = date.offset.and_then(|minutes| FixedOffset::east_opt(i32::from(minutes) * 60));
+    Some(ExifDateTime { local, offset })
+}
+
+fn gps_time(exif: &Exif) -> Option<DateTime<Utc>> {
+    let date = std::str::from_utf8(ascii(exif, Tag::GPSDateStamp)?).ok()?;
+    let date = NaiveDate::parse_from_str(date.trim(), "%Y:%m:%d").ok()?;
+    let time = match &exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)?.value {
+        Value::Rational(parts) if parts.len() == 3 => parts.iter().map(|part| part.to_f64()).collect::<Vec<_>>(),
+        _ => return None,
+    };
+    let seconds = time[0] * 3600.0 + time[1] * 60.0 + time[2];
+    if !(0.0..86_400.0).contains(&seconds) {
+        return None;
+    }
+    let date_time = date.and_hms_opt(0, 0, 0)? + chrono::Duration::milliseconds((seconds * 1000.0).round() as i64);
+    Some(Utc.from_utc_datetime(&date_time))
+}
+
+fn gps_position(exif: &Exif) -> Option<GpsPosition> {
+    let coordinate = |tag, reference_tag, negative: u8, limit: f64| {
+        let degrees = match &exif.get_field(tag, In::PRIMARY)?.value {
+            Value::Rational(parts) if parts.len() == 3 => parts[0].to_f64() + parts[1].to_f64() / 60.0 + parts[2].to_f64() / 3600.0,
+            _ => return None,
+        };
+        let sign = if ascii(exif, reference_tag)?.first() == Some(&negative) { -1.0 } else { 1.0 };
+        Some(sign * degrees).filter(|value| value.is_finite() && value.abs() <= limit)
+    };
+    let latitude = coordinate(Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S', 90.0)?;
+    let longitude = coordinate(Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W', 180.0)?;
+
+    let altitude = match &exif.get_field(Tag::GPSAltitude, In::PRIMARY).map(|f| &f.value) {
+        Some(Value::Rational(parts)) if !parts.is_empty() => Some(parts[0].to_f64()),
+        _ => None,
+    };
+    let below_sea_level = exif.get_field(Tag::GPSAltitudeRef, In::PRIMARY).and_then(|f| f.value.get_uint(0)) == Some(1);
+    let altitude = altitude.filter(|a| a.is_finite()).map(|a| if below_sea_level { -a } else { a });
+    Some(GpsPosition { latitude, longitude, altitude })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use exif::experimental::Writer;
+    use exif::Rational;
+    use crate::sniff::{detect_type, TypePrecedence};
+    use tempfile::tempdir;
+
+    fn ascii_field(tag: Tag, value: &str) -> Field {
+        Field { tag, ifd_num: In::PRIMARY, value: Value::Ascii(vec![value.as_bytes().to_vec()]) }
+    }
+
+    fn rationals(tag: Tag, values: &[(u32, u32)]) -> Field {
+        let value = Value::Rational(values.iter().map(|&(num, denom)| Rational { num, denom }).collect());
+        Field { tag, ifd_num: In::PRIMARY, value }
+    }
+
+    /// A TIFF file holding `fields`, which `read_exif` reads like a photo.
+    fn write_tiff(fields: &[Field]) -> Vec<u8> {
+        let mut writer = Writer::new();
+        for field in fields {
+            writer.push_field(field);
+        }
+        let mut tiff = Cursor::new(Vec::new());
+        writer.write(&mut tiff, false).unwrap();
+        tiff.into_inner()
+    }
+
+    #[test]
+    fn test_reads_typed_exif_fields() {
+        let fields = [
+            ascii_field(Tag::Make, "Apple"),
+            ascii_field(Tag::Model, "iPhone 14 Pro"),
+            ascii_field(Tag::LensModel, "iPhone 14 Pro back triple camera 6.86mm f/1.78"),
+            ascii_field(Tag::Software, "16.2"),
+            Field { tag: Tag::Orientation, ifd_num: In::PRIMARY, value: Value::Short(vec![6]) },
+            Field { tag: Tag::PixelXDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![4032]) },
+            Field { tag: Tag::PixelYDimension, ifd_num: In::PRIMARY, value: Value::Long(vec![3024]) },
+            ascii_field(Tag::DateTimeOriginal, "2023:01:14 23:30:05"),
+            ascii_field(Tag::SubSecTimeOriginal, "25"),
+            ascii_field(Tag::OffsetTimeOriginal, "-05:00"),
+            ascii_field(Tag::DateTimeDigitized, "2023:01:14 23:30:05"),
+            ascii_field(Tag::GPSLatitudeRef, "N"),
+            rationals(Tag::GPSLatitude, &[(40, 1), (41, 1), (2142, 100)]),
+            ascii_field(Tag::GPSLongitudeRef, "W"),
+            rationals(Tag::GPSLongitude, &[(74, 1), (2, 1), (4008, 100)]),
+            rationals(Tag::GPSAltitude, &[(105, 10)]),
+            ascii_field(Tag::GPSDateStamp, "2023:01:15"),
+            rationals(Tag::GPSTimeStamp, &[(4, 1), (30, 1), (4, 1)]),
+        ];
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("IMG_0001.tif");
+        std::fs::write(&path, write_tiff(&fields)).unwrap();
+
+        let image = read_image_metadata(&path).unwrap();
+        let taken = image.taken.unwrap();
+        assert_eq!(taken.local, NaiveDate::from_ymd_opt(2023, 1, 14).unwrap().and_hms_milli_opt(23, 30, 5, 250).unwrap());
+        assert_eq!(taken.with_offset().unwrap().with_timezone(&Utc), Utc.with_ymd_and_hms(2023, 1, 15, 4, 30, 5).unwrap() + chrono::Duration::milliseconds(250));
+        assert_eq!(image.digitized.unwrap().offset, None, "Offsets belong to their own date tag.");
+        assert_eq!(image.gps_time, Some(Utc.with_ymd_and_hms(2023, 1, 15, 4, 30, 4).unwrap()));
+        assert_eq!((image.orientation, image.width, image.height), (Some(6), Some(4032), Some(3024)));
+
+        let gps = image.gps.unwrap();
+        assert!((gps.latitude - 40.689283).abs() < 1e-5 && (gps.longitude + 74.044467).abs() < 1e-5);
+        assert_eq!(gps.altitude, Some(10.5));
+
+        let fields = image.fields();
+        for (name, value) in [("camera_make", "Apple"), ("camera_model", "iPhone 14 Pro"), ("software", "16.2"), ("resolution", "4032x3024"), ("gps_longitude", "-74.044467"), ("utc_offset", "-05:00"), ("utc_time", "20230115T043005Z")] {
+            assert_eq!(fields.get(name).map(String::as_str), Some(value), "field {}", name);
+        }
+    }
+
+    #[test]
+    fn test_invalid_values_are_left_out() {
+        let fields = [
+            ascii_field(Tag::DateTimeOriginal, "2023:02:30 10:00:00"),
+            ascii_field(Tag::Make, "   "),
+            ascii_field(Tag::GPSLatitudeRef, "N"),
+            rationals(Tag::GPSLatitude, &[(95, 1), (0, 1), (0, 1)]),
+            ascii_field(Tag::GPSLongitudeRef, "E"),
+            rationals(Tag::GPSLongitude, &[(10, 1), (0, 1), (0, 1)]),
+        ];
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("broken.tif");
+        std::fs::write(&path, write_tiff(&fields)).unwrap();
+
+        let image = read_image_metadata(&path).unwrap();
+        assert_eq!((image.taken, image.make, image.gps), (None, None, None));
+    }
+
+    #[test]
+    fn test_fixture_capture_dates() {
+        // Written by tests/fixtures/images/generate.py
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/images");
+        let expected = [
+            ("photo.jpg", "Apple", "2020-01-02 03:04:05"),
+            ("photo.heic", "Apple", "2021-04-05 10:11:12"),
+            ("photo.avif", "Google", "2022-06-07 08:09:10"),
+            ("photo.cr3", "Canon", "2023-03-03 13:14:15"),
+            ("photo.cr2", "Canon", "2019-07-08 09:10:11"),
+            ("photo.nef", "NIKON CORPORATION", "2018-02-03 04:05:06"),
+            ("photo.arw", "SONY", "2017-11-12 13:14:15"),
+            ("photo.dng", "Ricoh", "2016-05-06 07:08:09"),
+            ("photo.orf", "OLYMPUS CORPORATION", "2015-09-10 11:12:13"),
+            ("photo.rw2", "Panasonic", "2014-12-13 14:15:16"),
+            ("photo.raf", "FUJIFILM", "2013-10-11 12:13:14"),
+        ];
+        for (name, make, taken) in expected {
+            let path = fixtures.join(name);
+            let image = read_image_metadata(&path).unwrap_or_else(|| panic!("No EXIF data read from {}", name));
+            let taken = NaiveDateTime::parse_from_str(taken, "%Y-%m-%d %H:%M:%S").unwrap();
+            assert_eq!(image.taken.map(|date| date.local), Some(taken), "Wrong capture date of {}", name);
+            assert_eq!(image.make.as_deref(), Some(make), "Wrong camera make of {}", name);
+            for precedence in [TypePrecedence::Content, TypePrecedence::Extension] {
+                let mime = detect_type(&path, precedence).mime;
+                assert!(mime.starts_with("image/"), "{} is classified as {}", name, mime);
+            }
+        }
+    }
+}
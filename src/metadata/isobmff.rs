@@ -0,0 +1,133 @@
+//! Reads the boxes of ISO base media files (MP4, QuickTime and Canon CR3),
+//! and the EXIF data that CR3 raw files keep in them.
+
+use exif::experimental::Writer;
+use exif::{Context, Field, In, Tag, Value};
+use std::io::{Cursor, Read, Seek, SeekFrom};
+
+/// Box types that an MP4 or QuickTime file starts with.
+pub(super) const MP4_FIRST_BOXES: &[&[u8; 4]] = &[b"ftyp", b"moov", b"mdat", b"wide", b"free", b"skip"];
+
+/// The `uuid` box in which CR3 files keep their metadata.
+const CANON_METADATA_UUID: [u8; 16] = [0x85, 0xC0, 0xB6, 0x87, 0x82, 0x0F, 0x11, 0xE0, 0x81, 0x11, 0xF4, 0xCE, 0x46, 0x2B, 0x6A, 0x48];
+/// Largest IFD box read. EXIF data is usually a few dozen kilobytes.
+const MAX_EXIF_SIZE: u64 = 4 * 1024 * 1024;
+
+/// A box of an MP4 file, or an element of a Matroska file, given by the
+/// offsets of its contents.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Node<T> {
+    pub(super) kind: T,
+    pub(super) start: u64,
+    pub(super) end: u64,
+}
+
+/// Lists the boxes between the offsets `start` and `end`.
+pub(super) fn mp4_boxes<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Vec<Node<[u8; 4]>> {
+    let mut boxes = Vec::new();
+    let mut position = start;
+    while position + 8 <= end {
+        let Some(node) = mp4_box(reader, position, end) else {
+            break;
+        };
+        position = node.end;
+        boxes.push(node);
+    }
+    boxes
+}
+
+fn mp4_box<R: Read + Seek>(reader: &mut R, position: u64, end: u64) -> Option<Node<[u8; 4]>> {
+    reader.seek(SeekFrom::Start(position)).ok()?;
+    let size = read_u32(reader)? as u64;
+    let kind: [u8; 4] = read_array(reader)?;
+    let (header_size, size) = match size {
+        0 => (8, end - position), // The box extends to the end
+        1 => (16, read_u64(reader)?),
+        size => (8, size),
+    };
+    if size < header_size {
+        return None;
+    }
+    Some(Node { kind, start: position + header_size, end: position.checked_add(size)?.min(end) })
+}
+
+/// Finds the EXIF data of a Canon CR3 raw file and returns it as a TIFF
+/// structure for `exif::Reader::read_raw`. HEIF and AVIF images are left to
+/// `kamadak-exif`, which reads their EXIF item itself.
+pub fn read_cr3_exif<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let end = reader.seek(SeekFrom::End(0)).ok()?;
+    let top_level = mp4_boxes(reader, 0, end);
+    let ftyp = top_level.first().filter(|node| &node.kind == b"ftyp")?;
+    reader.seek(SeekFrom::Start(ftyp.start)).ok()?;
+    if &read_array::<_, 4>(reader)? != b"crx " {
+        return None;
+    }
+    let moov = top_level.iter().find(|node| &node.kind == b"moov")?;
+    cr3_exif(reader, moov)
+}
+
+/// CR3 files keep the IFDs of a TIFF file as separate TIFF structures in the
+/// `CMT1` (main IFD), `CMT2` (EXIF IFD) and `CMT4` (GPS IFD) boxes. They are
+/// merged into one TIFF structure, as other raw files have it.
+fn cr3_exif<R: Read + Seek>(reader: &mut R, moov: &Node<[u8; 4]>) -> Option<Vec<u8>> {
+    let uuid = mp4_boxes(reader, moov.start, moov.end).into_iter().find(|node| {
+        &node.kind == b"uuid" && reader.seek(SeekFrom::Start(node.start)).is_ok() && read_array(reader) == Some(CANON_METADATA_UUID)
+    })?;
+
+    let mut fields: Vec<Field> = Vec::new();
+    let mut little_endian = true;
+    for node in mp4_boxes(reader, uuid.start + 16, uuid.end) {
+        let context = match &node.kind {
+            b"CMT1" => Context::Tiff,
+            b"CMT2" => Context::Exif,
+            b"CMT4" => Context::Gps,
+            _ => continue,
+        };
+        let size = node.end.saturating_sub(node.start);
+        if size > MAX_EXIF_SIZE {
+            continue;
+        }
+        let mut data = Vec::new();
+        reader.seek(SeekFrom::Start(node.start)).ok()?;
+        reader.by_ref().take(size).read_to_end(&mut data).ok()?;
+        let Ok((parsed, parsed_little_endian)) = exif::parse_exif(&data) else {
+            continue;
+        };
+        if context == Context::Tiff {
+            little_endian = parsed_little_endian;
+        }
+        // Each box holds its IFD as the main IFD of its own TIFF structure
+        let pointers = [Tag::ExifIFDPointer, Tag::GPSInfoIFDPointer, Tag::InteropIFDPointer];
+        fields.extend(
+            parsed
+                .into_iter()
+                .filter(|field| field.ifd_num == In::PRIMARY && !pointers.contains(&field.tag) && !matches!(field.value, Value::Unknown(..)))
+                .map(|field| Field { tag: Tag(context, field.tag.number()), ..field }),
+        );
+    }
+    if fields.is_empty() {
+        return None;
+    }
+
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, little_endian).ok()?;
+    Some(tiff.into_inner())
+}
+
+pub(super) fn read_array<R: Read, const N: usize>(reader: &mut R) -> Option<[u8; N]> {
+    let mut bytes = [0u8; N];
+    reader.read_exact(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+pub(super) fn read_u32<R: Read>(reader: &mut R) -> Option<u32> {
+    read_array(reader).map(u32::from_be_bytes)
+}
+
+pub(super) fn read_u64<R: Read>(reader: &mut R) -> Option<u64> {
+    read_array(reader).map(u64::from_be_bytes)
+}
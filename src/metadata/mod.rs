@@ -0,0 +1,102 @@
+use chrono::{Datelike, NaiveDateTime};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::dates::DateResolver;
+use self::image::ImageMetadata;
+use self::video::VideoMetadata;
+
+pub mod audio;
+pub mod image;
+mod isobmff;
+pub mod video;
+
+/// Named metadata fields of a file, used to expand destination path templates.
+pub type Metadata = BTreeMap<String, String>;
+
+/// What a file records about itself in its contents, read once per file and
+/// shared by the date resolver and the fields of the file's media type.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Embedded {
+    /// The EXIF data of images.
+    pub image: Option<ImageMetadata>,
+    /// The container metadata of MP4, QuickTime and Matroska files.
+    pub video: Option<VideoMetadata>,
+}
+
+impl Embedded {
+    /// Reads what a file of type `mime` records. Only images are read for
+    /// EXIF data, and files that cannot have container metadata are not
+    /// opened for it.
+    pub fn read(path: &Path, mime: &str) -> Embedded {
+        Embedded {
+            image: mime.starts_with("image/").then(|| image::read_image_metadata(path)).flatten(),
+            video: video::is_container_type(mime).then(|| video::read_video_metadata(path)).flatten(),
+        }
+    }
+}
+
+/// Collects the fields every file has: name parts, size, and the date that
+/// `dates` resolves for it along with the source it came from.
+pub fn file_metadata(path: &Path, dates: &DateResolver, embedded: &Embedded) -> Metadata {
+    let mut metadata = Metadata::new();
+
+    if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
+        metadata.insert("filename".to_string(), file_name.to_string());
+    }
+    if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+        metadata.insert("stem".to_string(), stem.to_string());
+    }
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        metadata.insert("ext".to_string(), extension.to_lowercase());
+    }
+
+    if let Ok(file_metadata) = fs::metadata(path) {
+        metadata.insert("size".to_string(), file_metadata.len().to_string());
+    }
+    if let Some(resolved) = dates.resolve(path, embedded) {
+        insert_date(&mut metadata, &resolved.date);
+        metadata.insert("date_source".to_string(), resolved.source.name().to_string());
+    }
+
+    metadata
+}
+
+/// Sets the `year`, `month`, `day` and `month_name` fields from a date.
+pub fn insert_date(metadata: &mut Metadata, date: &NaiveDateTime) {
+    metadata.insert("year".to_string(), date.year().to_string());
+    metadata.insert("month".to_string(), date.month().to_string());
+    metadata.insert("day".to_string(), date.day().to_string());
+    metadata.insert("month_name".to_string(), date.format("%B").to_string());
+}
+
+/// The `{year}/{MM} - {month_name}` folder of the date in `metadata`, or
+/// `Unknown` if no date was found.
+pub fn date_folder(metadata: &Metadata) -> PathBuf {
+    let month = metadata.get("month").and_then(|month| month.parse::<u32>().ok());
+    match (metadata.get("year"), month, metadata.get("month_name")) {
+        (Some(year), Some(month), Some(month_name)) => PathBuf::from(year).join(format!("{:02} - {}", month, month_name)),
+        _ => PathBuf::from("Unknown"),
+    }
+}
+
+/// The fields a file has because of its type: those of images, videos or
+/// audio files, depending on the top-level MIME type.
+pub fn media_metadata(path: &Path, mime: &str, embedded: &Embedded) -> Metadata {
+    match mime.split('/').next() {
+        Some("image") => embedded.image.as_ref().map(ImageMetadata::fields).unwrap_or_default(),
+        Some("video") => embedded.video.as_ref().map(VideoMetadata::fields).unwrap_or_default(),
+        Some("audio") => audio::extract_audio_metadata(&audio::read_audio_tags(path).unwrap_or_default()),
+        _ => Metadata::new(),
+    }
+}
+
+/// Every field known for `path`, whichever processor it would go to: those of
+/// `file_metadata` and those of its media type, as given by `mime`.
+pub fn full_metadata(path: &Path, dates: &DateResolver, mime: &str) -> Metadata {
+    let embedded = Embedded::read(path, mime);
+    let mut metadata = file_metadata(path, dates, &embedded);
+    metadata.extend(media_metadata(path, mime, &embedded));
+    metadata
+}
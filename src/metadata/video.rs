@@ -0,0 +1,470 @@
+//! Reads the recording date, duration and resolution of MP4, QuickTime and
+//! Matroska (MKV, WebM) files from their container metadata.
+
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use super::isobmff::{mp4_boxes, read_array, read_u32, read_u64, Node, MP4_FIRST_BOXES};
+use super::Metadata;
+
+/// Seconds from 1904-01-01, the epoch of MP4 and QuickTime times, to 1970-01-01.
+const MP4_EPOCH_OFFSET: i64 = 2_082_844_800;
+/// Seconds from 1970-01-01 to 2001-01-01, the epoch of Matroska dates.
+const MATROSKA_EPOCH: i64 = 978_307_200;
+
+/// The QuickTime metadata key that iPhones and many cameras store the local
+/// recording time in, with its UTC offset.
+const QUICKTIME_CREATION_DATE: &[u8] = b"com.apple.quicktime.creationdate";
+
+/// Magic number of EBML, the format Matroska is built on.
+const EBML_MAGIC: [u8; 4] = [0x1A, 0x45, 0xDF, 0xA3];
+const MKV_SEGMENT: u32 = 0x1853_8067;
+const MKV_INFO: u32 = 0x1549_A966;
+const MKV_TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const MKV_DURATION: u32 = 0x4489;
+const MKV_DATE_UTC: u32 = 0x4461;
+const MKV_TRACKS: u32 = 0x1654_AE6B;
+const MKV_TRACK_ENTRY: u32 = 0xAE;
+const MKV_VIDEO: u32 = 0xE0;
+const MKV_PIXEL_WIDTH: u32 = 0xB0;
+const MKV_PIXEL_HEIGHT: u32 = 0xBA;
+const MKV_CLUSTER: u32 = 0x1F43_B675;
+
+/// What the container of a video says about the recording.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VideoMetadata {
+    /// When the recording was made, in local time.
+    pub created: Option<NaiveDateTime>,
+    pub duration: Option<Duration>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+}
+
+/// Types whose files may be MP4, QuickTime or Matroska containers besides
+/// videos: recordings such as voice memos (`.m4a`) and Matroska audio.
+const CONTAINER_AUDIO_TYPES: &[&str] = &["audio/mp4", "audio/m4a", "audio/m4b", "audio/x-m4a", "audio/x-matroska", "audio/webm"];
+
+impl VideoMetadata {
+    /// The template fields of the video: `duration` in whole seconds,
+    /// `width`, `height` and `resolution` (e.g. `1920x1080`), for those the
+    /// container has. The recording date is left to `DateResolver`.
+    pub fn fields(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        if let Some(duration) = self.duration {
+            metadata.insert("duration".to_string(), duration.as_secs_f64().round().to_string());
+        }
+        if let (Some(width), Some(height)) = (self.width, self.height) {
+            metadata.insert("width".to_string(), width.to_string());
+            metadata.insert("height".to_string(), height.to_string());
+            metadata.insert("resolution".to_string(), format!("{}x{}", width, height));
+        }
+        metadata
+    }
+}
+
+/// Whether files of type `mime` can have container metadata, i.e. whether
+/// `read_video_metadata` is worth trying for them.
+pub fn is_container_type(mime: &str) -> bool {
+    mime.starts_with("video/") || CONTAINER_AUDIO_TYPES.contains(&mime)
+}
+
+/// Reads the container metadata of a video, or returns `None` if the file is
+/// neither an MP4/QuickTime nor a Matroska file.
+pub fn read_video_metadata(path: &Path) -> Option<VideoMetadata> {
+    let mut reader = BufReader::new(File::open(path).ok()?);
+    let end = reader.get_ref().metadata().ok()?.len();
+
+    let mut magic = [0u8; 8];
+    reader.read_exact(&mut magic).ok()?;
+    if magic[..4] == EBML_MAGIC {
+        read_matroska(&mut reader, end)
+    } else if MP4_FIRST_BOXES.iter().any(|kind| magic[4..] == kind[..]) {
+        read_mp4(&mut reader, end)
+    } else {
+        None
+    }
+}
+
+/// Reads the `moov` box. The creation date is taken from the QuickTime
+/// metadata if present, since it is in the local time of the recording,
+/// and otherwise from the movie or the first track header, which are in UTC.
+fn read_mp4<R: Read + Seek>(reader: &mut R, end: u64) -> Option<VideoMetadata> {
+    let moov = mp4_boxes(reader, 0, end).into_iter().find(|node| &node.kind == b"moov")?;
+    let mut video = VideoMetadata::default();
+    let mut header_created = None;
+    let mut track_created = None;
+
+    for node in mp4_boxes(reader, moov.start, moov.end) {
+        match &node.kind {
+            b"mvhd" => {
+                if let Some((created, duration)) = read_mvhd(reader, node) {
+                    header_created = created;
+                    video.duration = duration;
+                }
+            }
+            b"trak" => {
+                let Some(tkhd) = mp4_boxes(reader, node.start, node.end).into_iter().find(|child| &child.kind == b"tkhd") else {
+                    continue;
+                };
+                if let Some((created, width, height)) = read_tkhd(reader, tkhd) {
+                    track_created = track_created.or(created);
+                    // Audio tracks have no size
+                    if video.width.is_none() && width > 0 && height > 0 {
+                        video.width = Some(width);
+                        video.height = Some(height);
+                    }
+                }
+            }
+            b"meta" => video.created = quicktime_creation_date(reader, node),
+            _ => {}
+        }
+    }
+
+    video.created = video.created.or(header_created).or(track_created);
+    Some(video)
+}
+
+/// Reads the creation time and the duration from a movie header. Headers cut
+/// short after the creation time still give the date.
+fn read_mvhd<R: Read + Seek>(reader: &mut R, mvhd: Node<[u8; 4]>) -> Option<(Option<NaiveDateTime>, Option<Duration>)> {
+    reader.seek(SeekFrom::Start(mvhd.start)).ok()?;
+    let long_times = read_u32(reader)? >> 24 == 1;
+    let read_time = |reader: &mut R| if long_times { read_u64(reader) } else { read_u32(reader).map(u64::from) };
+    let created = read_time(reader)?;
+
+    let duration = (|| {
+        read_time(reader)?; // Modification time
+        let timescale = read_u32(reader)?;
+        let duration = read_time(reader)?;
+        let unknown = if long_times { u64::MAX } else { u32::MAX as u64 };
+        if timescale == 0 || duration == unknown {
+            return None;
+        }
+        Duration::try_from_secs_f64(duration as f64 / timescale as f64).ok()
+    })();
+    Some((mp4_time(created), duration))
+}
+
+/// Reads the creation time and the display size from a track header.
+fn read_tkhd<R: Read + Seek>(reader: &mut R, tkhd: Node<[u8; 4]>) -> Option<(Option<NaiveDateTime>, u32, u32)> {
+    reader.seek(SeekFrom::Start(tkhd.start)).ok()?;
+    let created = match read_u32(reader)? >> 24 {
+        0 => {
+            let created = read_u32(reader)? as u64;
+            let _: [u8; 16] = read_array(reader)?; // Modification time, track ID, reserved, duration
+            created
+        }
+        _ => {
+            let created = read_u64(reader)?;
+            let _: [u8; 24] = read_array(reader)?;
+            created
+        }
+    };
+    let _: [u8; 52] = read_array(reader)?; // Reserved, layer, group, volume and the matrix
+    // The size is a 16.16 fixed point number
+    let width = read_u32(reader)? >> 16;
+    let height = read_u32(reader)? >> 16;
+    Some((mp4_time(created), width, height))
+}
+
+/// Looks up `com.apple.quicktime.creationdate` in a QuickTime `meta` box,
+/// whose `keys` box names the entries of its `ilst` box by index.
+fn quicktime_creation_date<R: Read + Seek>(reader: &mut R, meta: Node<[u8; 4]>) -> Option<NaiveDateTime> {
+    // Unlike the MP4 one, the QuickTime meta box has no version and flags
+    reader.seek(SeekFrom::Start(meta.start)).ok()?;
+    let probe: [u8; 8] = read_array(reader)?;
+    let start = if &probe[4..] == b"hdlr" { meta.start } else { meta.start + 4 };
+    let children = mp4_boxes(reader, start, meta.end);
+    let keys = children.iter().find(|node| &node.kind == b"keys")?;
+    let items = children.iter().find(|node| &node.kind == b"ilst")?;
+
+    reader.seek(SeekFrom::Start(keys.start + 4)).ok()?;
+    let count = read_u32(reader)?;
+    let mut index = None;
+    for key_index in 1..=count {
+        let size = read_u32(reader)? as usize;
+        let _namespace: [u8; 4] = read_array(reader)?;
+        if !(8..=256).contains(&size) {
+            return None;
+        }
+        let mut name = vec![0u8; size - 8];
+        reader.read_exact(&mut name).ok()?;
+        if name == QUICKTIME_CREATION_DATE {
+            index = Some(key_index);
+            break;
+        }
+    }
+
+    let index = index?.to_be_bytes();
+    let item = mp4_boxes(reader, items.start, items.end).into_iter().find(|node| node.kind == index)?;
+    let data = mp4_boxes(reader, item.start, item.end).into_iter().find(|node| &node.kind == b"data")?;
+    let length = data.end.checked_sub(data.start + 8)?;
+    if length > 64 {
+        return None;
+    }
+    reader.seek(SeekFrom::Start(data.start + 8)).ok()?; // Skip the type and the locale
+    let mut value = vec![0u8; length as usize];
+    reader.read_exact(&mut value).ok()?;
+    parse_quicktime_date(std::str::from_utf8(&value).ok()?)
+}
+
+/// Parses a QuickTime date such as `2023-01-14T10:15:30+0100` to the local
+/// time of the recording, which is what cameras put in EXIF dates as well.
+fn parse_quicktime_date(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim_end_matches('\0').trim();
+    DateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%z")
+        .map(|date| date.naive_local())
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+}
+
+/// Converts a time in seconds since 1904 in UTC to local time. Zero means the
+/// time was not set.
+fn mp4_time(seconds: u64) -> Option<NaiveDateTime> {
+    if seconds == 0 {
+        return None;
+    }
+    let time = DateTime::<Utc>::from_timestamp(i64::try_from(seconds).ok()? - MP4_EPOCH_OFFSET, 0)?;
+    Some(time.with_timezone(&Local).naive_local())
+}
+
+/// Reads the `Info` and `Tracks` elements of a Matroska segment.
+fn read_matroska<R: Read + Seek>(reader: &mut R, end: u64) -> Option<VideoMetadata> {
+    let segment = ebml_elements(reader, 0, end).into_iter().find(|element| element.kind == MKV_SEGMENT)?;
+    let mut video = VideoMetadata::default();
+
+    for element in ebml_elements(reader, segment.start, segment.end) {
+        match element.kind {
+            MKV_INFO => {
+                let mut timecode_scale = 1_000_000; // Nanoseconds per tick, by default a millisecond
+                let mut duration = None;
+                for child in ebml_elements(reader, element.start, element.end) {
+                    match child.kind {
+                        MKV_TIMECODE_SCALE => timecode_scale = read_ebml_uint(reader, child)?,
+                        MKV_DURATION => duration = read_ebml_float(reader, child),
+                        MKV_DATE_UTC => {
+                            let nanoseconds = read_ebml_uint(reader, child)? as i64;
+                            let date = DateTime::<Utc>::from_timestamp(MATROSKA_EPOCH + nanoseconds.div_euclid(1_000_000_000), 0);
+                            video.created = date.map(|date| date.with_timezone(&Local).naive_local());
+                        }
+                        _ => {}
+                    }
+                }
+                video.duration = duration
+                    .filter(|ticks| ticks.is_finite() && *ticks >= 0.0)
+                    .and_then(|ticks| Duration::try_from_secs_f64(ticks * timecode_scale as f64 / 1e9).ok());
+            }
+            MKV_TRACKS => {
+                for entry in ebml_elements(reader, element.start, element.end).into_iter().filter(|e| e.kind == MKV_TRACK_ENTRY) {
+                    let Some(settings) = ebml_elements(reader, entry.start, entry.end).into_iter().find(|e| e.kind == MKV_VIDEO) else {
+                        continue;
+                    };
+                    for child in ebml_elements(reader, settings.start, settings.end) {
+                        match child.kind {
+                            MKV_PIXEL_WIDTH => video.width = read_ebml_uint(reader, child).and_then(|w| u32::try_from(w).ok()),
+                            MKV_PIXEL_HEIGHT => video.height = read_ebml_uint(reader, child).and_then(|h| u32::try_from(h).ok()),
+                            _ => {}
+                        }
+                    }
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    Some(video)
+}
+
+/// Lists the EBML elements between the offsets `start` and `end`. Listing
+/// stops at the first cluster, since the elements of interest come before
+/// the clusters that hold the frames.
+fn ebml_elements<R: Read + Seek>(reader: &mut R, start: u64, end: u64) -> Vec<Node<u32>> {
+    let mut elements = Vec::new();
+    let mut position = start;
+    while position < end {
+        let Some(element) = ebml_element(reader, position, end) else {
+            break;
+        };
+        if element.kind == MKV_CLUSTER {
+            break;
+        }
+        position = element.end;
+        elements.push(element);
+    }
+    elements
+}
+
+fn ebml_element<R: Read + Seek>(reader: &mut R, position: u64, end: u64) -> Option<Node<u32>> {
+    reader.seek(SeekFrom::Start(position)).ok()?;
+    // IDs keep their length marker, sizes drop it
+    let (kind, id_length) = read_vint(reader, 4, true)?;
+    let (size, size_length) = read_vint(reader, 8, false)?;
+    let start = position + id_length + size_length;
+    let unknown_size = size == (1 << (7 * size_length)) - 1;
+    let element_end = if unknown_size { end } else { start.checked_add(size)?.min(end) };
+    Some(Node { kind: kind as u32, start, end: element_end })
+}
+
+/// Reads a variable length integer of at most `max_length` bytes, returning
+/// it with its length.
+fn read_vint<R: Read>(reader: &mut R, max_length: u32, keep_marker: bool) -> Option<(u64, u64)> {
+    let [first] = read_array(reader)?;
+    let length = first.leading_zeros() + 1;
+    if length > max_length {
+        return None;
+    }
+    let mut value = if keep_marker { first as u64 } else { (first & (0xFF >> length)) as u64 };
+    for _ in 1..length {
+        let [byte] = read_array(reader)?;
+        value = (value << 8) | byte as u64;
+    }
+    Some((value, length as u64))
+}
+
+fn read_ebml_uint<R: Read + Seek>(reader: &mut R, element: Node<u32>) -> Option<u64> {
+    let length = element.end.saturating_sub(element.start);
+    if length > 8 {
+        return None;
+    }
+    reader.seek(SeekFrom::Start(element.start)).ok()?;
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes[8 - length as usize..]).ok()?;
+    Some(u64::from_be_bytes(bytes))
+}
+
+fn read_ebml_float<R: Read + Seek>(reader: &mut R, element: Node<u32>) -> Option<f64> {
+    reader.seek(SeekFrom::Start(element.start)).ok()?;
+    match element.end.saturating_sub(element.start) {
+        4 => Some(f32::from_be_bytes(read_array(reader)?) as f64),
+        8 => Some(f64::from_be_bytes(read_array(reader)?)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use tempfile::tempdir;
+
+    /// An MP4 box of `kind` holding `contents`.
+    fn mp4_atom(kind: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut atom = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(kind);
+        atom.extend_from_slice(contents);
+        atom
+    }
+
+    /// A Matroska element with a one byte size.
+    fn mkv_element(id: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut element = id.to_vec();
+        element.push(0x80 | contents.len() as u8);
+        element.extend_from_slice(contents);
+        element
+    }
+
+    fn phone_clip() -> Vec<u8> {
+        let created = (Utc.with_ymd_and_hms(2023, 1, 14, 9, 15, 30).unwrap().timestamp() + MP4_EPOCH_OFFSET) as u32;
+        let mut mvhd = vec![0u8; 4];
+        mvhd.extend_from_slice(&created.to_be_bytes());
+        mvhd.extend_from_slice(&created.to_be_bytes());
+        mvhd.extend_from_slice(&600u32.to_be_bytes()); // Timescale
+        mvhd.extend_from_slice(&(600u32 * 93 + 200).to_be_bytes()); // 93.33 seconds
+        mvhd.extend_from_slice(&[0; 80]);
+
+        let mut tkhd = vec![0u8; 4];
+        tkhd.extend_from_slice(&created.to_be_bytes());
+        tkhd.extend_from_slice(&[0; 16 + 52]);
+        tkhd.extend_from_slice(&(1920u32 << 16).to_be_bytes());
+        tkhd.extend_from_slice(&(1080u32 << 16).to_be_bytes());
+
+        let key = mp4_atom(b"mdta", QUICKTIME_CREATION_DATE);
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 1];
+        keys.extend_from_slice(&key);
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(b"2023-01-14T10:15:30+0100");
+        let item = mp4_atom(&1u32.to_be_bytes(), &mp4_atom(b"data", &data));
+        let meta = [mp4_atom(b"hdlr", &[0; 25]), mp4_atom(b"keys", &keys), mp4_atom(b"ilst", &item)].concat();
+
+        let moov = [mp4_atom(b"mvhd", &mvhd), mp4_atom(b"trak", &mp4_atom(b"tkhd", &tkhd)), mp4_atom(b"meta", &meta)].concat();
+        [mp4_atom(b"ftyp", b"qt  \0\0\0\0"), mp4_atom(b"moov", &moov), mp4_atom(b"mdat", &[0; 16])].concat()
+    }
+
+    fn read(bytes: &[u8], name: &str) -> Option<VideoMetadata> {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join(name);
+        std::fs::write(&path, bytes).unwrap();
+        read_video_metadata(&path)
+    }
+
+    #[test]
+    fn test_reads_quicktime_metadata() {
+        let video = read(&phone_clip(), "IMG_0001.MOV").unwrap();
+        let local_recording_time = NaiveDateTime::parse_from_str("2023-01-14 10:15:30", "%Y-%m-%d %H:%M:%S").unwrap();
+        assert_eq!(video.created, Some(local_recording_time), "The QuickTime creation date must win over the movie header.");
+        assert_eq!(video.duration.map(|d| d.as_secs()), Some(93));
+        assert_eq!((video.width, video.height), (Some(1920), Some(1080)));
+
+        let metadata = video.fields();
+        assert_eq!(metadata.get("resolution").map(String::as_str), Some("1920x1080"));
+        assert_eq!(metadata.get("duration").map(String::as_str), Some("93"));
+    }
+
+    #[test]
+    fn test_reads_matroska_metadata() {
+        let created = Utc.with_ymd_and_hms(2022, 7, 1, 18, 0, 0).unwrap();
+        let nanoseconds = (created.timestamp() - MATROSKA_EPOCH) * 1_000_000_000;
+        let info = [
+            mkv_element(&[0x2A, 0xD7, 0xB1], &1_000_000u32.to_be_bytes()),
+            mkv_element(&[0x44, 0x89], &12_500f64.to_be_bytes()),
+            mkv_element(&[0x44, 0x61], &nanoseconds.to_be_bytes()),
+        ]
+        .concat();
+        let video = [mkv_element(&[0xB0], &1280u16.to_be_bytes()), mkv_element(&[0xBA], &720u16.to_be_bytes())].concat();
+        let audio_track = mkv_element(&[0xAE], &mkv_element(&[0xD7], &[1]));
+        let video_track = mkv_element(&[0xAE], &mkv_element(&[0xE0], &video));
+        let tracks = mkv_element(&[0x16, 0x54, 0xAE, 0x6B], &[audio_track, video_track].concat());
+        let cluster = mkv_element(&[0x1F, 0x43, 0xB6, 0x75], &[0; 8]);
+        let segment = [mkv_element(&[0x15, 0x49, 0xA9, 0x66], &info), tracks, cluster].concat();
+        // The segment has an unknown size, as in streamed files
+        let file = [mkv_element(&EBML_MAGIC, &[0x42, 0x82, 0x84, b'w', b'e', b'b', b'm']), vec![0x18, 0x53, 0x80, 0x67, 0xFF], segment].concat();
+
+        let video = read(&file, "clip.webm").unwrap();
+        assert_eq!(video.created, Some(created.with_timezone(&Local).naive_local()));
+        assert_eq!(video.duration, Some(Duration::from_millis(12_500)));
+        assert_eq!((video.width, video.height), (Some(1280), Some(720)));
+    }
+
+    #[test]
+    fn test_malformed_durations_are_ignored() {
+        // A movie header claiming a duration of half a trillion years
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend_from_slice(&[0; 16]);
+        mvhd.extend_from_slice(&1u32.to_be_bytes());
+        mvhd.extend_from_slice(&(u64::MAX - 1).to_be_bytes());
+        let file = [mp4_atom(b"ftyp", b"isom\0\0\0\0"), mp4_atom(b"moov", &mp4_atom(b"mvhd", &mvhd))].concat();
+        assert_eq!(read(&file, "clip.mp4").unwrap().duration, None);
+
+        let info = mkv_element(&[0x44, 0x89], &f64::MAX.to_be_bytes());
+        let segment = mkv_element(&[0x15, 0x49, 0xA9, 0x66], &info);
+        let file = [mkv_element(&EBML_MAGIC, &[0x42, 0x82, 0x84, b'w', b'e', b'b', b'm']), vec![0x18, 0x53, 0x80, 0x67, 0xFF], segment].concat();
+        assert_eq!(read(&file, "clip.mkv").map(|video| video.duration), Some(None));
+
+        // An element whose header runs past the end of its parent
+        let cut_short = Node { kind: MKV_DURATION, start: 12, end: 8 };
+        assert_eq!(read_ebml_float(&mut std::io::Cursor::new([0u8; 16]), cut_short), None);
+        assert_eq!(read_ebml_uint(&mut std::io::Cursor::new([0u8; 16]), cut_short), Some(0));
+    }
+
+    #[test]
+    fn test_other_files_are_not_videos() {
+        assert_eq!(read(b"\xFF\xD8\xFF\xE0 not a movie", "photo.jpg"), None);
+        assert_eq!(read(b"plain text", "notes.txt"), None);
+
+        assert!(is_container_type("video/quicktime") && is_container_type("audio/m4a"));
+        assert!(!is_container_type("audio/mpeg") && !is_container_type("image/heic"));
+    }
+}
@@ -1,129 +1,259 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Error, ErrorKind};
+use std::io::{Error, ErrorKind};
 // organizer.rs
-use serde_json::Value;
 use std::collections::HashSet;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::{fs, io};
 use log::{debug, error, info, warn};
 
+use crate::conflict::{free_path, resolve, ConflictPolicy, Resolution};
+use crate::journal::{
+    read_journal, temporary_path, EntryState, Journal, JournalEntry, COPY_ACTION, COPY_SUFFIX, DROP_DUPLICATE_ACTION, HARDLINK_ACTION,
+    HARDLINK_DUPLICATE_ACTION, MOVE_ACTION, REFLINK_ACTION, REPLACED_SUFFIX, SYMLINK_ACTION,
+};
 use crate::processing_mode::ProcessingMode;
+use crate::transfer::{move_file, transfer_file, TransferMode};
+
+/// Settings that decide how `organize_file` places files at their destination.
+#[derive(Debug, Clone, Default)]
+pub struct OrganizeOptions {
+    pub conflict_policy: ConflictPolicy,
+    pub journal: Option<Arc<Journal>>, // Journal of the current session; nothing is recorded if `None`
+    pub transfer: TransferMode, // Whether files are moved, copied or linked to their destination
+}
+
+/// What `organize_file` did with a file, or would do in `DryRun` mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// The file was moved, copied or linked to this path.
+    Transferred(PathBuf),
+    /// The file was removed as an exact duplicate of this file.
+    DroppedDuplicate(PathBuf),
+    /// The file was replaced by a hard link to this identical file.
+    HardLinked(PathBuf),
+    /// The file was left alone for this reason.
+    Skipped(String),
+}
 
-pub fn organize_file(source_path: &Path, destination_path: &Path, mode: &mut ProcessingMode) -> Result<(), io::Error> {
+/// Moves `source_path` to `destination_path`, the full path of the file at its
+/// destination. In `DryRun` mode the move is only recorded in the virtual
+/// directory. Both modes apply the configured conflict policy, so a dry run
+/// reports the same collisions a live run would hit.
+pub fn organize_file(source_path: &Path, destination_path: &Path, mode: &mut ProcessingMode, options: &OrganizeOptions) -> Result<Outcome, io::Error> {
     match mode {
         ProcessingMode::DryRun(virtual_dir) => {
-            let mut parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-            if let Some(file_name) = source_path.file_name().and_then(|n| n.to_str()) {
-                parts.push(file_name.to_string());
+            let parts = path_parts(destination_path);
+            let existing = if destination_path.exists() {
+                Some(destination_path.to_path_buf())
+            } else {
+                virtual_dir.source_of(&parts).map(Path::to_path_buf)
+            };
+            let is_taken = |path: &Path| path.exists() || virtual_dir.contains_file(&path_parts(path));
+            let resolution = resolve(options.conflict_policy, source_path, destination_path, existing.as_deref(), &is_taken)?;
+
+            match resolution {
+                Resolution::Move(target) => {
+                    if target != destination_path {
+                        println!("Conflict: {} already exists, {} would be renamed to {}", destination_path.display(), source_path.display(), target.display());
+                    } else if existing.is_some() {
+                        println!("Conflict: {} already exists and would be replaced by {}", destination_path.display(), source_path.display());
+                    }
+                    debug!("Would {} {} to {}", options.transfer.action(), source_path.display(), target.display());
+                    virtual_dir.add_file(&path_parts(&target), source_path);
+                    Ok(Outcome::Transferred(target))
+                }
+                Resolution::Skip(reason) => {
+                    println!("Conflict: {} would be skipped, {}: {}", source_path.display(), reason, destination_path.display());
+                    Ok(Outcome::Skipped(reason))
+                }
+                Resolution::DropDuplicate(existing) => {
+                    println!("Conflict: {} is a duplicate of {} and would be removed", source_path.display(), existing.display());
+                    Ok(Outcome::DroppedDuplicate(existing))
+                }
             }
-            virtual_dir.add_path(&parts);
-            Ok(())
         }
         ProcessingMode::Live => {
-            fs::rename(source_path, destination_path).map_err(|e| {
-                println!("Failed to move file from {} to {}: {}", source_path.display(), destination_path.display(), e);
-                e
-            })?;
-            debug!("Successfully moved file from {} to {}", source_path.display(), destination_path.display());
-            log_move_operation(source_path, destination_path).map_err(|log_err| {
-                eprintln!("Failed to log the move operation: {}", log_err);
-                log_err
-            })
+            if let Some(parent_dir) = destination_path.parent() {
+                fs::create_dir_all(parent_dir)?;
+            }
+            let existing = destination_path.exists().then_some(destination_path);
+            let is_taken = |path: &Path| path.exists();
+            let resolution = resolve(options.conflict_policy, source_path, destination_path, existing, &is_taken)?;
+
+            match resolution {
+                Resolution::Move(target) => {
+                    let transfer = options.transfer;
+                    if existing.is_some() && target == destination_path {
+                        info!("{} already exists, replacing it with {}", destination_path.display(), source_path.display());
+                        replace_file(options, source_path, &target)?;
+                    } else {
+                        if existing.is_some() {
+                            info!("{} already exists, moving {} to {}", destination_path.display(), source_path.display(), target.display());
+                        }
+                        journaled(options, transfer.action(), source_path, &target, || transfer_file(transfer, source_path, &target))?;
+                    }
+                    debug!("Successfully transferred file from {} to {} ({})", source_path.display(), target.display(), transfer.action());
+                    Ok(Outcome::Transferred(target))
+                }
+                Resolution::Skip(reason) => {
+                    warn!("Skipping {}, {}: {}", source_path.display(), reason, destination_path.display());
+                    Ok(Outcome::Skipped(reason))
+                }
+                Resolution::DropDuplicate(existing) if !options.transfer.removes_source() => {
+                    info!("{} is already at {}, nothing to {}", source_path.display(), existing.display(), options.transfer.action());
+                    Ok(Outcome::Skipped(format!("already at {}", existing.display())))
+                }
+                Resolution::DropDuplicate(existing) => {
+                    journaled(options, DROP_DUPLICATE_ACTION, source_path, &existing, || fs::remove_file(source_path))?;
+                    info!("Removed {}, an exact duplicate of {}", source_path.display(), existing.display());
+                    Ok(Outcome::DroppedDuplicate(existing))
+                }
+            }
         }
     }
 }
 
-#[cfg(not(feature = "test_env"))]
-fn log_move_operation(original_path: &Path, destination_path: &Path) -> std::io::Result<()> {
-    use std::io::Write;
-
-    use serde_json::json;
-
-    let log_entry = json!({
-        "original_path": original_path.to_str(),
-        "destination_path": destination_path.to_str(),
-    });
-
-    // Validate JSON format
-    if let Ok(log_str) = serde_json::to_string(&log_entry) {
-        let mut log_file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("undo_log.jsonl")?;
-
-        // Write the JSON string and manually append a newline
-        if let Err(e) = write!(log_file, "{}\n", log_str) {
-            return Err(e); // Handle write error
-        }
+fn path_parts(path: &Path) -> Vec<String> {
+    path.iter().map(|s| s.to_string_lossy().to_string()).collect()
+}
 
-        // Explicitly flush the buffer to ensure the newline is written
-        log_file.flush()?;
-    } else {
-        eprintln!("Invalid JSON format for log entry");
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Invalid JSON format",
-        ));
+/// Performs `perform` and records it in the session's journal, if the run
+/// keeps one.
+pub fn journaled(
+    options: &OrganizeOptions,
+    action: &str,
+    original_path: &Path,
+    destination_path: &Path,
+    perform: impl FnOnce() -> io::Result<()>,
+) -> io::Result<()> {
+    match &options.journal {
+        Some(journal) => journal.record(action, original_path, destination_path, perform),
+        None => perform(),
     }
-
-    Ok(())
 }
 
-#[cfg(feature = "test_env")]
-fn log_move_operation(_original_path: &Path, _destination_path: &Path) -> std::io::Result<()> {
-    Ok(())
+/// Transfers `source_path` to `target`, replacing the file there. When the
+/// session keeps a journal, the replaced file is first set aside under a
+/// hidden name next to `target`, so that undoing the session restores it.
+fn replace_file(options: &OrganizeOptions, source_path: &Path, target: &Path) -> io::Result<()> {
+    let transfer = options.transfer;
+    let Some(journal) = &options.journal else {
+        return transfer_file(transfer, source_path, target);
+    };
+    let is_taken = |path: &Path| path.symlink_metadata().is_ok();
+    let set_aside = temporary_path(target, REPLACED_SUFFIX);
+    let set_aside = if is_taken(&set_aside) { free_path(&set_aside, &is_taken) } else { set_aside };
+    journal.record_replacing(transfer.action(), source_path, target, Some(&set_aside), || {
+        fs::rename(target, &set_aside)?;
+        transfer_file(transfer, source_path, target).inspect_err(|_| {
+            if let Err(e) = fs::rename(&set_aside, target) {
+                error!("Failed to put {} back at {}: {}", set_aside.display(), target.display(), e);
+            }
+        })
+    })
 }
 
-
-pub fn undo_last_actions() -> io::Result<()> {
-    let log_path = Path::new("undo_log.jsonl");
-    let file = File::open(log_path)?;
-    let reader = BufReader::new(file);
-
-    let mut affected_dirs = HashSet::new();
-
-    // Process each line in the undo log
-    for line in reader.lines() {
-        let line = line?.trim().to_string();
-        if line.is_empty() {
-            continue;
+/// Describes why an entry cannot be undone, or `None` if it can.
+fn undo_conflict(entry: &JournalEntry) -> Option<String> {
+    let original = entry.original_path.display();
+    let destination = entry.destination_path.display();
+    if let Some(replaced) = entry.replaced_path.as_ref().filter(|replaced| !replaced.exists()) {
+        return Some(format!("{}, the file replaced at {}, no longer exists", replaced.display(), destination));
+    }
+    match entry.action.as_str() {
+        HARDLINK_DUPLICATE_ACTION => (!entry.original_path.exists())
+            .then(|| format!("{} no longer exists", original)),
+        // Removing a copy or link is only safe while the original is still there
+        COPY_ACTION | HARDLINK_ACTION | SYMLINK_ACTION | REFLINK_ACTION => {
+            if entry.destination_path.symlink_metadata().is_err() {
+                Some(format!("{} no longer exists", destination))
+            } else if !entry.original_path.exists() {
+                Some(format!("{} no longer exists, removing {} would lose it", original, destination))
+            } else {
+                None
+            }
         }
+        _ if !entry.destination_path.exists() => Some(format!("{} no longer exists", destination)),
+        _ if entry.original_path.exists() => Some(format!("{} already exists again", original)),
+        _ => None,
+    }
+}
 
-        let action: Value = serde_json::from_str(&line)
-            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+/// Reverts the actions recorded in a session's journal, newest first.
+///
+/// Every entry is checked before anything is changed: if a moved file has
+/// disappeared or its original location is occupied again, nothing is undone
+/// and the conflicts are returned as an error. Directories under
+/// `destination_root` that are left empty are removed afterwards.
+pub fn undo_actions(journal_path: &Path, destination_root: &Path) -> io::Result<()> {
+    let records = read_journal(journal_path)?;
+    let pending = records.iter().filter(|record| record.state == EntryState::Pending).count();
+    if pending > 0 {
+        return Err(Error::new(
+            ErrorKind::Interrupted,
+            format!("{} action(s) were interrupted, run `plexisort recover` first", pending),
+        ));
+    }
+    let entries: Vec<JournalEntry> = records
+        .into_iter()
+        .filter(|record| record.state == EntryState::Done)
+        .map(|record| record.entry)
+        .collect();
+
+    let conflicts: Vec<String> = entries.iter().filter_map(undo_conflict).collect();
+    if !conflicts.is_empty() {
+        return Err(Error::new(
+            ErrorKind::AlreadyExists,
+            format!("Cannot undo, {} conflict(s):\n  {}", conflicts.len(), conflicts.join("\n  ")),
+        ));
+    }
 
-        let original_path_str = action["original_path"].as_str().ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing 'original_path'"))?;
-        let destination_path_str = action["destination_path"].as_str().ok_or_else(|| Error::new(ErrorKind::InvalidData, "Missing 'destination_path'"))?;
+    let mut affected_dirs = HashSet::new();
 
-        let original_path = Path::new(original_path_str);
-        let destination_path = Path::new(destination_path_str);
+    // Process the undo log newest-first, so that an action that depends on an
+    // earlier one (such as a dropped duplicate of a moved file) is undone first
+    for entry in entries.iter().rev() {
+        let original_path = entry.original_path.as_path();
+        let destination_path = entry.destination_path.as_path();
 
-        if destination_path.exists() {
+        if entry.action == DROP_DUPLICATE_ACTION {
+            // The duplicate was deleted; restore it from the file it duplicated
             if let Some(parent_dir) = original_path.parent() {
                 fs::create_dir_all(parent_dir)?;
             }
-
-            fs::rename(&destination_path, &original_path)?;
-            debug!("Reversed move: {} -> {}", destination_path.display(), original_path.display());
+            fs::copy(destination_path, original_path)?;
+            debug!("Restored duplicate: {} -> {}", destination_path.display(), original_path.display());
+        } else if entry.action == HARDLINK_DUPLICATE_ACTION {
+            // Turn the hard link back into an independent copy of the file
+            let copy_path = temporary_path(original_path, COPY_SUFFIX);
+            fs::copy(original_path, &copy_path)?;
+            fs::rename(&copy_path, original_path)?;
+            debug!("Replaced hard link with a copy: {}", original_path.display());
+        } else {
+            if entry.action == MOVE_ACTION {
+                if let Some(parent_dir) = original_path.parent() {
+                    fs::create_dir_all(parent_dir)?;
+                }
+                move_file(destination_path, original_path)?;
+                debug!("Reversed move: {} -> {}", destination_path.display(), original_path.display());
+            } else {
+                // The original never left, so the copy or link is simply removed
+                fs::remove_file(destination_path)?;
+                debug!("Removed {}: {}", entry.action, destination_path.display());
+            }
+            if let Some(replaced) = &entry.replaced_path {
+                fs::rename(replaced, destination_path)?;
+                debug!("Restored replaced file: {}", destination_path.display());
+            }
 
             let mut current_dir = destination_path.parent();
-            while let Some(dir) = current_dir {
+            while let Some(dir) = current_dir.filter(|dir| dir.starts_with(destination_root)) {
                 affected_dirs.insert(dir.to_path_buf());
                 current_dir = dir.parent();
             }
-        } else {
-            return Err(Error::new(ErrorKind::NotFound, format!("Destination file does not exist: {}", destination_path.display())));
         }
     }
 
-    // Attempt to remove the log file before removing directories
-    if log_path.exists() {
-        fs::remove_file(log_path)?;
-        println!("Undo log cleared.");
-    } else {
-        println!("Undo log file not found or already removed.");
-    }
-
     // Now attempt to remove directories
     remove_directories(affected_dirs)?;
 
@@ -164,19 +294,6 @@ fn is_dir_empty(dir: &Path) -> io::Result<bool> {
 }
 
 
-pub fn clear_undo_log() -> std::io::Result<()> {
-    let log_path = "undo_log.jsonl";
-    if Path::new(log_path).exists() {
-        fs::remove_file(log_path)?;
-        println!("Undo log cleared.");
-    } else {
-        println!("No undo log file found to clear.");
-    }
-    Ok(())
-}
-
-
-
 pub fn print_current_structure(path: &Path, prefix: &str) {
     // Check if the path is a directory or a file
     if path.is_dir() {
@@ -207,4 +324,145 @@ pub fn print_current_structure(path: &Path, prefix: &str) {
         // If the path is a file, just print its name
         println!("{}── {}", prefix, path.file_name().unwrap().to_str().unwrap());
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_directory::VirtualDirectory;
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_live_collision_is_renamed() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        fs::create_dir_all(&destination_dir).unwrap();
+        let source_path = source_dir.join("photo.jpg");
+        let existing_path = destination_dir.join("photo.jpg");
+        writeln!(File::create(&source_path).unwrap(), "new photo").unwrap();
+        writeln!(File::create(&existing_path).unwrap(), "old photo").unwrap();
+
+        let mut mode = ProcessingMode::Live;
+        organize_file(&source_path, &existing_path, &mut mode, &OrganizeOptions::default()).unwrap();
+
+        assert_eq!(fs::read_to_string(&existing_path).unwrap(), "old photo\n", "Existing file was overwritten.");
+        assert_eq!(fs::read_to_string(destination_dir.join("photo (1).jpg")).unwrap(), "new photo\n");
+        assert!(!source_path.exists());
+    }
+
+    #[test]
+    fn test_undo_restores_overwritten_files() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("source").join("photo.jpg");
+        let existing = temp_dir.path().join("destination").join("photo.jpg");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::create_dir_all(existing.parent().unwrap()).unwrap();
+        writeln!(File::create(&source).unwrap(), "new photo").unwrap();
+        writeln!(File::create(&existing).unwrap(), "old photo").unwrap();
+
+        let log_path = temp_dir.path().join("session.jsonl");
+        let options = OrganizeOptions {
+            conflict_policy: ConflictPolicy::Overwrite,
+            journal: Some(Arc::new(Journal::open(&log_path).unwrap())),
+            ..OrganizeOptions::default()
+        };
+        organize_file(&source, &existing, &mut ProcessingMode::Live, &options).unwrap();
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "new photo\n");
+        assert_eq!(fs::read_dir(existing.parent().unwrap()).unwrap().count(), 2, "The replaced file must be kept until the session is undone.");
+
+        undo_actions(&log_path, &temp_dir.path().join("destination")).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "new photo\n");
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "old photo\n");
+        assert_eq!(fs::read_dir(existing.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_live_collision_is_skipped() {
+        let temp_dir = tempdir().unwrap();
+        let source_path = temp_dir.path().join("photo.jpg");
+        let existing_path = temp_dir.path().join("destination").join("photo.jpg");
+        fs::create_dir_all(existing_path.parent().unwrap()).unwrap();
+        File::create(&source_path).unwrap();
+        File::create(&existing_path).unwrap();
+
+        let mut mode = ProcessingMode::Live;
+        let options = OrganizeOptions { conflict_policy: ConflictPolicy::Skip, ..OrganizeOptions::default() };
+        organize_file(&source_path, &existing_path, &mut mode, &options).unwrap();
+
+        assert!(source_path.exists(), "Skipped file should stay in the source directory.");
+    }
+
+    #[test]
+    fn test_dry_run_detects_planned_collision() {
+        let temp_dir = tempdir().unwrap();
+        let first = temp_dir.path().join("a").join("notes.txt");
+        let second = temp_dir.path().join("b").join("notes.txt");
+        let destination_path = temp_dir.path().join("destination").join("notes.txt");
+
+        let mut mode = ProcessingMode::DryRun(VirtualDirectory::default());
+        organize_file(&first, &destination_path, &mut mode, &OrganizeOptions::default()).unwrap();
+        organize_file(&second, &destination_path, &mut mode, &OrganizeOptions::default()).unwrap();
+
+        let renamed = temp_dir.path().join("destination").join("notes (1).txt");
+        assert!(mode.contains_file(Path::new("notes.txt"), destination_path.parent().unwrap()));
+        assert!(mode.contains_file(&renamed, destination_path.parent().unwrap()));
+    }
+
+    #[test]
+    fn test_undo_removes_copies() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("card").join("IMG_0001.jpg");
+        let destination_dir = temp_dir.path().join("library");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        writeln!(File::create(&source).unwrap(), "photo").unwrap();
+
+        let log_path = temp_dir.path().join("session.jsonl");
+        let options = OrganizeOptions {
+            journal: Some(Arc::new(Journal::open(&log_path).unwrap())),
+            transfer: TransferMode::Copy,
+            ..OrganizeOptions::default()
+        };
+        let copy = destination_dir.join("Images").join("IMG_0001.jpg");
+        organize_file(&source, &copy, &mut ProcessingMode::Live, &options).unwrap();
+        assert!(source.exists() && copy.exists());
+
+        undo_actions(&log_path, &destination_dir).unwrap();
+        assert_eq!(fs::read_to_string(&source).unwrap(), "photo\n");
+        assert!(!destination_dir.exists());
+    }
+
+    #[test]
+    fn test_undo_session_checks_conflicts_first() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        let first = source_dir.join("a.txt");
+        let second = source_dir.join("b.txt");
+        writeln!(File::create(&first).unwrap(), "a").unwrap();
+        writeln!(File::create(&second).unwrap(), "b").unwrap();
+
+        let log_path = temp_dir.path().join("session.jsonl");
+        let journal = Journal::open(&log_path).unwrap();
+        let options = OrganizeOptions { journal: Some(Arc::new(journal)), ..OrganizeOptions::default() };
+        let mut mode = ProcessingMode::Live;
+        organize_file(&first, &destination_dir.join("Notes").join("a.txt"), &mut mode, &options).unwrap();
+        organize_file(&second, &destination_dir.join("Notes").join("b.txt"), &mut mode, &options).unwrap();
+
+        // A new file at an original location blocks the whole undo
+        writeln!(File::create(&second).unwrap(), "new b").unwrap();
+        assert!(undo_actions(&log_path, &destination_dir).is_err());
+        assert!(destination_dir.join("Notes").join("a.txt").exists(), "Nothing may be undone when there are conflicts.");
+
+        fs::remove_file(&second).unwrap();
+        undo_actions(&log_path, &destination_dir).unwrap();
+        assert_eq!(fs::read_to_string(&first).unwrap(), "a\n");
+        assert_eq!(fs::read_to_string(&second).unwrap(), "b\n");
+        assert!(!destination_dir.exists(), "Empty destination directories should be removed.");
+        assert!(log_path.exists(), "The session log is kept as history.");
+    }
+}
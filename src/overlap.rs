@@ -0,0 +1,125 @@
+use serde::Deserialize;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What to do when the destination directory lies inside a source directory.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum NestedDestination {
+    /// Refuse to run.
+    #[default]
+    Error,
+    /// Leave the destination subtree out when walking the source directory.
+    Prune,
+}
+
+impl NestedDestination {
+    pub const NAMES: &'static [&'static str] = &["error", "prune"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(NestedDestination::Error),
+            "prune" => Some(NestedDestination::Prune),
+            _ => None,
+        }
+    }
+}
+
+/// Resolves symlinks and `..` in `path`. Unlike `fs::canonicalize` it also
+/// works for a path that does not exist yet, by resolving its nearest
+/// existing ancestor.
+pub fn resolve_path(path: &Path) -> io::Result<PathBuf> {
+    let absolute = std::path::absolute(path)?;
+    let mut existing = absolute.as_path();
+    let mut missing = Vec::new();
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => return Ok(missing.iter().rev().fold(resolved, |path, part| path.join(part))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                missing.push(existing.file_name().unwrap_or_default().to_os_string());
+                existing = match existing.parent() {
+                    Some(parent) => parent,
+                    None => return Ok(absolute),
+                };
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Finds the source directories that contain the destination, after
+/// resolving symlinks, and returns the destination as a path inside each of
+/// them, in the form the source directory is walked in.
+///
+/// With `NestedDestination::Error` any nesting is an error; otherwise the
+/// returned directories are to be pruned from the walk. A source that is the
+/// destination itself is always an error.
+pub fn nested_destinations(sources: &[String], destination: &str, handling: NestedDestination) -> Result<Vec<PathBuf>, String> {
+    let resolved_destination = resolve_path(Path::new(destination))
+        .map_err(|e| format!("Cannot resolve destination directory '{}': {}", destination, e))?;
+
+    let mut nested = Vec::new();
+    for source in sources {
+        let resolved_source = resolve_path(Path::new(source))
+            .map_err(|e| format!("Cannot resolve source directory '{}': {}", source, e))?;
+        let relative = match resolved_destination.strip_prefix(&resolved_source) {
+            Ok(relative) => relative,
+            Err(_) => continue,
+        };
+
+        if relative.as_os_str().is_empty() {
+            return Err(format!("Destination directory '{}' is the source directory '{}'", destination, source));
+        }
+        if handling == NestedDestination::Error {
+            return Err(format!(
+                "Destination directory '{}' is inside the source directory '{}', so organized files would be organized again. \
+                 Choose another destination or set nested_destination = \"prune\"",
+                destination, source
+            ));
+        }
+        log::info!("Skipping the destination directory inside '{}'", source);
+        nested.push(Path::new(source).join(relative));
+    }
+    Ok(nested)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_nested_destination_is_detected() {
+        let temp_dir = tempdir().unwrap();
+        let downloads = temp_dir.path().join("Downloads");
+        fs::create_dir_all(&downloads).unwrap();
+        let source = downloads.display().to_string();
+        let destination = downloads.join("Organized").display().to_string();
+
+        assert!(nested_destinations(std::slice::from_ref(&source), &destination, NestedDestination::Error).is_err());
+        assert_eq!(
+            nested_destinations(std::slice::from_ref(&source), &destination, NestedDestination::Prune).unwrap(),
+            vec![downloads.join("Organized")]
+        );
+        assert!(nested_destinations(std::slice::from_ref(&source), &source, NestedDestination::Prune).is_err());
+
+        let elsewhere = temp_dir.path().join("Library").display().to_string();
+        assert!(nested_destinations(&[source], &elsewhere, NestedDestination::Error).unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_nesting_through_symlink_is_detected() {
+        let temp_dir = tempdir().unwrap();
+        let downloads = temp_dir.path().join("Downloads");
+        fs::create_dir_all(downloads.join("Organized")).unwrap();
+        let link = temp_dir.path().join("library-link");
+        std::os::unix::fs::symlink(downloads.join("Organized"), &link).unwrap();
+
+        let source = downloads.display().to_string();
+        let destination = link.join("Photos").display().to_string();
+        let nested = nested_destinations(&[source], &destination, NestedDestination::Prune).unwrap();
+        assert_eq!(nested, vec![downloads.join("Organized").join("Photos")]);
+    }
+}
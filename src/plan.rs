@@ -0,0 +1,186 @@
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::conflict::ConflictPolicy;
+use crate::error::PlexisortError;
+use crate::history::absolute_display;
+use crate::metadata::Metadata;
+use crate::organizer::OrganizeOptions;
+use crate::transfer::TransferMode;
+
+/// Version of the plan file format; `apply` refuses plans of other versions.
+const PLAN_VERSION: u32 = 1;
+
+/// What a processor decided to do with a file. Processors only produce
+/// plans; `executor::execute` carries them out, in dry-run and live mode alike.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct PlannedAction {
+    pub source: PathBuf,
+    /// Where the file goes, before conflicts with existing files are resolved.
+    pub destination: PathBuf,
+    /// Why the file goes there, e.g. the rule that matched it.
+    pub reason: String,
+    /// The metadata the decision was based on.
+    pub metadata: Metadata,
+    /// The processor that made the decision, e.g. `ImageProcessor`.
+    pub processor: String,
+    /// The category the file is filed under, or the label of the rule that matched it.
+    pub category: String,
+    /// The label of the `[[rules]]` entry that matched the file, if any.
+    #[serde(default)]
+    pub rule: Option<String>,
+    /// Things worth a look, e.g. contents that contradict the extension.
+    #[serde(default)]
+    pub notes: Vec<String>,
+    /// The file this one travels with, e.g. the photo of an `.xmp` sidecar.
+    /// It goes wherever that file ends up, and stays if that file does.
+    #[serde(default)]
+    pub follows: Option<PathBuf>,
+}
+
+/// The size and modification time of a source file when it was planned.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+}
+
+impl Fingerprint {
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Fingerprint { size: metadata.len(), modified: metadata.modified()?.into() })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlanEntry {
+    #[serde(flatten)]
+    pub action: PlannedAction,
+    pub fingerprint: Fingerprint,
+}
+
+/// A plan written by `plexisort plan` for review, and carried out as is by
+/// `plexisort apply`. It holds the options that decide how the files are
+/// moved, so that applying it does not depend on the config at that time.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlanFile {
+    pub version: u32,
+    pub created_at: DateTime<Local>,
+    pub source_directories: Vec<String>,
+    pub destination: String,
+    pub on_conflict: ConflictPolicy,
+    pub transfer: TransferMode,
+    pub entries: Vec<PlanEntry>,
+}
+
+impl PlanFile {
+    /// Fingerprints the sources of `actions`, with all paths made absolute so
+    /// that the plan can be applied from any directory. Files that cannot be
+    /// read any more are left out.
+    pub fn new(source_directories: &[String], destination: &str, options: &OrganizeOptions, actions: Vec<PlannedAction>) -> Self {
+        let entries = actions
+            .into_iter()
+            .filter_map(|mut action| {
+                action.source = std::path::absolute(&action.source).unwrap_or(action.source);
+                action.destination = std::path::absolute(&action.destination).unwrap_or(action.destination);
+                action.follows = action.follows.map(|primary| std::path::absolute(&primary).unwrap_or(primary));
+                match Fingerprint::of(&action.source) {
+                    Ok(fingerprint) => Some(PlanEntry { action, fingerprint }),
+                    Err(e) => {
+                        log::warn!("Leaving {} out of the plan: {}", action.source.display(), e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        PlanFile {
+            version: PLAN_VERSION,
+            created_at: Local::now(),
+            source_directories: source_directories.iter().map(|dir| absolute_display(dir)).collect(),
+            destination: absolute_display(destination),
+            on_conflict: options.conflict_policy,
+            transfer: options.transfer,
+            entries,
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, json)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read plan {}: {}", path.display(), e))?;
+        let plan: PlanFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Invalid plan {}: {}", path.display(), e))?;
+        if plan.version != PLAN_VERSION {
+            return Err(format!("Plan {} has version {}, expected {}", path.display(), plan.version, PLAN_VERSION));
+        }
+        Ok(plan)
+    }
+
+    /// Splits the plan into the actions whose source is unchanged since
+    /// planning, and the reasons the other entries must not be applied.
+    pub fn verify(&self) -> (Vec<PlannedAction>, Vec<PlexisortError>) {
+        let mut unchanged = Vec::new();
+        let mut refused = Vec::new();
+        for entry in &self.entries {
+            let source = &entry.action.source;
+            match Fingerprint::of(source) {
+                Ok(fingerprint) if fingerprint == entry.fingerprint => unchanged.push(entry.action.clone()),
+                Ok(_) => refused.push(PlexisortError::Stale { path: source.clone() }),
+                Err(e) => refused.push(PlexisortError::from_io(source, e)),
+            }
+        }
+        (unchanged, refused)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_plan_round_trip_and_verification() {
+        let temp_dir = tempdir().unwrap();
+        let unchanged = temp_dir.path().join("a.txt");
+        let edited = temp_dir.path().join("b.txt");
+        let deleted = temp_dir.path().join("c.txt");
+        for path in [&unchanged, &edited, &deleted] {
+            fs::write(path, "original").unwrap();
+        }
+        let action = |source: &Path| PlannedAction {
+            source: source.to_path_buf(),
+            destination: temp_dir.path().join("destination").join(source.file_name().unwrap()),
+            reason: "Documents by file type".to_string(),
+            metadata: Metadata::from([("ext".to_string(), "txt".to_string())]),
+            processor: "DocumentProcessor".to_string(),
+            category: "Documents".to_string(),
+            rule: None,
+            notes: vec![],
+            follows: None,
+        };
+        let options = OrganizeOptions { transfer: TransferMode::Copy, ..OrganizeOptions::default() };
+        let plan = PlanFile::new(&[], "destination", &options, vec![action(&unchanged), action(&edited), action(&deleted)]);
+
+        let plan_path = temp_dir.path().join("plan.json");
+        plan.write(&plan_path).unwrap();
+        let read = PlanFile::read(&plan_path).unwrap();
+        assert_eq!(read, plan);
+        assert_eq!(read.transfer, TransferMode::Copy);
+
+        fs::write(&edited, "edited since").unwrap();
+        fs::remove_file(&deleted).unwrap();
+        let (actions, refused) = read.verify();
+        assert_eq!(actions, vec![action(&unchanged)]);
+        let kinds: Vec<&str> = refused.iter().map(PlexisortError::kind).collect();
+        assert_eq!(kinds, vec!["stale", "io"]);
+    }
+}
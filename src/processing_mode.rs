@@ -1,4 +1,4 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use crate::virtual_directory::VirtualDirectory;
 
@@ -43,16 +43,14 @@ impl ProcessingMode {
     ///
     /// Note: Replace `your_crate_name` with the actual name of your crate.
     #[allow(dead_code)]
-    pub fn contains_file(&self, file_path: &Path, dest_path: &PathBuf) -> bool {
+    pub fn contains_file(&self, file_path: &Path, dest_path: &Path) -> bool {
         match self {
             ProcessingMode::DryRun(virtual_dir) => {
                 // Convert `file_path` and `dest_path` to a Vec<String> representation.
                 let mut path_components = Vec::new();
 
                 // Add the destination path components to the vector.
-                if let Some(dest_str) = dest_path.to_str() {
-                    path_components.extend(dest_str.split('/').map(String::from));
-                }
+                path_components.extend(dest_path.iter().map(|s| s.to_string_lossy().to_string()));
 
                 // Add the file name to the vector.
                 if let Some(file_name) = file_path.file_name().and_then(|name| name.to_str()) {
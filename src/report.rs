@@ -0,0 +1,288 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::metadata::Metadata;
+use crate::organizer::Outcome;
+use crate::summary::{FileOutcome, RunSummary};
+
+/// File formats a run report can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    /// A self-contained page with per-category totals and a table of all files.
+    Html,
+}
+
+impl ReportFormat {
+    pub const NAMES: &'static [&'static str] = &["json", "csv", "html"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(ReportFormat::Json),
+            "csv" => Some(ReportFormat::Csv),
+            "html" | "htm" => Some(ReportFormat::Html),
+            _ => None,
+        }
+    }
+
+    /// The format that the extension of `path` names, if any.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| ReportFormat::from_name(&extension.to_lowercase()))
+    }
+}
+
+/// What happened to one file, as listed in the report.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ReportEntry {
+    pub source: PathBuf,
+    /// Where the file ended up; for skipped and failed files, where it was planned to go.
+    pub destination: Option<PathBuf>,
+    pub processor: Option<String>,
+    pub category: String,
+    pub rule: Option<String>,
+    pub metadata: Metadata,
+    /// `organized`, `duplicate_removed`, `hard_linked`, `skipped` or `failed`.
+    pub outcome: &'static str,
+    /// Why the file was skipped, or the error it failed with.
+    pub detail: Option<String>,
+    pub error_kind: Option<&'static str>,
+    pub bytes: u64,
+    pub notes: Vec<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct CategoryTotals {
+    pub category: String,
+    pub files: usize,
+    pub organized: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub bytes: u64,
+}
+
+/// A machine-readable account of a run, e.g. for archival records.
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub generated_at: DateTime<Local>,
+    pub dry_run: bool,
+    /// The session the run was recorded as, for `--undo`.
+    pub session: Option<String>,
+    pub totals: Vec<CategoryTotals>,
+    pub files: Vec<ReportEntry>,
+}
+
+impl Report {
+    pub fn new(summary: &RunSummary, dry_run: bool, session: Option<String>) -> Self {
+        let files: Vec<ReportEntry> = summary.files.iter().map(entry).collect();
+
+        let mut totals: BTreeMap<&str, CategoryTotals> = BTreeMap::new();
+        for file in &files {
+            let total = totals.entry(&file.category).or_insert_with(|| CategoryTotals {
+                category: file.category.clone(),
+                ..CategoryTotals::default()
+            });
+            total.files += 1;
+            total.bytes += file.bytes;
+            match file.outcome {
+                "skipped" => total.skipped += 1,
+                "failed" => total.failed += 1,
+                _ => total.organized += 1,
+            }
+        }
+
+        Report {
+            generated_at: Local::now(),
+            dry_run,
+            session,
+            totals: totals.into_values().collect(),
+            files,
+        }
+    }
+
+    pub fn write(&self, path: &Path, format: ReportFormat) -> io::Result<()> {
+        let contents = match format {
+            ReportFormat::Json => serde_json::to_string_pretty(self).map_err(io::Error::other)?,
+            ReportFormat::Csv => self.to_csv()?,
+            ReportFormat::Html => self.to_html(),
+        };
+        fs::write(path, contents)
+    }
+
+    /// One row per file. Notes are joined by `; ` and the metadata is a JSON object.
+    fn to_csv(&self) -> io::Result<String> {
+        let mut writer = csv::Writer::from_writer(Vec::new());
+        writer.write_record(["source", "destination", "processor", "category", "rule", "outcome", "detail", "error_kind", "bytes", "notes", "metadata"])?;
+        for file in &self.files {
+            writer.write_record([
+                file.source.display().to_string(),
+                file.destination.as_ref().map(|path| path.display().to_string()).unwrap_or_default(),
+                file.processor.clone().unwrap_or_default(),
+                file.category.clone(),
+                file.rule.clone().unwrap_or_default(),
+                file.outcome.to_string(),
+                file.detail.clone().unwrap_or_default(),
+                file.error_kind.unwrap_or_default().to_string(),
+                file.bytes.to_string(),
+                file.notes.join("; "),
+                serde_json::to_string(&file.metadata).map_err(io::Error::other)?,
+            ])?;
+        }
+        let bytes = writer.into_inner().map_err(|e| io::Error::other(e.to_string()))?;
+        String::from_utf8(bytes).map_err(io::Error::other)
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+        let title = if self.dry_run { "Plexisort dry run report" } else { "Plexisort run report" };
+        let _ = write!(
+            html,
+            "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>\n\
+             body {{ font-family: sans-serif; margin: 2em; }}\n\
+             table {{ border-collapse: collapse; margin-bottom: 2em; }}\n\
+             th, td {{ border: 1px solid #ccc; padding: 0.3em 0.6em; text-align: left; vertical-align: top; }}\n\
+             th {{ background: #f0f0f0; }}\n\
+             td.number {{ text-align: right; }}\n\
+             tr.failed {{ background: #fde8e8; }}\n\
+             tr.skipped {{ background: #fdf6e3; }}\n\
+             </style>\n</head>\n<body>\n<h1>{title}</h1>\n<p>Generated {}",
+            self.generated_at.format("%Y-%m-%d %H:%M:%S"),
+        );
+        if let Some(session) = &self.session {
+            let _ = write!(html, ", session {}", escape(session));
+        }
+        html.push_str("</p>\n<h2>Totals</h2>\n<table>\n<tr><th>Category</th><th>Files</th><th>Organized</th><th>Skipped</th><th>Failed</th><th>Bytes</th></tr>\n");
+        for total in &self.totals {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td><td class=\"number\">{}</td></tr>",
+                escape(&total.category), total.files, total.organized, total.skipped, total.failed, total.bytes,
+            );
+        }
+        html.push_str("</table>\n<h2>Files</h2>\n<table>\n<tr><th>Source</th><th>Destination</th><th>Processor</th><th>Rule</th><th>Outcome</th><th>Bytes</th><th>Details</th></tr>\n");
+        for file in &self.files {
+            let details: Vec<String> = file.detail.iter().chain(&file.notes).map(|text| escape(text)).collect();
+            let _ = writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td class=\"number\">{}</td><td>{}</td></tr>",
+                file.outcome,
+                escape(&file.source.display().to_string()),
+                escape(&file.destination.as_ref().map(|path| path.display().to_string()).unwrap_or_default()),
+                escape(file.processor.as_deref().unwrap_or("")),
+                escape(file.rule.as_deref().unwrap_or("")),
+                file.outcome,
+                file.bytes,
+                details.join("<br>"),
+            );
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+fn entry(file: &FileOutcome) -> ReportEntry {
+    let action = file.action.as_ref();
+    let (outcome, destination, detail) = match &file.result {
+        Ok(Outcome::Transferred(target)) => ("organized", Some(target.clone()), None),
+        Ok(Outcome::DroppedDuplicate(existing)) => ("duplicate_removed", Some(existing.clone()), None),
+        Ok(Outcome::HardLinked(original)) => ("hard_linked", Some(original.clone()), None),
+        Ok(Outcome::Skipped(reason)) => ("skipped", action.map(|action| action.destination.clone()), Some(reason.clone())),
+        Err(e) => ("failed", action.map(|action| action.destination.clone()), Some(e.to_string())),
+    };
+
+    ReportEntry {
+        source: file.source.clone(),
+        destination,
+        processor: action.map(|action| action.processor.clone()),
+        category: file.category.clone(),
+        rule: action.and_then(|action| action.rule.clone()),
+        metadata: action.map(|action| action.metadata.clone()).unwrap_or_default(),
+        outcome,
+        detail,
+        error_kind: file.result.as_ref().err().map(|e| e.kind()),
+        bytes: file.bytes,
+        notes: action.map(|action| action.notes.clone()).unwrap_or_default(),
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::PlexisortError;
+    use crate::plan::PlannedAction;
+    use crate::summary::DUPLICATES_CATEGORY;
+    use tempfile::tempdir;
+
+    fn planned(source: &str, category: &str, rule: Option<&str>) -> PlannedAction {
+        PlannedAction {
+            source: PathBuf::from(source),
+            destination: PathBuf::from("/archive").join(category).join(Path::new(source).file_name().unwrap()),
+            reason: format!("{} by file type", category),
+            metadata: Metadata::from([("year".to_string(), "2023".to_string())]),
+            processor: "ImageProcessor".to_string(),
+            category: category.to_string(),
+            rule: rule.map(str::to_string),
+            notes: vec!["Type mismatch: looks like image/jpeg (jpg) but its extension suggests image/png".to_string()],
+            follows: None,
+        }
+    }
+
+    fn summary() -> RunSummary {
+        let mut summary = RunSummary::default();
+        let photo = planned("/inbox/<photo>.png", "Images", None);
+        summary.record_planned(&photo, Ok(Outcome::Transferred(photo.destination.clone())));
+        let scan = planned("/inbox/scan.jpg", "Scans", Some("scans"));
+        summary.record_planned(&scan, Err(PlexisortError::Stale { path: scan.source.clone() }));
+        summary.record(Path::new("/inbox/copy.png"), DUPLICATES_CATEGORY, Ok(Outcome::DroppedDuplicate(photo.destination.clone())));
+        summary
+    }
+
+    #[test]
+    fn test_report_lists_files_and_totals() {
+        let report = Report::new(&summary(), false, Some("20240101-120000-1".to_string()));
+
+        assert_eq!(report.files.len(), 3);
+        assert_eq!(report.files[1].outcome, "failed");
+        assert_eq!(report.files[1].error_kind, Some("stale"));
+        assert_eq!(report.files[1].rule.as_deref(), Some("scans"));
+        let categories: Vec<(&str, usize, usize)> = report.totals.iter().map(|t| (t.category.as_str(), t.organized, t.failed)).collect();
+        assert_eq!(categories, vec![("Duplicates", 1, 0), ("Images", 1, 0), ("Scans", 0, 1)]);
+    }
+
+    #[test]
+    fn test_report_formats() {
+        let temp_dir = tempdir().unwrap();
+        let report = Report::new(&summary(), true, None);
+
+        let json_path = temp_dir.path().join("report.json");
+        report.write(&json_path, ReportFormat::from_path(&json_path).unwrap()).unwrap();
+        let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert_eq!(json["files"][0]["metadata"]["year"], "2023");
+        assert_eq!(json["dry_run"], true);
+
+        let csv_path = temp_dir.path().join("report.csv");
+        report.write(&csv_path, ReportFormat::Csv).unwrap();
+        let mut rows = csv::Reader::from_path(&csv_path).unwrap().into_records().map(Result::unwrap);
+        let first = rows.next().unwrap();
+        assert_eq!(&first[0], "/inbox/<photo>.png");
+        assert_eq!(&first[5], "organized");
+        assert!(first[9].starts_with("Type mismatch"));
+
+        let html_path = temp_dir.path().join("report.html");
+        report.write(&html_path, ReportFormat::Html).unwrap();
+        let html = fs::read_to_string(&html_path).unwrap();
+        assert!(html.contains("/inbox/&lt;photo&gt;.png"), "File names must be escaped.");
+        assert!(!html.contains("<link") && !html.contains("<script"), "The page must be self-contained.");
+    }
+}
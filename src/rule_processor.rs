@@ -0,0 +1,110 @@
+use crate::dates::DateResolver;
+use crate::metadata::{full_metadata, Metadata};
+use crate::plan::PlannedAction;
+use crate::summary::RULES_CATEGORY;
+use crate::template::PathTemplate;
+use crate::traits::processor::Processor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+
+/// Moves files that matched a `[[rules]]` entry into the rule's destination subfolder.
+pub struct RuleProcessor {
+    pub name: String,
+    pub destination: PathTemplate,
+    pub unknown_token: String,
+    pub mime: String, // The detected type of the file, for its media fields
+    /// The file's fields if they were collected to match the rule, so that
+    /// they are not read a second time.
+    pub fields: Option<Metadata>,
+    pub dates: Arc<DateResolver>,
+}
+
+impl RuleProcessor {
+    fn relative_path(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        self.destination.expand_file_path(path, metadata, &self.unknown_token)
+    }
+}
+
+impl Processor for RuleProcessor {
+    fn plan(&self, path: &Path, destination: &Path) -> PlannedAction {
+        let metadata = self.metadata(path);
+        PlannedAction {
+            source: path.to_path_buf(),
+            destination: destination.join(self.relative_path(path, &metadata)),
+            reason: format!("rule '{}'", self.name),
+            metadata,
+            processor: Processor::name(self).to_string(),
+            category: self.category().to_string(),
+            rule: Some(self.name.clone()),
+            notes: Vec::new(),
+            follows: None,
+        }
+    }
+
+    fn get_destination_subfolder(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        self.relative_path(path, metadata).parent().map(Path::to_path_buf).unwrap_or_default()
+    }
+
+    fn category(&self) -> &str {
+        RULES_CATEGORY
+    }
+
+    fn dates(&self) -> &DateResolver {
+        &self.dates
+    }
+
+    /// Rules match any kind of file, so their destinations may use the
+    /// fields of every media type.
+    fn metadata(&self, path: &Path) -> Metadata {
+        match &self.fields {
+            Some(fields) => fields.clone(),
+            None => full_metadata(path, &self.dates, &self.mime),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod rule_processor_tests {
+    use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_rule_processor_live() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        let notes_path = source_dir.join("meeting.md");
+        File::create(&notes_path).unwrap();
+
+        let processor = RuleProcessor {
+            name: "notes".to_string(),
+            destination: PathTemplate::parse("Notes/{ext}").unwrap(),
+            unknown_token: "Unknown".to_string(),
+            mime: "text/markdown".to_string(),
+            fields: None,
+            dates: Arc::default(),
+        };
+        let mut mode = ProcessingMode::Live;
+
+        let plan = processor.plan(&notes_path, &destination_dir);
+        assert_eq!(plan.reason, "rule 'notes'");
+        assert_eq!((plan.category.as_str(), plan.rule.as_deref()), (RULES_CATEGORY, Some("notes")));
+        assert_eq!(plan.metadata.get("ext").map(String::as_str), Some("md"));
+        execute(&plan, &mut mode, &OrganizeOptions::default()).unwrap();
+
+        let expected_destination = destination_dir.join("Notes").join("md").join("meeting.md");
+        assert!(expected_destination.exists(), "File was not moved to the rule's destination in Live mode.");
+
+        let fields = Metadata::from([("ext".to_string(), "txt".to_string())]);
+        let processor = RuleProcessor { fields: Some(fields), ..processor };
+        let plan = processor.plan(&source_dir.join("gone.md"), &destination_dir);
+        assert_eq!(plan.destination, destination_dir.join("Notes").join("txt").join("gone.md"), "The fields the rule matched by must be reused.");
+    }
+}
@@ -0,0 +1,334 @@
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::metadata::Metadata;
+use crate::sniff::FileType;
+use crate::template::PathTemplate;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// A single `[[rules]]` entry from the config file.
+///
+/// Every condition that is set must match for the rule to apply; conditions
+/// that are left out are ignored. The first matching rule wins.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct Rule {
+    /// Optional label, only used in log and error messages.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// File extensions without the leading dot, compared case-insensitively.
+    #[serde(default)]
+    pub extensions: Vec<String>,
+    /// MIME type such as `application/pdf`, or a wildcard such as `image/*`.
+    #[serde(default)]
+    pub mime: Option<String>,
+    /// Glob matched against the file name, or against the full path when it contains a `/`.
+    #[serde(default)]
+    pub glob: Option<String>,
+    /// Minimum file size in bytes.
+    #[serde(default)]
+    pub min_size: Option<u64>,
+    /// Maximum file size in bytes.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Minimum age in days, based on the modification time.
+    #[serde(default)]
+    pub min_age_days: Option<u64>,
+    /// Maximum age in days, based on the modification time.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+    /// Globs that metadata fields must match, compared case-insensitively,
+    /// e.g. `camera_model = "iPhone*"`. A file without the field does not match.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    /// Subfolder of the destination directory that matching files are moved into.
+    /// It may use the same placeholders as the `[templates]` section.
+    pub destination: String,
+}
+
+impl Rule {
+    /// Checks that the rule can be compiled and that its destination stays
+    /// inside the destination directory.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.destination.trim().is_empty() {
+            return Err("'destination' must not be empty".to_string());
+        }
+        PathTemplate::parse(&self.destination).map_err(|e| format!("invalid 'destination': {}", e))?;
+
+        if self.extensions.is_empty()
+            && self.mime.is_none()
+            && self.glob.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+            && self.min_age_days.is_none()
+            && self.max_age_days.is_none()
+            && self.metadata.is_empty()
+        {
+            return Err("at least one match condition (extensions, mime, glob, size, age or metadata) is required".to_string());
+        }
+
+        if let Some(mime) = &self.mime {
+            let mut parts = mime.split('/');
+            let valid = matches!((parts.next(), parts.next(), parts.next()), (Some(t), Some(s), None) if !t.is_empty() && !s.is_empty());
+            if !valid {
+                return Err(format!("'mime' must look like 'type/subtype' or 'type/*': {}", mime));
+            }
+        }
+
+        if let Some(glob) = &self.glob {
+            Glob::new(glob).map_err(|e| format!("invalid 'glob' {}: {}", glob, e))?;
+        }
+
+        for (field, pattern) in &self.metadata {
+            metadata_glob(pattern).map_err(|e| format!("invalid 'metadata' glob for '{}': {}", field, e))?;
+        }
+
+        if let (Some(min), Some(max)) = (self.min_size, self.max_size) {
+            if min > max {
+                return Err(format!("'min_size' ({}) is greater than 'max_size' ({})", min, max));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (self.min_age_days, self.max_age_days) {
+            if min > max {
+                return Err(format!("'min_age_days' ({}) is greater than 'max_age_days' ({})", min, max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Human readable label for messages: the rule's name, or its destination.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.destination)
+    }
+}
+
+struct CompiledRule {
+    rule: Rule,
+    destination: PathTemplate,
+    glob: Option<GlobMatcher>,
+    match_full_path: bool,
+    metadata: Vec<(String, GlobMatcher)>,
+}
+
+/// The ordered list of routing rules, compiled once and evaluated per file.
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+impl RuleSet {
+    pub fn new(rules: &[Rule]) -> Result<Self, String> {
+        let mut compiled = Vec::with_capacity(rules.len());
+        for (index, rule) in rules.iter().enumerate() {
+            rule.validate().map_err(|e| format!("Invalid rule #{}: {}", index + 1, e))?;
+            let glob = match &rule.glob {
+                Some(pattern) => Some(
+                    Glob::new(pattern)
+                        .map_err(|e| format!("Invalid rule #{}: {}", index + 1, e))?
+                        .compile_matcher(),
+                ),
+                None => None,
+            };
+            compiled.push(CompiledRule {
+                rule: rule.clone(),
+                destination: PathTemplate::parse(&rule.destination)
+                    .map_err(|e| format!("Invalid rule #{}: {}", index + 1, e))?,
+                glob,
+                match_full_path: rule.glob.as_deref().is_some_and(|g| g.contains('/')),
+                metadata: rule
+                    .metadata
+                    .iter()
+                    .map(|(field, pattern)| Ok((field.clone(), metadata_glob(pattern)?)))
+                    .collect::<Result<_, globset::Error>>()
+                    .map_err(|e| format!("Invalid rule #{}: {}", index + 1, e))?,
+            });
+        }
+        Ok(RuleSet { rules: compiled })
+    }
+
+    /// Returns the first rule that matches the file, if any, together with
+    /// its parsed destination template. `mime` conditions are matched against
+    /// `file_type`. `metadata` gives the file's metadata fields; it is only
+    /// called if a rule has `metadata` conditions, and it is up to the
+    /// caller to collect the fields once.
+    pub fn find_match<'m>(&self, path: &Path, file_type: &FileType, metadata: impl Fn() -> &'m Metadata) -> Option<(&Rule, &PathTemplate)> {
+        self.rules
+            .iter()
+            .find(|compiled| compiled.matches(path, file_type, &metadata))
+            .map(|compiled| (&compiled.rule, &compiled.destination))
+    }
+}
+
+impl CompiledRule {
+    fn matches<'a>(&self, path: &Path, file_type: &FileType, metadata: &dyn Fn() -> &'a Metadata) -> bool {
+        let rule = &self.rule;
+
+        if !rule.extensions.is_empty() {
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+            if !rule.extensions.iter().any(|e| e.trim_start_matches('.').to_lowercase() == extension) {
+                return false;
+            }
+        }
+
+        if let Some(pattern) = &rule.mime {
+            if !mime_matches(pattern, &file_type.mime) {
+                return false;
+            }
+        }
+
+        if let Some(glob) = &self.glob {
+            let matched = if self.match_full_path {
+                glob.is_match(path)
+            } else {
+                path.file_name().is_some_and(|name| glob.is_match(name))
+            };
+            if !matched {
+                return false;
+            }
+        }
+
+        if !self.metadata.is_empty() {
+            let fields = metadata();
+            let all_match = self.metadata.iter().all(|(field, glob)| fields.get(field).is_some_and(|value| glob.is_match(value)));
+            if !all_match {
+                return false;
+            }
+        }
+
+        let needs_size = rule.min_size.is_some() || rule.max_size.is_some();
+        let needs_age = rule.min_age_days.is_some() || rule.max_age_days.is_some();
+        if !needs_size && !needs_age {
+            return true;
+        }
+
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) => return false,
+        };
+
+        if rule.min_size.is_some_and(|min| metadata.len() < min)
+            || rule.max_size.is_some_and(|max| metadata.len() > max)
+        {
+            return false;
+        }
+
+        if needs_age {
+            let age = match metadata.modified() {
+                Ok(modified) => SystemTime::now().duration_since(modified).unwrap_or(Duration::ZERO),
+                Err(_) => return false,
+            };
+            let age_days = age.as_secs() / SECONDS_PER_DAY;
+            if rule.min_age_days.is_some_and(|min| age_days < min)
+                || rule.max_age_days.is_some_and(|max| age_days > max)
+            {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Compiles a `metadata` condition. Values are not paths, so `*` matches `/` too.
+fn metadata_glob(pattern: &str) -> Result<GlobMatcher, globset::Error> {
+    Ok(GlobBuilder::new(pattern).case_insensitive(true).literal_separator(false).build()?.compile_matcher())
+}
+
+fn mime_matches(pattern: &str, mime_type: &str) -> bool {
+    let pattern = pattern.to_lowercase();
+    match pattern.strip_suffix("/*") {
+        Some(top_level) => mime_type.split('/').next() == Some(top_level),
+        None => pattern == mime_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sniff::{detect_type, TypePrecedence};
+    use std::fs::File;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    static NO_FIELDS: Metadata = Metadata::new();
+
+    fn typed(path: &Path) -> FileType {
+        detect_type(path, TypePrecedence::Extension)
+    }
+
+    fn rule(destination: &str) -> Rule {
+        Rule { destination: destination.to_string(), ..Rule::default() }
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let temp_dir = tempdir().unwrap();
+        let notes = temp_dir.path().join("todo.md");
+        let drawing = temp_dir.path().join("plan.DWG");
+        let mut file = File::create(&notes).unwrap();
+        writeln!(file, "# Notes").unwrap();
+        File::create(&drawing).unwrap();
+
+        let rules = vec![
+            Rule { extensions: vec!["md".to_string()], ..rule("Notes") },
+            Rule { extensions: vec!["dwg".to_string()], ..rule("CAD") },
+            Rule { glob: Some("*.md".to_string()), ..rule("Never") },
+        ];
+        let rule_set = RuleSet::new(&rules).unwrap();
+
+        assert_eq!(rule_set.find_match(&notes, &typed(&notes), || &NO_FIELDS).map(|(r, _)| r.destination.as_str()), Some("Notes"));
+        assert_eq!(rule_set.find_match(&drawing, &typed(&drawing), || &NO_FIELDS).map(|(r, _)| r.destination.as_str()), Some("CAD"));
+        let photo = temp_dir.path().join("photo.jpg");
+        assert!(rule_set.find_match(&photo, &typed(&photo), || &NO_FIELDS).is_none());
+    }
+
+    #[test]
+    fn test_rule_conditions_are_combined() {
+        let temp_dir = tempdir().unwrap();
+        let small = temp_dir.path().join("small.pdf");
+        let large = temp_dir.path().join("large.pdf");
+        File::create(&small).unwrap().write_all(&[0u8; 10]).unwrap();
+        File::create(&large).unwrap().write_all(&[0u8; 2048]).unwrap();
+
+        let rules = vec![Rule { mime: Some("application/*".to_string()), min_size: Some(1024), ..rule("Large_PDFs") }];
+        let rule_set = RuleSet::new(&rules).unwrap();
+
+        assert!(rule_set.find_match(&small, &typed(&small), || &NO_FIELDS).is_none());
+        assert!(rule_set.find_match(&large, &typed(&large), || &NO_FIELDS).is_some());
+    }
+
+    #[test]
+    fn test_metadata_conditions() {
+        let temp_dir = tempdir().unwrap();
+        let photo = temp_dir.path().join("IMG_0001.jpg");
+        File::create(&photo).unwrap();
+        let mut conditions = BTreeMap::new();
+        conditions.insert("camera_model".to_string(), "iphone*".to_string());
+        let rules = vec![Rule { metadata: conditions, ..rule("Phone") }];
+        let rule_set = RuleSet::new(&rules).unwrap();
+
+        let iphone = Metadata::from([("camera_model".to_string(), "iPhone 14 Pro".to_string())]);
+        let canon = Metadata::from([("camera_model".to_string(), "EOS R5".to_string())]);
+        assert!(rule_set.find_match(&photo, &typed(&photo), || &iphone).is_some());
+        assert!(rule_set.find_match(&photo, &typed(&photo), || &canon).is_none());
+        assert!(rule_set.find_match(&photo, &typed(&photo), || &NO_FIELDS).is_none(), "A missing field must not match.");
+    }
+
+    #[test]
+    fn test_invalid_rule_reports_index() {
+        let rules = vec![
+            Rule { extensions: vec!["md".to_string()], ..rule("Notes") },
+            Rule { glob: Some("[".to_string()), ..rule("Broken") },
+        ];
+        let error = RuleSet::new(&rules).err().unwrap();
+        assert!(error.starts_with("Invalid rule #2"), "unexpected error: {}", error);
+
+        assert!(Rule { extensions: vec!["md".to_string()], ..rule("../outside") }.validate().is_err());
+        assert!(rule("Everything").validate().is_err());
+    }
+}
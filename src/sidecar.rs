@@ -0,0 +1,279 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::error::PlexisortError;
+use crate::executor::Placements;
+use crate::plan::PlannedAction;
+use crate::sniff::extension_mime;
+
+/// Decides which files travel with the photo or video they belong to, such
+/// as the raw file of a RAW+JPEG pair or the `.xmp` file Lightroom keeps next
+/// to a photo. Moving them apart breaks the catalogs of photo editors.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct SidecarOptions {
+    /// Whether files are grouped at all.
+    pub enabled: bool,
+    /// Extensions of files that only describe another file: XMP metadata,
+    /// the edits of Apple Photos (`.AAE`), Google Takeout's `.json` files,
+    /// camera thumbnails (`.THM`) and the edits of RawTherapee and DxO.
+    pub extensions: Vec<String>,
+}
+
+impl Default for SidecarOptions {
+    fn default() -> Self {
+        SidecarOptions {
+            enabled: true,
+            extensions: ["xmp", "aae", "json", "thm", "pp3", "dop"].iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl SidecarOptions {
+    /// Whether `path` only describes another file, by its extension.
+    pub fn is_sidecar(&self, path: &Path) -> bool {
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        self.extensions.iter().any(|known| known.trim_start_matches('.').eq_ignore_ascii_case(extension))
+    }
+}
+
+/// Groups the photos and videos of a directory that share a name, e.g.
+/// `IMG_0001.CR2` and `IMG_0001.JPG`, with their sidecars, e.g.
+/// `IMG_0001.xmp` or `IMG_0001.JPG.json`. The first image of a group, or its
+/// first video if it has no image, is its primary file; the other files
+/// follow it to its destination folder and are planned right after it.
+/// Files that belong to no group keep their plan.
+pub fn group_sidecars(planned: Vec<Result<PlannedAction, PlexisortError>>, options: &SidecarOptions) -> Vec<Result<PlannedAction, PlexisortError>> {
+    if !options.enabled {
+        return planned;
+    }
+    let sources: Vec<Option<&Path>> = planned.iter().map(|action| action.as_ref().ok().map(|action| action.source.as_path())).collect();
+    let key = |path: &Path, name: &str| (path.parent().map(Path::to_path_buf), name.to_lowercase());
+
+    // The photos and videos sharing a stem, images first
+    let mut media: HashMap<_, Vec<(bool, usize)>> = HashMap::new();
+    for (index, source) in sources.iter().enumerate() {
+        let Some(source) = source else { continue };
+        let is_image = match extension_mime(source) {
+            Some(mime) if mime.starts_with("image/") => true,
+            Some(mime) if mime.starts_with("video/") => false,
+            _ => continue,
+        };
+        let stem = source.file_stem().unwrap_or_default().to_string_lossy();
+        media.entry(key(source, &stem)).or_default().push((!is_image, index));
+    }
+    let mut primary_of: HashMap<usize, usize> = HashMap::new();
+    let mut primaries_by_name = HashMap::new();
+    for members in media.values_mut() {
+        members.sort();
+        let primary = members[0].1;
+        for &(_, index) in members.iter() {
+            let source = sources[index].expect("Only planned files are grouped");
+            primaries_by_name.insert(key(source, &source.file_stem().unwrap_or_default().to_string_lossy()), primary);
+            primaries_by_name.insert(key(source, &source.file_name().unwrap_or_default().to_string_lossy()), primary);
+            if index != primary {
+                primary_of.insert(index, primary);
+            }
+        }
+    }
+
+    // A sidecar is named after the stem or the full name of its file, e.g.
+    // `IMG_0001.JPG.supplemental-metadata.json`
+    for (index, source) in sources.iter().enumerate() {
+        let Some(source) = source.filter(|source| options.is_sidecar(source)) else { continue };
+        let mut name = source.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        loop {
+            if let Some(&primary) = primaries_by_name.get(&key(source, &name)) {
+                if primary != index {
+                    primary_of.insert(index, primary);
+                }
+                break;
+            }
+            match name.rsplit_once('.') {
+                Some((shorter, _)) => name = shorter.to_string(),
+                None => break,
+            }
+        }
+    }
+    if primary_of.is_empty() {
+        return planned;
+    }
+
+    let mut followers: HashMap<usize, Vec<PlannedAction>> = HashMap::new();
+    let mut kept = Vec::new();
+    for (index, action) in planned.into_iter().enumerate() {
+        match (primary_of.get(&index), action) {
+            (Some(&primary), Ok(action)) => followers.entry(primary).or_default().push(action),
+            (_, action) => kept.push((index, action)),
+        }
+    }
+    let mut grouped = Vec::new();
+    for (index, action) in kept {
+        let group = followers.remove(&index).unwrap_or_default();
+        let group: Vec<_> = match &action {
+            Ok(primary) => group.into_iter().map(|follower| Ok(follow(follower, primary))).collect(),
+            Err(_) => group.into_iter().map(Ok).collect(),
+        };
+        grouped.push(action);
+        grouped.extend(group);
+    }
+    grouped
+}
+
+/// Lets the sidecars among `planned` whose photo or video is not part of the
+/// plan follow that file if it was organized earlier, as recorded in
+/// `placements`. In watch mode this files an `.xmp` that is written some time
+/// after its photo next to the photo.
+pub fn follow_placed(planned: Vec<Result<PlannedAction, PlexisortError>>, placements: &Placements, options: &SidecarOptions) -> Vec<Result<PlannedAction, PlexisortError>> {
+    if !options.enabled || placements.is_empty() {
+        return planned;
+    }
+    planned
+        .into_iter()
+        .map(|action| match action {
+            Ok(action) if action.follows.is_none() && options.is_sidecar(&action.source) => {
+                let Some(primary) = find_primary(&action.source, placements.keys().map(PathBuf::as_path)) else {
+                    return Ok(action);
+                };
+                Ok(PlannedAction {
+                    destination: sidecar_destination(&action.source, primary, &placements[primary]),
+                    reason: format!("Travels with {}", primary.file_name().unwrap_or_default().to_string_lossy()),
+                    follows: Some(primary.to_path_buf()),
+                    ..action
+                })
+            }
+            action => action,
+        })
+        .collect()
+}
+
+/// The photo or video among `files` that `sidecar` belongs to: one in the
+/// same directory whose stem or full name the sidecar is named after, as in
+/// `group_sidecars`. Images win over videos.
+pub fn find_primary<'a>(sidecar: &Path, files: impl IntoIterator<Item = &'a Path>) -> Option<&'a Path> {
+    let mut media: Vec<(bool, &Path)> = files
+        .into_iter()
+        .filter(|file| *file != sidecar && file.parent() == sidecar.parent())
+        .filter_map(|file| match extension_mime(file) {
+            Some(mime) if mime.starts_with("image/") => Some((false, file)),
+            Some(mime) if mime.starts_with("video/") => Some((true, file)),
+            _ => None,
+        })
+        .collect();
+    media.sort();
+    let named = |file: &Path, name: &str| {
+        [file.file_stem(), file.file_name()].iter().any(|part| part.unwrap_or_default().to_string_lossy().eq_ignore_ascii_case(name))
+    };
+
+    let mut name = sidecar.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    loop {
+        if let Some((_, file)) = media.iter().find(|(_, file)| named(file, &name)) {
+            return Some(file);
+        }
+        let (shorter, _) = name.rsplit_once('.')?;
+        name = shorter.to_string();
+    }
+}
+
+/// Plans `follower` to go wherever `primary` goes.
+fn follow(follower: PlannedAction, primary: &PlannedAction) -> PlannedAction {
+    PlannedAction {
+        destination: sidecar_destination(&follower.source, &primary.source, &primary.destination),
+        reason: format!("Travels with {}", primary.source.file_name().unwrap_or_default().to_string_lossy()),
+        category: primary.category.clone(),
+        follows: Some(primary.source.clone()),
+        ..follower
+    }
+}
+
+/// Where the file at `source`, which belongs to the file at `primary`, goes
+/// when that file goes to `primary_target`: next to it, with the part of the
+/// name it shares with the primary file renamed like that file. For example
+/// `IMG_0001.xmp` follows `IMG_0001.CR2` to `2023/IMG_0001 (1).cr2` as
+/// `2023/IMG_0001 (1).xmp`.
+pub fn sidecar_destination(source: &Path, primary: &Path, primary_target: &Path) -> PathBuf {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    let part = |path: &Path, whole: bool| {
+        let part = if whole { path.file_name() } else { path.file_stem() };
+        part.unwrap_or_default().to_string_lossy().into_owned()
+    };
+    let renamed = [true, false].into_iter().find_map(|whole| {
+        let shared = part(primary, whole);
+        let rest = name.get(shared.len()..).filter(|rest| name[..shared.len()].eq_ignore_ascii_case(&shared) && rest.starts_with('.'))?;
+        Some(format!("{}{}", part(primary_target, whole), rest))
+    });
+    primary_target.with_file_name(renamed.unwrap_or_else(|| name.into_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::Metadata;
+
+    fn planned(source: &str, destination: &str) -> Result<PlannedAction, PlexisortError> {
+        Ok(PlannedAction {
+            source: PathBuf::from(source),
+            destination: PathBuf::from(destination),
+            reason: "by file type".to_string(),
+            metadata: Metadata::new(),
+            processor: "GenericProcessor".to_string(),
+            category: destination.split('/').nth(2).unwrap_or_default().to_string(),
+            rule: None,
+            notes: Vec::new(),
+            follows: None,
+        })
+    }
+
+    #[test]
+    fn test_files_sharing_a_name_are_grouped() {
+        let plan = vec![
+            planned("/card/IMG_0001.CR2", "/library/Images/2023/IMG_0001.CR2"),
+            planned("/card/IMG_0001.JPG", "/library/Images/2024/IMG_0001.JPG"),
+            planned("/card/IMG_0001.JPG.supplemental-metadata.json", "/library/Documents/IMG_0001.JPG.supplemental-metadata.json"),
+            planned("/card/IMG_0002.HEIC", "/library/Images/2023/IMG_0002.HEIC"),
+            planned("/card/IMG_0002.MOV", "/library/Videos/2023/IMG_0002.MOV"),
+            planned("/card/img_0001.xmp", "/library/Other_Files/img_0001.xmp"),
+            planned("/card/notes.json", "/library/Documents/notes.json"),
+            planned("/other/IMG_0001.xmp", "/library/Other_Files/IMG_0001.xmp"),
+        ];
+        let grouped: Vec<PlannedAction> = group_sidecars(plan, &SidecarOptions::default()).into_iter().map(Result::unwrap).collect();
+
+        let placed: Vec<(&str, &str, Option<&str>)> = grouped
+            .iter()
+            .map(|action| (action.source.to_str().unwrap(), action.destination.to_str().unwrap(), action.follows.as_deref().and_then(Path::to_str)))
+            .collect();
+        assert_eq!(
+            placed,
+            vec![
+                ("/card/IMG_0001.CR2", "/library/Images/2023/IMG_0001.CR2", None),
+                ("/card/IMG_0001.JPG", "/library/Images/2023/IMG_0001.JPG", Some("/card/IMG_0001.CR2")),
+                ("/card/IMG_0001.JPG.supplemental-metadata.json", "/library/Images/2023/IMG_0001.JPG.supplemental-metadata.json", Some("/card/IMG_0001.CR2")),
+                ("/card/img_0001.xmp", "/library/Images/2023/IMG_0001.xmp", Some("/card/IMG_0001.CR2")),
+                ("/card/IMG_0002.HEIC", "/library/Images/2023/IMG_0002.HEIC", None),
+                ("/card/IMG_0002.MOV", "/library/Images/2023/IMG_0002.MOV", Some("/card/IMG_0002.HEIC")),
+                ("/card/notes.json", "/library/Documents/notes.json", None),
+                ("/other/IMG_0001.xmp", "/library/Other_Files/IMG_0001.xmp", None),
+            ]
+        );
+        assert_eq!(grouped[3].category, "Images", "Sidecars are counted with their file.");
+
+        let disabled = SidecarOptions { enabled: false, ..SidecarOptions::default() };
+        let plan = vec![planned("/card/IMG_0001.JPG", "/library/Images/IMG_0001.JPG"), planned("/card/IMG_0001.xmp", "/library/Other_Files/IMG_0001.xmp")];
+        let kept = group_sidecars(plan, &disabled);
+        assert_eq!(kept[1].as_ref().unwrap().destination, Path::new("/library/Other_Files/IMG_0001.xmp"));
+
+        let files = [Path::new("/card/IMG_0001.MOV"), Path::new("/card/IMG_0001.JPG"), Path::new("/other/IMG_0001.JPG")];
+        assert_eq!(find_primary(Path::new("/card/img_0001.JPG.json"), files), Some(Path::new("/card/IMG_0001.JPG")));
+        assert_eq!(find_primary(Path::new("/card/IMG_0002.xmp"), files), None);
+    }
+
+    #[test]
+    fn test_sidecars_are_renamed_like_their_file() {
+        let target = Path::new("/library/2023/20230114_IMG_0001 (1).jpg");
+        assert_eq!(sidecar_destination(Path::new("/card/IMG_0001.xmp"), Path::new("/card/IMG_0001.JPG"), target), Path::new("/library/2023/20230114_IMG_0001 (1).xmp"));
+        assert_eq!(sidecar_destination(Path::new("/card/IMG_0001.JPG.json"), Path::new("/card/IMG_0001.JPG"), target), Path::new("/library/2023/20230114_IMG_0001 (1).jpg.json"));
+        assert_eq!(sidecar_destination(Path::new("/card/IMG_0001.CR2"), Path::new("/card/IMG_0001.JPG"), target), Path::new("/library/2023/20230114_IMG_0001 (1).CR2"));
+        assert_eq!(sidecar_destination(Path::new("/card/IMG_00010.xmp"), Path::new("/card/IMG_0001.JPG"), target), Path::new("/library/2023/IMG_00010.xmp"), "Only whole name parts are shared.");
+    }
+}
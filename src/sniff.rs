@@ -0,0 +1,319 @@
+use mime_guess::from_path;
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Number of leading bytes read to recognize a file's format.
+const SNIFF_BYTES: u64 = 8 * 1024;
+
+/// Types of the camera raw and HEIF extensions that `mime_guess` does not know.
+const EXTRA_EXTENSIONS: &[(&str, &str)] = &[
+    ("heic", "image/heic"),
+    ("heif", "image/heif"),
+    ("cr2", "image/x-canon-cr2"),
+    ("cr3", "image/x-canon-cr3"),
+    ("nef", "image/x-nikon-nef"),
+    ("nrw", "image/x-nikon-nrw"),
+    ("arw", "image/x-sony-arw"),
+    ("srw", "image/x-samsung-srw"),
+    ("dng", "image/x-adobe-dng"),
+    ("orf", "image/x-olympus-orf"),
+    ("rw2", "image/x-panasonic-rw2"),
+    ("raf", "image/x-fuji-raf"),
+    ("pef", "image/x-pentax-pef"),
+];
+
+/// Which source of a file's type wins when both are known.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TypePrecedence {
+    /// The type recognized from the file's contents wins over its extension.
+    #[default]
+    Content,
+    /// The extension wins; the contents are only looked at for files whose
+    /// extension is missing or unknown.
+    Extension,
+}
+
+impl TypePrecedence {
+    pub const NAMES: &'static [&'static str] = &["content", "extension"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "content" => Some(TypePrecedence::Content),
+            "extension" => Some(TypePrecedence::Extension),
+            _ => None,
+        }
+    }
+}
+
+/// A format recognized from a file's leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sniffed {
+    pub mime: &'static str,
+    /// The usual extension of the format, without the dot.
+    pub extension: &'static str,
+    /// Container formats such as ZIP are the basis of many others (JAR, EPUB,
+    /// ...), so a more specific extension is trusted over them.
+    pub generic: bool,
+}
+
+const fn format(mime: &'static str, extension: &'static str) -> Option<Sniffed> {
+    Some(Sniffed { mime, extension, generic: false })
+}
+
+const fn container(mime: &'static str, extension: &'static str) -> Option<Sniffed> {
+    Some(Sniffed { mime, extension, generic: true })
+}
+
+/// Recognizes the format of a file from its leading bytes.
+pub fn sniff(path: &Path) -> io::Result<Option<Sniffed>> {
+    let mut header = Vec::with_capacity(SNIFF_BYTES as usize);
+    File::open(path)?.take(SNIFF_BYTES).read_to_end(&mut header)?;
+    Ok(sniff_bytes(&header))
+}
+
+/// Recognizes a format from the leading bytes of a file.
+pub fn sniff_bytes(header: &[u8]) -> Option<Sniffed> {
+    let starts = |signature: &[u8]| header.starts_with(signature);
+    let at = |offset: usize, signature: &[u8]| header.get(offset..offset + signature.len()) == Some(signature);
+
+    if starts(b"\xFF\xD8\xFF") {
+        return format("image/jpeg", "jpg");
+    }
+    if starts(b"\x89PNG\r\n\x1A\n") {
+        return format("image/png", "png");
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return format("image/gif", "gif");
+    }
+    if starts(b"II*\0\x10\0\0\0CR") {
+        return format("image/x-canon-cr2", "cr2");
+    }
+    if starts(b"II*\0") || starts(b"MM\0*") {
+        // Most raw formats are TIFF files, too
+        return format("image/tiff", "tif");
+    }
+    if starts(b"IIRO") || starts(b"IIRS") || starts(b"MMOR") {
+        return format("image/x-olympus-orf", "orf");
+    }
+    if starts(b"IIU\0") {
+        return format("image/x-panasonic-rw2", "rw2");
+    }
+    if starts(b"FUJIFILMCCD-RAW") {
+        return format("image/x-fuji-raf", "raf");
+    }
+    if starts(b"RIFF") {
+        return match header.get(8..12) {
+            Some(b"WEBP") => format("image/webp", "webp"),
+            Some(b"AVI ") => format("video/x-msvideo", "avi"),
+            Some(b"WAVE") => format("audio/wav", "wav"),
+            _ => None,
+        };
+    }
+    if at(4, b"ftyp") {
+        return sniff_ftyp(header.get(8..12).unwrap_or_default());
+    }
+    if starts(b"\x1A\x45\xDF\xA3") {
+        // The EBML header names the document type early on
+        return if contains(header, b"webm") {
+            format("video/webm", "webm")
+        } else {
+            format("video/x-matroska", "mkv")
+        };
+    }
+    if starts(b"%PDF-") {
+        return format("application/pdf", "pdf");
+    }
+    if starts(b"PK\x03\x04") {
+        return sniff_zip(header);
+    }
+    if starts(b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1") {
+        // Compound files hold .doc, .xls and .ppt alike
+        return container("application/x-ole-storage", "doc");
+    }
+    if starts(b"\x1F\x8B") {
+        return format("application/gzip", "gz");
+    }
+    if starts(b"7z\xBC\xAF\x27\x1C") {
+        return format("application/x-7z-compressed", "7z");
+    }
+    if starts(b"Rar!\x1A\x07") {
+        return format("application/vnd.rar", "rar");
+    }
+    if starts(b"BZh") {
+        return format("application/x-bzip2", "bz2");
+    }
+    if starts(b"\xFD7zXZ\0") {
+        return format("application/x-xz", "xz");
+    }
+    if at(257, b"ustar") {
+        return format("application/x-tar", "tar");
+    }
+    if starts(b"\x7FELF") {
+        return format("application/x-executable", "elf");
+    }
+    if starts(b"ID3") || starts(b"\xFF\xFB") || starts(b"\xFF\xF3") || starts(b"\xFF\xF2") {
+        return format("audio/mpeg", "mp3");
+    }
+    if starts(b"fLaC") {
+        return format("audio/flac", "flac");
+    }
+    if starts(b"OggS") {
+        return format("audio/ogg", "ogg");
+    }
+    None
+}
+
+/// ISO base media files (MP4, QuickTime, HEIF, ...) name their flavour in the
+/// major brand of the `ftyp` box.
+fn sniff_ftyp(brand: &[u8]) -> Option<Sniffed> {
+    match brand {
+        b"heic" | b"heix" | b"heim" | b"heis" | b"hevc" | b"hevx" => format("image/heic", "heic"),
+        b"mif1" | b"msf1" => format("image/heif", "heif"),
+        b"avif" | b"avis" => format("image/avif", "avif"),
+        b"crx " => format("image/x-canon-cr3", "cr3"),
+        b"qt  " => format("video/quicktime", "mov"),
+        b"M4A " | b"M4B " => format("audio/mp4", "m4a"),
+        b"3gp4" | b"3gp5" | b"3gp6" | b"3g2a" => format("video/3gpp", "3gp"),
+        // Generic brands such as `isom` or `mp42` are used for audio-only files, too
+        _ => container("video/mp4", "mp4"),
+    }
+}
+
+/// ZIP archives are also the container of Office Open XML, OpenDocument and
+/// EPUB files, which name their parts near the start of the archive.
+fn sniff_zip(header: &[u8]) -> Option<Sniffed> {
+    if contains(header, b"mimetypeapplication/epub+zip") {
+        return format("application/epub+zip", "epub");
+    }
+    if contains(header, b"mimetypeapplication/vnd.oasis.opendocument.text") {
+        return format("application/vnd.oasis.opendocument.text", "odt");
+    }
+    if contains(header, b"mimetypeapplication/vnd.oasis.opendocument.spreadsheet") {
+        return format("application/vnd.oasis.opendocument.spreadsheet", "ods");
+    }
+    if contains(header, b"[Content_Types].xml") || contains(header, b"_rels/.rels") {
+        if contains(header, b"word/") {
+            return format("application/vnd.openxmlformats-officedocument.wordprocessingml.document", "docx");
+        }
+        if contains(header, b"xl/") {
+            return format("application/vnd.openxmlformats-officedocument.spreadsheetml.sheet", "xlsx");
+        }
+        if contains(header, b"ppt/") {
+            return format("application/vnd.openxmlformats-officedocument.presentationml.presentation", "pptx");
+        }
+    }
+    container("application/zip", "zip")
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+/// A file's type, as decided from its extension and contents.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileType {
+    pub mime: String,
+    /// The extension the file is classified by, lowercase and without the dot.
+    /// It differs from the file's own extension when the contents won.
+    pub extension: String,
+    /// How the contents contradict the file's extension, if they do. Such
+    /// files are reported whichever `TypePrecedence` is configured.
+    pub mismatch: Option<String>,
+}
+
+/// Decides the type of a file. The contents are used when the extension is
+/// missing or unknown; when both are known, `precedence` picks one.
+pub fn detect_type(path: &Path, precedence: TypePrecedence) -> FileType {
+    let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("").to_lowercase();
+    let by_extension = extension_mime(path);
+    let sniffed = sniff_file(path);
+
+    let mismatch = match (&sniffed, &by_extension) {
+        (Some(sniffed), Some(mime)) if !compatible(sniffed, mime) => {
+            Some(format!("looks like {} ({}) but its extension suggests {}", sniffed.mime, sniffed.extension, mime))
+        }
+        _ => None,
+    };
+    let (mime, extension) = match (sniffed, by_extension) {
+        (_, Some(mime)) if precedence == TypePrecedence::Extension => (mime, extension),
+        (Some(sniffed), Some(mime)) if compatible(&sniffed, &mime) => (mime, extension),
+        (Some(sniffed), _) => (sniffed.mime.to_string(), sniffed.extension.to_string()),
+        (None, Some(mime)) => (mime, extension),
+        (None, None) => (mime::APPLICATION_OCTET_STREAM.to_string(), extension),
+    };
+    FileType { mime, extension, mismatch }
+}
+
+/// The type of a file by its extension alone.
+pub fn extension_mime(path: &Path) -> Option<String> {
+    from_path(path).first().map(|mime| mime.essence_str().to_string()).or_else(|| {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        EXTRA_EXTENSIONS.iter().find(|(known, _)| *known == extension).map(|(_, mime)| mime.to_string())
+    })
+}
+
+fn sniff_file(path: &Path) -> Option<Sniffed> {
+    sniff(path).unwrap_or_else(|e| {
+        log::debug!("Could not read {} to detect its type: {}", path.display(), e);
+        None
+    })
+}
+
+/// Whether the extension's type agrees with the contents closely enough to be
+/// kept, e.g. a JPEG named `.png` is still an image.
+fn compatible(sniffed: &Sniffed, mime: &str) -> bool {
+    let top_level = |mime: &str| mime.split('/').next().unwrap_or("").to_string();
+    sniffed.generic
+        || sniffed.mime == mime
+        || (top_level(sniffed.mime) == top_level(mime) && top_level(mime) != "application")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sniff_signatures() {
+        assert_eq!(sniff_bytes(b"\xFF\xD8\xFF\xE1\0\0Exif").unwrap().mime, "image/jpeg");
+        assert_eq!(sniff_bytes(b"\0\0\0\x18ftypheic\0\0\0\0").unwrap().mime, "image/heic");
+        assert_eq!(sniff_bytes(b"\0\0\0\x14ftypqt  \0\0\0\0").unwrap().extension, "mov");
+        assert_eq!(sniff_bytes(b"II*\0\x10\0\0\0CR\x02\0").unwrap().extension, "cr2");
+        assert_eq!(sniff_bytes(b"IIU\0\x18\0\0\0").unwrap().mime, "image/x-panasonic-rw2");
+        assert_eq!(sniff_bytes(b"%PDF-1.7\n").unwrap().extension, "pdf");
+        assert_eq!(sniff_bytes(b"PK\x03\x04....[Content_Types].xml....word/document.xml").unwrap().extension, "docx");
+        assert_eq!(sniff_bytes(b"PK\x03\x04....META-INF/MANIFEST.MF").unwrap().extension, "zip");
+        assert_eq!(sniff_bytes(b"\x7FELF\x02\x01\x01").unwrap().mime, "application/x-executable");
+        assert_eq!(sniff_bytes(b"Just some text"), None);
+    }
+
+    #[test]
+    fn test_detect_type_precedence() {
+        let temp_dir = tempdir().unwrap();
+        let disguised_pdf = temp_dir.path().join("report.txt");
+        let jpeg_without_extension = temp_dir.path().join("download");
+        let jar = temp_dir.path().join("tool.jar");
+        fs::write(&disguised_pdf, b"%PDF-1.4\n").unwrap();
+        fs::write(&jpeg_without_extension, b"\xFF\xD8\xFF\xE0\0\x10JFIF").unwrap();
+        fs::write(&jar, b"PK\x03\x04....META-INF/MANIFEST.MF").unwrap();
+
+        let detected = detect_type(&disguised_pdf, TypePrecedence::Content);
+        assert_eq!((detected.mime.as_str(), detected.extension.as_str()), ("application/pdf", "pdf"));
+        assert_eq!(detect_type(&disguised_pdf, TypePrecedence::Extension).mime, "text/plain");
+
+        for precedence in [TypePrecedence::Content, TypePrecedence::Extension] {
+            assert_eq!(detect_type(&jpeg_without_extension, precedence).mime, "image/jpeg");
+        }
+        assert_eq!(detect_type(&jar, TypePrecedence::Content).extension, "jar", "A ZIP container must not override a specific extension.");
+
+        for precedence in [TypePrecedence::Content, TypePrecedence::Extension] {
+            assert!(detect_type(&disguised_pdf, precedence).mismatch.unwrap().contains("application/pdf"));
+            assert_eq!(detect_type(&jpeg_without_extension, precedence).mismatch, None);
+            assert_eq!(detect_type(&jar, precedence).mismatch, None);
+        }
+    }
+}
@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Decides when a file counts as still being written, so that it is left
+/// alone instead of being moved half-finished.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct StabilityOptions {
+    /// Seconds that must have passed since a file was last modified. Zero
+    /// turns the check off.
+    pub quiet_period: u64,
+    /// File name endings of downloads and copies in progress.
+    pub temp_suffixes: Vec<String>,
+    /// Whether files that a process holds open for writing are left alone.
+    /// Checking this reads `/proc`, so it only works on Linux and only sees
+    /// the processes of the current user unless run as root.
+    pub check_open_files: bool,
+}
+
+impl Default for StabilityOptions {
+    fn default() -> Self {
+        StabilityOptions {
+            quiet_period: 0,
+            temp_suffixes: [".part", ".crdownload", ".download", ".tmp"].iter().map(|s| s.to_string()).collect(),
+            check_open_files: false,
+        }
+    }
+}
+
+/// Why a file is not settled yet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Unsettled {
+    /// The name ends like a download or copy in progress, e.g. `.part`. Such
+    /// files are renamed once they are complete.
+    TemporaryName(String),
+    /// The file was modified within the quiet period.
+    RecentlyModified(Duration),
+    /// A process holds the file open for writing.
+    OpenForWriting,
+}
+
+impl fmt::Display for Unsettled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Unsettled::TemporaryName(suffix) => write!(f, "temporary {} file, probably still being downloaded", suffix),
+            Unsettled::RecentlyModified(age) => write!(f, "modified {}s ago, probably still being written", age.as_secs()),
+            Unsettled::OpenForWriting => write!(f, "open for writing by another process"),
+        }
+    }
+}
+
+/// Checks files against `StabilityOptions`. The files held open for writing
+/// are looked up once when the gate is created, so a gate is meant to be used
+/// for one batch of files.
+pub struct StabilityGate<'a> {
+    options: &'a StabilityOptions,
+    open_for_writing: HashSet<PathBuf>,
+}
+
+impl<'a> StabilityGate<'a> {
+    pub fn new(options: &'a StabilityOptions) -> Self {
+        let open_for_writing = if options.check_open_files { files_open_for_writing() } else { HashSet::new() };
+        StabilityGate { options, open_for_writing }
+    }
+
+    /// Returns why `path` should be left alone for now, if it should.
+    pub fn check(&self, path: &Path, now: SystemTime) -> Option<Unsettled> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if let Some(suffix) = self.options.temp_suffixes.iter().find(|suffix| name.ends_with(&suffix.to_lowercase())) {
+            return Some(Unsettled::TemporaryName(suffix.clone()));
+        }
+
+        let quiet = Duration::from_secs(self.options.quiet_period);
+        if !quiet.is_zero() {
+            let age = fs::metadata(path).and_then(|m| m.modified()).ok().and_then(|modified| now.duration_since(modified).ok());
+            // A modification time in the future counts as recent
+            let age = age.unwrap_or_default();
+            if age < quiet {
+                return Some(Unsettled::RecentlyModified(age));
+            }
+        }
+
+        if !self.open_for_writing.is_empty() && fs::canonicalize(path).is_ok_and(|path| self.open_for_writing.contains(&path)) {
+            return Some(Unsettled::OpenForWriting);
+        }
+        None
+    }
+}
+
+/// The files that some process has open for writing, according to the
+/// `/proc/<pid>/fd` links and the access mode in `/proc/<pid>/fdinfo`.
+/// Processes whose descriptors cannot be read are passed over.
+fn files_open_for_writing() -> HashSet<PathBuf> {
+    const O_ACCMODE: u32 = 0o3;
+    const O_RDONLY: u32 = 0o0;
+
+    let mut files = HashSet::new();
+    let Ok(processes) = fs::read_dir("/proc") else {
+        return files;
+    };
+    for process in processes.flatten() {
+        if !process.file_name().to_string_lossy().bytes().all(|b| b.is_ascii_digit()) {
+            continue;
+        }
+        let Ok(descriptors) = fs::read_dir(process.path().join("fd")) else {
+            continue;
+        };
+        for descriptor in descriptors.flatten() {
+            let Ok(target) = fs::read_link(descriptor.path()) else {
+                continue;
+            };
+            let flags = fs::read_to_string(process.path().join("fdinfo").join(descriptor.file_name()))
+                .ok()
+                .and_then(|info| open_flags(&info));
+            if flags.is_some_and(|flags| flags & O_ACCMODE != O_RDONLY) {
+                files.insert(target);
+            }
+        }
+    }
+    files
+}
+
+/// The `flags:` line of an fdinfo file, which is in octal.
+fn open_flags(fdinfo: &str) -> Option<u32> {
+    let flags = fdinfo.lines().find_map(|line| line.strip_prefix("flags:"))?;
+    u32::from_str_radix(flags.trim(), 8).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_gate_leaves_unsettled_files_alone() {
+        let temp_dir = tempdir().unwrap();
+        let download = temp_dir.path().join("movie.mp4.crdownload");
+        let fresh = temp_dir.path().join("fresh.jpg");
+        let old = temp_dir.path().join("old.jpg");
+        for path in [&download, &fresh, &old] {
+            fs::write(path, "content").unwrap();
+        }
+        let now = SystemTime::now();
+        File::options().write(true).open(&old).unwrap().set_modified(now - Duration::from_secs(60)).unwrap();
+
+        let options = StabilityOptions { quiet_period: 10, ..StabilityOptions::default() };
+        let gate = StabilityGate::new(&options);
+        assert_eq!(gate.check(&download, now), Some(Unsettled::TemporaryName(".crdownload".to_string())));
+        assert!(matches!(gate.check(&fresh, now), Some(Unsettled::RecentlyModified(_))));
+        assert_eq!(gate.check(&old, now), None);
+
+        let defaults = StabilityOptions::default();
+        assert_eq!(StabilityGate::new(&defaults).check(&fresh, now), None, "Without a quiet period fresh files must be settled.");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gate_detects_files_open_for_writing() {
+        let temp_dir = tempdir().unwrap();
+        let writing = temp_dir.path().join("writing.mp4");
+        let reading = temp_dir.path().join("reading.mp4");
+        fs::write(&reading, "content").unwrap();
+        let _writer = File::create(&writing).unwrap();
+        let _reader = File::open(&reading).unwrap();
+
+        let options = StabilityOptions { check_open_files: true, ..StabilityOptions::default() };
+        let gate = StabilityGate::new(&options);
+        assert_eq!(gate.check(&writing, SystemTime::now()), Some(Unsettled::OpenForWriting));
+        assert_eq!(gate.check(&reading, SystemTime::now()), None);
+    }
+}
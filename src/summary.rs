@@ -0,0 +1,131 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::PlexisortError;
+use crate::organizer::Outcome;
+use crate::plan::PlannedAction;
+
+/// Exit code of a run in which every file was organized or deliberately skipped.
+pub const EXIT_SUCCESS: i32 = 0;
+/// Exit code of a run that could not start or was aborted, e.g. by an invalid config.
+pub const EXIT_FATAL: i32 = 1;
+/// Exit code of a run in which some files could not be organized.
+pub const EXIT_PARTIAL: i32 = 2;
+
+/// Category of files that were handled as duplicates before planning.
+pub const DUPLICATES_CATEGORY: &str = "Duplicates";
+/// Category of files placed by a `[[rules]]` entry. The rule itself is named
+/// in the report, and category templates never apply to these files.
+pub const RULES_CATEGORY: &str = "Rules";
+/// Category of files that were left alone because they are still being written.
+pub const UNSETTLED_CATEGORY: &str = "Unsettled";
+/// Category of files that could not be planned, e.g. because they vanished.
+pub const UNPLANNED_CATEGORY: &str = "Unplanned";
+
+/// What happened to one file of a run.
+#[derive(Debug)]
+pub struct FileOutcome {
+    pub source: PathBuf,
+    /// The plan the file was organized by; `None` for files handled before
+    /// planning, such as duplicates.
+    pub action: Option<PlannedAction>,
+    /// The category the file is counted under: that of its plan, or one of
+    /// the categories of files that were not planned.
+    pub category: String,
+    pub result: Result<Outcome, PlexisortError>,
+    /// Size of the file that was transferred to the destination.
+    pub bytes: u64,
+}
+
+/// The outcomes of all files of a run, in the order they were handled.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    pub files: Vec<FileOutcome>,
+}
+
+impl RunSummary {
+    /// Records the outcome of a file that was handled without a plan,
+    /// logging failures as they happen.
+    pub fn record(&mut self, source: &Path, category: &str, result: Result<Outcome, PlexisortError>) {
+        self.push(source, category, None, result);
+    }
+
+    /// Records the outcome of carrying out a planned action.
+    pub fn record_planned(&mut self, action: &PlannedAction, result: Result<Outcome, PlexisortError>) {
+        self.push(&action.source, &action.category, Some(action.clone()), result);
+    }
+
+    fn push(&mut self, source: &Path, category: &str, action: Option<PlannedAction>, result: Result<Outcome, PlexisortError>) {
+        if let Err(e) = &result {
+            log::error!("Failed to organize {}", e);
+        }
+        let bytes = match &result {
+            // A dry run leaves the file at its source
+            Ok(Outcome::Transferred(target)) => fs::symlink_metadata(target).or_else(|_| fs::metadata(source)).map(|m| m.len()).unwrap_or(0),
+            _ => 0,
+        };
+        self.files.push(FileOutcome { source: source.to_path_buf(), action, category: category.to_string(), result, bytes });
+    }
+
+    /// Records a failure that is not about a file being organized, e.g. one
+    /// that occurred while planning.
+    pub fn record_failure(&mut self, error: PlexisortError) {
+        let source = error.path().map(Path::to_path_buf).unwrap_or_default();
+        self.record(&source, UNPLANNED_CATEGORY, Err(error));
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &PlexisortError> {
+        self.files.iter().filter_map(|file| file.result.as_ref().err())
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        if self.failures().next().is_some() {
+            EXIT_PARTIAL
+        } else {
+            EXIT_SUCCESS
+        }
+    }
+
+    /// Prints how many files ended up how, followed by the files that were
+    /// skipped and every failure.
+    pub fn print(&self) {
+        let count = |matches: fn(&Outcome) -> bool| {
+            self.files.iter().filter(|file| file.result.as_ref().is_ok_and(matches)).count()
+        };
+        let failed = self.failures().count();
+
+        println!("Summary:");
+        println!("  {:<20} {}", "organized", count(|outcome| matches!(outcome, Outcome::Transferred(_))));
+        println!("  {:<20} {}", "duplicates removed", count(|outcome| matches!(outcome, Outcome::DroppedDuplicate(_))));
+        println!("  {:<20} {}", "duplicates linked", count(|outcome| matches!(outcome, Outcome::HardLinked(_))));
+        println!("  {:<20} {}", "skipped", count(|outcome| matches!(outcome, Outcome::Skipped(_))));
+        println!("  {:<20} {}", "failed", failed);
+        for file in &self.files {
+            if let Ok(Outcome::Skipped(reason)) = &file.result {
+                println!("  skipped {}: {}", file.source.display(), reason);
+            }
+        }
+        for error in self.failures() {
+            println!("  {} error: {}", error.kind(), error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    #[test]
+    fn test_exit_code_reflects_failures() {
+        let mut summary = RunSummary::default();
+        summary.record(Path::new("a.jpg"), "Images", Ok(Outcome::Transferred(PathBuf::from("Images/a.jpg"))));
+        summary.record(Path::new("b.jpg"), "Images", Ok(Outcome::Skipped("destination already exists".to_string())));
+        assert_eq!(summary.exit_code(), EXIT_SUCCESS);
+
+        let error = PlexisortError::from_io(Path::new("c.jpg"), io::Error::from(io::ErrorKind::PermissionDenied));
+        summary.record(Path::new("c.jpg"), "Images", Err(error));
+        assert_eq!(summary.exit_code(), EXIT_PARTIAL);
+        assert_eq!(summary.failures().count(), 1);
+    }
+}
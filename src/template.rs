@@ -0,0 +1,202 @@
+use std::path::{Component, Path, PathBuf};
+
+use crate::metadata::Metadata;
+
+/// Placeholders that name the file itself. A template whose last component
+/// uses none of them only describes the directory, and the original file name
+/// is appended.
+const FILE_NAME_FIELDS: &[&str] = &["filename", "stem"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Field { name: String, width: Option<usize> },
+}
+
+/// A destination path template such as `"{category}/{year}/{month:02}/{stem}.{ext}"`.
+///
+/// Placeholders are written as `{name}` and may carry a zero-padding width,
+/// e.g. `{month:02}`. Literal braces are written as `{{` and `}}`.
+#[derive(Debug, Clone)]
+pub struct PathTemplate {
+    segments: Vec<Segment>,
+    names_file: bool,
+}
+
+impl PathTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        if template.trim().is_empty() {
+            return Err("template must not be empty".to_string());
+        }
+        if template.starts_with('/') || template.starts_with('\\') {
+            return Err(format!("template must be a relative path: {}", template));
+        }
+
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some('{') | None => return Err(format!("unclosed placeholder in template: {}", template)),
+                            Some(c) => field.push(c),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(std::mem::take(&mut literal)));
+                    }
+                    segments.push(parse_field(&field)?);
+                }
+                '}' => return Err(format!("unmatched '}}' in template: {}", template)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+
+        let has_parent_dir = segments.iter().any(|s| match s {
+            Segment::Literal(text) => Path::new(text).components().any(|c| c == Component::ParentDir),
+            Segment::Field { .. } => false,
+        });
+        if has_parent_dir {
+            return Err(format!("template must not contain '..': {}", template));
+        }
+
+        let mut names_file = false;
+        for segment in &segments {
+            match segment {
+                Segment::Literal(text) if text.contains(['/', '\\']) => names_file = false,
+                Segment::Field { name, .. } if FILE_NAME_FIELDS.contains(&name.as_str()) => names_file = true,
+                _ => {}
+            }
+        }
+
+        Ok(PathTemplate { segments, names_file })
+    }
+
+    /// Expands the template into a relative path. Fields that are missing from
+    /// `metadata` (or empty) are replaced by `unknown`.
+    pub fn expand(&self, metadata: &Metadata, unknown: &str) -> PathBuf {
+        let mut expanded = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => expanded.push_str(text),
+                Segment::Field { name, width } => {
+                    let value = metadata
+                        .get(name)
+                        .map(|value| sanitize(value))
+                        .filter(|value| !value.is_empty())
+                        .unwrap_or_else(|| sanitize(unknown));
+                    match width {
+                        Some(width) => expanded.push_str(&format!("{:0>width$}", value, width = *width)),
+                        None => expanded.push_str(&value),
+                    }
+                }
+            }
+        }
+
+        // Drop empty, `.` and `..` components so that a field value can never
+        // escape the destination directory.
+        expanded
+            .split(['/', '\\'])
+            .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+            .collect()
+    }
+
+    /// Expands the template into the path of `path` relative to the destination
+    /// directory, appending the original file name when the template only
+    /// describes a directory.
+    pub fn expand_file_path(&self, path: &Path, metadata: &Metadata, unknown: &str) -> PathBuf {
+        let expanded = self.expand(metadata, unknown);
+        if self.names_file {
+            expanded
+        } else {
+            expanded.join(path.file_name().unwrap_or_default())
+        }
+    }
+}
+
+fn parse_field(field: &str) -> Result<Segment, String> {
+    let (name, spec) = match field.split_once(':') {
+        Some((name, spec)) => (name.trim(), Some(spec.trim())),
+        None => (field.trim(), None),
+    };
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return Err(format!("invalid placeholder name '{{{}}}'", field));
+    }
+    let width = match spec {
+        Some(spec) => Some(
+            spec.parse::<usize>()
+                .map_err(|_| format!("invalid format '{}' in placeholder '{{{}}}', expected a width such as '02'", spec, field))?,
+        ),
+        None => None,
+    };
+    Ok(Segment::Field { name: name.to_string(), width })
+}
+
+/// Makes a metadata value safe to use as (part of) a single path component.
+fn sanitize(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(fields: &[(&str, &str)]) -> Metadata {
+        fields.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_expand_with_padding_and_unknown() {
+        let template = PathTemplate::parse("{category}/{year}/{month:02}/{camera_make}/{stem}.{ext}").unwrap();
+        let fields = metadata(&[("category", "Images"), ("year", "2023"), ("month", "1"), ("stem", "IMG_0001"), ("ext", "jpg")]);
+
+        let expanded = template.expand_file_path(Path::new("/src/IMG_0001.jpg"), &fields, "Unknown");
+        assert_eq!(expanded, PathBuf::from("Images/2023/01/Unknown/IMG_0001.jpg"));
+    }
+
+    #[test]
+    fn test_directory_template_keeps_file_name() {
+        let template = PathTemplate::parse("Videos/{year}").unwrap();
+        let fields = metadata(&[("year", "2024")]);
+
+        let expanded = template.expand_file_path(Path::new("/src/clip.mp4"), &fields, "Unknown");
+        assert_eq!(expanded, PathBuf::from("Videos/2024/clip.mp4"));
+    }
+
+    #[test]
+    fn test_values_cannot_escape_destination() {
+        let template = PathTemplate::parse("{album}/{stem}.{ext}").unwrap();
+        let fields = metadata(&[("album", "../../etc"), ("stem", "track"), ("ext", "mp3")]);
+
+        assert_eq!(template.expand(&fields, "Unknown"), PathBuf::from(".._.._etc/track.mp3"));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(PathTemplate::parse("{year").is_err());
+        assert!(PathTemplate::parse("{month:xx}").is_err());
+        assert!(PathTemplate::parse("../{year}").is_err());
+        assert!(PathTemplate::parse("/abs/{year}").is_err());
+        assert!(PathTemplate::parse("{{literal}}/{year}").is_ok());
+    }
+}
@@ -0,0 +1,101 @@
+use crate::dates::DateResolver;
+use crate::metadata::Metadata;
+use crate::plan::PlannedAction;
+use crate::template::PathTemplate;
+use crate::traits::processor::Processor;
+use std::path::{Path, PathBuf};
+
+
+/// Wraps a built-in processor and files its output according to the path
+/// template configured for the processor's category.
+pub struct TemplateProcessor {
+    pub inner: Box<dyn Processor>,
+    pub template: PathTemplate,
+    pub unknown_token: String,
+}
+
+impl TemplateProcessor {
+    fn relative_path(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        self.template.expand_file_path(path, metadata, &self.unknown_token)
+    }
+}
+
+impl Processor for TemplateProcessor {
+    fn plan(&self, path: &Path, destination: &Path) -> PlannedAction {
+        let metadata = self.metadata(path);
+        PlannedAction {
+            source: path.to_path_buf(),
+            destination: destination.join(self.relative_path(path, &metadata)),
+            reason: format!("template for {}", self.category()),
+            metadata,
+            processor: self.name().to_string(),
+            category: self.category().to_string(),
+            rule: None,
+            notes: Vec::new(),
+            follows: None,
+        }
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn get_destination_subfolder(&self, path: &Path, metadata: &Metadata) -> PathBuf {
+        self.relative_path(path, metadata).parent().map(Path::to_path_buf).unwrap_or_default()
+    }
+
+    fn category(&self) -> &str {
+        self.inner.category()
+    }
+
+    fn dates(&self) -> &DateResolver {
+        self.inner.dates()
+    }
+
+    fn metadata(&self, path: &Path) -> Metadata {
+        let mut metadata = self.inner.metadata(path);
+        metadata.insert("category".to_string(), self.category().to_string());
+        metadata
+    }
+}
+
+
+#[cfg(test)]
+mod template_processor_tests {
+    use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
+    use crate::video_processor::VideoProcessor;
+    use chrono::{Datelike, Local};
+    use std::fs::{self, File};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_template_processor_live() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        let video_path = source_dir.join("Clip.MP4");
+        File::create(&video_path).unwrap();
+
+        let processor = TemplateProcessor {
+            inner: Box::new(VideoProcessor::default()),
+            template: PathTemplate::parse("{category}/{year}/{month:02}/{camera_make}/{stem}.{ext}").unwrap(),
+            unknown_token: "Unknown".to_string(),
+        };
+        let mut mode = ProcessingMode::Live;
+
+        execute(&processor.plan(&video_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
+
+        let now = Local::now();
+        let expected_destination = destination_dir
+            .join("Videos")
+            .join(now.year().to_string())
+            .join(format!("{:02}", now.month()))
+            .join("Unknown")
+            .join("Clip.mp4");
+        assert!(expected_destination.exists(), "File was not moved to the templated destination in Live mode.");
+    }
+}
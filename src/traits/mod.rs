@@ -1,61 +1,186 @@
-use std::{path::PathBuf, sync::Mutex};
+use std::path::Path;
+#[cfg(test)]
+use std::sync::Mutex;
 
+use crate::audio_processor::AudioProcessor;
 use crate::{compressed_file_processor::CompressedFileProcessor, document_processor::DocumentProcessor, image_processor::ImageProcessor, generic_processor::GenericProcessor, video_processor::VideoProcessor};
+use crate::dates::DateResolver;
+use crate::metadata::full_metadata;
+use crate::rule_processor::RuleProcessor;
+use crate::rules::{Rule, RuleSet};
+use crate::template::PathTemplate;
+use crate::template_processor::TemplateProcessor;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::sniff::{FileType, TypePrecedence};
 
 use self::processor::Processor;
-use mime_guess::from_path;
 
 pub mod processor;
 
+/// Categories of the built-in processors, as used for `[templates]` keys.
+pub const CATEGORIES: &[&str] = &["Images", "Videos", "Music", "Podcasts", "Voice_Memos", "Documents", "Compressed_Files", "Other_Files"];
 
-pub trait ProcessorFactory {
-    fn create_processor(&self, path: &PathBuf) -> Box<dyn Processor>;
+pub trait ProcessorFactory: Send + Sync {
+    /// Which of a file's extension and contents decides the `FileType` that
+    /// is passed to `create_processor`.
+    fn type_precedence(&self) -> TypePrecedence;
+    fn create_processor(&self, path: &Path, file_type: &FileType) -> Box<dyn Processor>;
 }
 
 
-pub struct DefaultProcessorFactory;
+/// Picks a built-in processor by the type of the file, as decided from its
+/// extension and contents.
+#[derive(Default)]
+pub struct DefaultProcessorFactory {
+    pub type_precedence: TypePrecedence,
+    pub dates: Arc<DateResolver>,
+}
 
 impl ProcessorFactory for DefaultProcessorFactory {
-    fn create_processor(&self, path: &PathBuf) -> Box<dyn Processor> {
-        let mime_type = from_path(path).first_or_octet_stream();
-        let file_extension = path.extension().unwrap_or_default().to_str().unwrap_or("").to_lowercase();
+    fn type_precedence(&self) -> TypePrecedence {
+        self.type_precedence
+    }
+
+    fn create_processor(&self, path: &Path, file_type: &FileType) -> Box<dyn Processor> {
+        let mime_type: mime::Mime = file_type.mime.parse().unwrap_or(mime::APPLICATION_OCTET_STREAM);
 
+        let dates = Arc::clone(&self.dates);
         match mime_type.type_() {
-            mime::IMAGE => Box::new(ImageProcessor),
-            mime::VIDEO => Box::new(VideoProcessor),
-            mime::TEXT => Box::new(DocumentProcessor),
-            mime::APPLICATION => match file_extension.as_str() {
-                "pdf" | "doc" | "docx" | "ppt" | "pptx" | "xlsx" | "xls" | "json" | "yml" => Box::new(DocumentProcessor),
-                "zip" | "tar" | "rar" | "7z" => Box::new(CompressedFileProcessor),
-                _ => Box::new(GenericProcessor),
+            mime::IMAGE => Box::new(ImageProcessor { dates }),
+            mime::VIDEO => Box::new(VideoProcessor { dates }),
+            mime::AUDIO => Box::new(AudioProcessor::for_file(path, &file_type.mime, dates)),
+            mime::TEXT => Box::new(DocumentProcessor { dates, extension: Some(file_type.extension.clone()) }),
+            mime::APPLICATION => match file_type.extension.as_str() {
+                "pdf" | "doc" | "docx" | "ppt" | "pptx" | "xlsx" | "xls" | "json" | "yml" => Box::new(DocumentProcessor { dates, extension: Some(file_type.extension.clone()) }),
+                "zip" | "tar" | "rar" | "7z" => Box::new(CompressedFileProcessor { dates }),
+                _ => Box::new(GenericProcessor { dates }),
             },
-            _ => Box::new(GenericProcessor),
+            _ => Box::new(GenericProcessor { dates }),
+        }
+    }
+}
+
+
+/// Routes files through the configured `[[rules]]` first and falls back to
+/// another factory (normally a `TemplateProcessorFactory`) when no rule
+/// matches. Files placed by a rule go exactly where the rule says.
+pub struct RuleProcessorFactory<F: ProcessorFactory> {
+    rules: RuleSet,
+    unknown_token: String,
+    dates: Arc<DateResolver>,
+    fallback: F,
+}
+
+impl<F: ProcessorFactory> RuleProcessorFactory<F> {
+    pub fn new(rules: &[Rule], unknown_token: &str, dates: Arc<DateResolver>, fallback: F) -> Result<Self, String> {
+        Ok(RuleProcessorFactory {
+            rules: RuleSet::new(rules)?,
+            unknown_token: unknown_token.to_string(),
+            dates,
+            fallback,
+        })
+    }
+}
+
+impl<F: ProcessorFactory> ProcessorFactory for RuleProcessorFactory<F> {
+    fn type_precedence(&self) -> TypePrecedence {
+        self.fallback.type_precedence()
+    }
+
+    fn create_processor(&self, path: &Path, file_type: &FileType) -> Box<dyn Processor> {
+        let fields = OnceCell::new();
+        let matched = self.rules.find_match(path, file_type, || fields.get_or_init(|| full_metadata(path, &self.dates, &file_type.mime)));
+        match matched {
+            Some((rule, destination)) => {
+                log::debug!("Rule '{}' matched {}", rule.label(), path.display());
+                Box::new(RuleProcessor {
+                    name: rule.label().to_string(),
+                    destination: destination.clone(),
+                    unknown_token: self.unknown_token.clone(),
+                    mime: file_type.mime.clone(),
+                    fields: fields.into_inner(),
+                    dates: Arc::clone(&self.dates),
+                })
+            }
+            None => self.fallback.create_processor(path, file_type),
         }
     }
 }
 
 
+/// Applies the `[templates]` config section: processors whose category has a
+/// template are wrapped so that their files are filed by the template.
+pub struct TemplateProcessorFactory<F: ProcessorFactory> {
+    templates: HashMap<String, PathTemplate>,
+    unknown_token: String,
+    inner: F,
+}
+
+impl<F: ProcessorFactory> TemplateProcessorFactory<F> {
+    pub fn new(templates: &HashMap<String, String>, unknown_token: &str, inner: F) -> Result<Self, String> {
+        let mut parsed = HashMap::new();
+        for (category, template) in templates {
+            let template = PathTemplate::parse(template)
+                .map_err(|e| format!("Invalid template for '{}': {}", category, e))?;
+            parsed.insert(category.to_lowercase(), template);
+        }
+        Ok(TemplateProcessorFactory {
+            templates: parsed,
+            unknown_token: unknown_token.to_string(),
+            inner,
+        })
+    }
+}
+
+impl<F: ProcessorFactory> ProcessorFactory for TemplateProcessorFactory<F> {
+    fn type_precedence(&self) -> TypePrecedence {
+        self.inner.type_precedence()
+    }
+
+    fn create_processor(&self, path: &Path, file_type: &FileType) -> Box<dyn Processor> {
+        let processor = self.inner.create_processor(path, file_type);
+        match self.templates.get(&processor.category().to_lowercase()) {
+            Some(template) => Box::new(TemplateProcessor {
+                inner: processor,
+                template: template.clone(),
+                unknown_token: self.unknown_token.clone(),
+            }),
+            None => processor,
+        }
+    }
+}
+
+
+#[cfg(test)]
 pub struct TestProcessorFactory {
     // Used in tests to check which processor was created last
     pub last_processor_type: Mutex<Option<String>>,
 }
 
+#[cfg(test)]
 impl ProcessorFactory for TestProcessorFactory {
-    fn create_processor(&self, path: &PathBuf) -> Box<dyn Processor> {
+    fn type_precedence(&self) -> TypePrecedence {
+        TypePrecedence::Extension
+    }
+
+    fn create_processor(&self, path: &Path, _file_type: &FileType) -> Box<dyn Processor> {
         let file_extension = path.extension().unwrap_or_default().to_str().unwrap_or("").to_lowercase();
         let processor = match file_extension.as_str() {
             "jpg" | "png" => {
                 self.last_processor_type.lock().unwrap().replace("ImageProcessor".to_string());
-                Box::new(ImageProcessor) as Box<dyn Processor>
+                Box::new(ImageProcessor::default()) as Box<dyn Processor>
             },
             "docx" | "txt" => {
                 self.last_processor_type.lock().unwrap().replace("DocumentProcessor".to_string());
-                Box::new(DocumentProcessor) as Box<dyn Processor>
+                Box::new(DocumentProcessor::default()) as Box<dyn Processor>
             },
             // Add other cases as necessary
             _ => {
                 self.last_processor_type.lock().unwrap().replace("UnknownProcessor".to_string());
-                Box::new(GenericProcessor) as Box<dyn Processor>
+                Box::new(GenericProcessor::default()) as Box<dyn Processor>
             },
         };
         processor
@@ -63,3 +188,66 @@ impl ProcessorFactory for TestProcessorFactory {
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::PlannedAction;
+    use crate::sniff::detect_type;
+    use std::fs;
+    use tempfile::tempdir;
+
+    fn plan(factory: &dyn ProcessorFactory, path: &Path) -> PlannedAction {
+        factory.create_processor(path, &detect_type(path, factory.type_precedence())).plan(path, Path::new("dest"))
+    }
+
+    #[test]
+    fn test_documents_are_filed_by_their_detected_type() {
+        let temp_dir = tempdir().unwrap();
+        let disguised_pdf = temp_dir.path().join("report.txt");
+        fs::write(&disguised_pdf, b"%PDF-1.4\n").unwrap();
+
+        let factory = DefaultProcessorFactory::default();
+        let action = plan(&factory, &disguised_pdf);
+        assert_eq!(action.destination, Path::new("dest/Documents/PDFs/report.txt"));
+        assert_eq!(action.metadata.get("ext").map(String::as_str), Some("pdf"));
+        assert_eq!(action.metadata.get("doc_type").map(String::as_str), Some("PDFs"));
+
+        let factory = DefaultProcessorFactory { type_precedence: TypePrecedence::Extension, ..DefaultProcessorFactory::default() };
+        let action = plan(&factory, &disguised_pdf);
+        assert_eq!(action.destination, Path::new("dest/Documents/Text_Files/report.txt"));
+    }
+
+    #[test]
+    fn test_rules_are_not_filed_by_category_templates() {
+        let temp_dir = tempdir().unwrap();
+        let readme = temp_dir.path().join("readme.md");
+        let notes = temp_dir.path().join("notes.txt");
+        fs::write(&readme, "# Readme").unwrap();
+        fs::write(&notes, "notes").unwrap();
+
+        let rules = vec![Rule { name: Some("documents".to_string()), extensions: vec!["md".to_string()], destination: "Notes".to_string(), ..Rule::default() }];
+        let templates = HashMap::from([("documents".to_string(), "Docs/{year}".to_string())]);
+        let templated = TemplateProcessorFactory::new(&templates, "Unknown", DefaultProcessorFactory::default()).unwrap();
+        let factory = RuleProcessorFactory::new(&rules, "Unknown", Arc::default(), templated).unwrap();
+
+        let action = plan(&factory, &readme);
+        assert_eq!(action.destination, Path::new("dest/Notes/readme.md"));
+        assert_eq!((action.category.as_str(), action.rule.as_deref()), ("Rules", Some("documents")));
+
+        let action = plan(&factory, &notes);
+        assert!(action.destination.starts_with("dest/Docs"), "Files that match no rule keep their category's template.");
+        assert_eq!(action.category, "Documents");
+    }
+
+    #[test]
+    fn test_rules_reuse_the_fields_they_were_matched_by() {
+        let photo = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/images/photo.jpg");
+        let conditions = std::collections::BTreeMap::from([("camera_make".to_string(), "Apple".to_string())]);
+        let rules = vec![Rule { metadata: conditions, destination: "Phone/{year}".to_string(), ..Rule::default() }];
+        let factory = RuleProcessorFactory::new(&rules, "Unknown", Arc::default(), DefaultProcessorFactory::default()).unwrap();
+
+        let action = plan(&factory, &photo);
+        assert_eq!(action.destination, Path::new("dest/Phone/2020/photo.jpg"));
+        assert_eq!(action.metadata.get("date_source").map(String::as_str), Some("exif_original"));
+    }
+}
@@ -1,8 +1,49 @@
-use std::path::PathBuf;
-use crate::processing_mode::ProcessingMode;
+use std::path::{Path, PathBuf};
+use crate::dates::DateResolver;
+use crate::metadata::{file_metadata, Embedded, Metadata};
+use crate::plan::PlannedAction;
 
-pub trait Processor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode);
-    fn get_destination_subfolder(&self, path: &PathBuf) -> PathBuf; // New method
-}
+/// Decides where a file goes. Processors only inspect files and return a
+/// `PlannedAction`; carrying it out is left to the executor, so that files
+/// can be planned on several threads and dry runs follow the same plan.
+pub trait Processor: Send + Sync {
+    /// The folder `path` goes to, relative to the destination directory.
+    /// `metadata` is what `Processor::metadata` collected for the file.
+    fn get_destination_subfolder(&self, path: &Path, metadata: &Metadata) -> PathBuf;
+
+    /// Plans to file `path` below `destination`, in the processor's
+    /// subfolder and under its own name.
+    fn plan(&self, path: &Path, destination: &Path) -> PlannedAction {
+        let metadata = self.metadata(path);
+        PlannedAction {
+            source: path.to_path_buf(),
+            destination: destination.join(self.get_destination_subfolder(path, &metadata)).join(path.file_name().unwrap_or_default()),
+            reason: format!("{} by file type", self.category()),
+            metadata,
+            processor: self.name().to_string(),
+            category: self.category().to_string(),
+            rule: None,
+            notes: Vec::new(),
+            follows: None,
+        }
+    }
+
+    /// Name of the processor for reports, by default its type name.
+    fn name(&self) -> &str {
+        let type_name = std::any::type_name::<Self>();
+        type_name.rsplit("::").next().unwrap_or(type_name)
+    }
 
+    /// Name of the category this processor files into, e.g. `Images`. It is
+    /// the value of the `{category}` placeholder and the key of the
+    /// category's entry in the `[templates]` config section.
+    fn category(&self) -> &str;
+
+    /// Decides which date the processor's files are filed by.
+    fn dates(&self) -> &DateResolver;
+
+    /// Metadata fields available to destination path templates.
+    fn metadata(&self, path: &Path) -> Metadata {
+        file_metadata(path, self.dates(), &Embedded::default())
+    }
+}
@@ -0,0 +1,330 @@
+use filetime::FileTime;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::journal::{temporary_path, COPY_ACTION, HARDLINK_ACTION, MOVE_ACTION, REFLINK_ACTION, SYMLINK_ACTION};
+
+/// Suffix of the temporary file that a copy or link is created as before it
+/// is renamed into place.
+pub const PARTIAL_SUFFIX: &str = "plexisort-partial";
+
+/// Files at least this large report their progress while being copied.
+const PROGRESS_THRESHOLD: u64 = 64 * 1024 * 1024;
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+
+/// How a file gets to its destination.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferMode {
+    /// Move the file, removing it from the source directory.
+    #[default]
+    Move,
+    /// Copy the file and leave the source untouched.
+    Copy,
+    /// Create a hard link to the source, which must be on the same file system.
+    Hardlink,
+    /// Create a symbolic link to the source.
+    Symlink,
+    /// Create a copy-on-write clone of the source (Btrfs, XFS, ...), or a
+    /// regular copy where the file system does not support it.
+    Reflink,
+}
+
+impl TransferMode {
+    pub const NAMES: &'static [&'static str] = &["move", "copy", "hardlink", "symlink", "reflink"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "move" => Some(TransferMode::Move),
+            "copy" => Some(TransferMode::Copy),
+            "hardlink" => Some(TransferMode::Hardlink),
+            "symlink" => Some(TransferMode::Symlink),
+            "reflink" => Some(TransferMode::Reflink),
+            _ => None,
+        }
+    }
+
+    /// The action that records a transfer in this mode in the journal.
+    pub fn action(self) -> &'static str {
+        match self {
+            TransferMode::Move => MOVE_ACTION,
+            TransferMode::Copy => COPY_ACTION,
+            TransferMode::Hardlink => HARDLINK_ACTION,
+            TransferMode::Symlink => SYMLINK_ACTION,
+            TransferMode::Reflink => REFLINK_ACTION,
+        }
+    }
+
+    /// Whether the source file is gone after the transfer.
+    pub fn removes_source(self) -> bool {
+        self == TransferMode::Move
+    }
+}
+
+/// Transfers `source` to `target` in the given mode. Except for moves, the new
+/// file or link is created under a temporary name and renamed into place, so
+/// `target` never holds a partial file.
+pub fn transfer_file(mode: TransferMode, source: &Path, target: &Path) -> io::Result<()> {
+    match mode {
+        TransferMode::Move => move_file(source, target),
+        TransferMode::Copy => copy_file(source, target),
+        TransferMode::Hardlink => create_in_place(target, |partial| fs::hard_link(source, partial)),
+        TransferMode::Symlink => {
+            let source = std::path::absolute(source)?;
+            create_in_place(target, |partial| symlink(&source, partial))
+        }
+        TransferMode::Reflink => match create_in_place(target, |partial| reflink_with_metadata(source, partial)) {
+            Err(e) if reflink_unsupported(&e) => {
+                if !REFLINK_FALLBACK_WARNED.swap(true, Ordering::Relaxed) {
+                    warn!("Reflinks are not supported for {} ({}), copying instead", target.display(), e);
+                }
+                copy_file(source, target)
+            }
+            result => result,
+        },
+    }
+}
+
+static REFLINK_FALLBACK_WARNED: AtomicBool = AtomicBool::new(false);
+
+/// Creates a file or link at a temporary path next to `target` and renames it
+/// into place, removing the temporary path if anything fails.
+fn create_in_place(target: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let partial = temporary_path(target, PARTIAL_SUFFIX);
+    let result = create(&partial).and_then(|_| fs::rename(&partial, target));
+    if result.is_err() && partial.symlink_metadata().is_ok() {
+        let _ = fs::remove_file(&partial);
+    }
+    result
+}
+
+#[cfg(unix)]
+fn symlink(source: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(source, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_source: &Path, _link: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are only supported on Unix"))
+}
+
+fn reflink_with_metadata(source: &Path, clone: &Path) -> io::Result<()> {
+    reflink(source, clone)?;
+    copy_metadata(source, clone)
+}
+
+/// Clones the contents of `source` into a new file `clone` with the FICLONE
+/// ioctl, which shares the data blocks until either file is changed.
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, clone: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = File::open(source)?;
+    let clone = File::create(clone)?;
+    // SAFETY: both file descriptors are open for the duration of the call
+    let result = unsafe { libc::ioctl(clone.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) };
+    if result == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _clone: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "reflinks are only supported on Linux"))
+}
+
+/// Whether a failed reflink should be retried as a regular copy.
+fn reflink_unsupported(error: &io::Error) -> bool {
+    #[cfg(target_os = "linux")]
+    if let Some(code) = error.raw_os_error() {
+        return [libc::EOPNOTSUPP, libc::EXDEV, libc::EINVAL, libc::ENOTTY].contains(&code);
+    }
+    error.kind() == io::ErrorKind::Unsupported
+}
+
+/// Moves `source` to `target`. Renaming only works within one file system, so
+/// when `target` is on another mount the file is copied, verified and then
+/// removed instead.
+pub fn move_file(source: &Path, target: &Path) -> io::Result<()> {
+    match fs::rename(source, target) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!("{} and {} are on different file systems, copying instead", source.display(), target.display());
+            copy_file(source, target)?;
+            fs::remove_file(source)?;
+            info!("Moved {} to {} by copying it", source.display(), target.display());
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Copies `source` to `target` like `copy_verify`, reporting the progress of
+/// large files on stderr.
+fn copy_file(source: &Path, target: &Path) -> io::Result<()> {
+    if fs::metadata(source)?.len() >= PROGRESS_THRESHOLD {
+        let mut progress = ProgressReporter::new(source);
+        copy_verify(source, target, &mut |copied, total| progress.update(copied, total))
+    } else {
+        copy_verify(source, target, &mut |_, _| {})
+    }
+}
+
+/// Copies `source` to `target`.
+///
+/// The contents are streamed into a temporary file next to `target` and
+/// synced to disk. The copy is read back and compared by size and BLAKE3 hash
+/// before it is renamed into place, so that `target` never holds a partial
+/// file. The source's permissions and access and modification times are kept.
+/// `progress` is called with the number of bytes copied so far and the total.
+pub fn copy_verify(source: &Path, target: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<()> {
+    create_in_place(target, |partial| copy_contents(source, partial, progress))?;
+    sync_parent(target)
+}
+
+fn copy_contents(source: &Path, partial: &Path, progress: &mut dyn FnMut(u64, u64)) -> io::Result<()> {
+    let total = fs::metadata(source)?.len();
+
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(partial)?;
+    let mut hasher = blake3::Hasher::new();
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut copied = 0;
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buffer[..read])?;
+        hasher.update(&buffer[..read]);
+        copied += read as u64;
+        progress(copied, total);
+    }
+    writer.sync_all()?;
+    drop(writer);
+
+    let copy_size = fs::metadata(partial)?.len();
+    if copy_size != total || copied != total {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("copy of {} has {} bytes, expected {}", source.display(), copy_size, total),
+        ));
+    }
+    if crate::dedupe::full_hash(partial)? != hasher.finalize() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("copy of {} does not match the original", source.display())));
+    }
+
+    copy_metadata(source, partial)
+}
+
+/// Gives `copy` the permissions and access and modification times of `source`.
+fn copy_metadata(source: &Path, copy: &Path) -> io::Result<()> {
+    let metadata = fs::metadata(source)?;
+    fs::set_permissions(copy, metadata.permissions())?;
+    filetime::set_file_times(
+        copy,
+        FileTime::from_last_access_time(&metadata),
+        FileTime::from_last_modification_time(&metadata),
+    )
+}
+
+/// Makes the rename of `path` durable by syncing its directory.
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => File::open(parent)?.sync_all(),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Prints the progress of a long copy to stderr in whole percent steps.
+struct ProgressReporter {
+    name: String,
+    last_percent: Option<u64>,
+}
+
+impl ProgressReporter {
+    fn new(source: &Path) -> Self {
+        let name = source.file_name().unwrap_or_default().to_string_lossy().to_string();
+        ProgressReporter { name, last_percent: None }
+    }
+
+    fn update(&mut self, copied: u64, total: u64) {
+        let percent = (copied * 100).checked_div(total).unwrap_or(100);
+        if self.last_percent == Some(percent) {
+            return;
+        }
+        self.last_percent = Some(percent);
+        eprint!("\rCopying {}: {:>3}% ({} of {} MiB)", self.name, percent, copied >> 20, total >> 20);
+        if copied >= total {
+            eprintln!();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_copy_verify_keeps_contents_and_times() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("clip.mp4");
+        let target = temp_dir.path().join("Videos").join("clip.mp4");
+        fs::create_dir_all(target.parent().unwrap()).unwrap();
+        let contents = vec![7u8; 3 * COPY_BUFFER_SIZE + 17];
+        fs::write(&source, &contents).unwrap();
+        let modified = FileTime::from_unix_time(1_600_000_000, 0);
+        filetime::set_file_mtime(&source, modified).unwrap();
+
+        let mut reports = Vec::new();
+        copy_verify(&source, &target, &mut |copied, total| reports.push((copied, total))).unwrap();
+
+        assert!(source.exists());
+        assert_eq!(fs::read(&target).unwrap(), contents);
+        assert_eq!(FileTime::from_last_modification_time(&fs::metadata(&target).unwrap()), modified);
+        assert!(!temporary_path(&target, PARTIAL_SUFFIX).exists());
+        assert_eq!(reports.len(), 4);
+        assert_eq!(reports.last(), Some(&(contents.len() as u64, contents.len() as u64)));
+    }
+
+    #[test]
+    fn test_failed_move_leaves_source_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("photo.jpg");
+        fs::write(&source, "photo").unwrap();
+        let target = temp_dir.path().join("missing").join("photo.jpg");
+
+        assert!(move_file(&source, &target).is_err());
+        assert!(source.exists());
+    }
+
+    #[test]
+    fn test_transfer_modes_leave_source_in_place() {
+        let temp_dir = tempdir().unwrap();
+        let source = temp_dir.path().join("card").join("IMG_0001.jpg");
+        fs::create_dir_all(source.parent().unwrap()).unwrap();
+        fs::write(&source, "photo").unwrap();
+
+        for mode in [TransferMode::Copy, TransferMode::Hardlink, TransferMode::Symlink, TransferMode::Reflink] {
+            let target = temp_dir.path().join(format!("{:?}.jpg", mode));
+            transfer_file(mode, &source, &target).unwrap();
+            assert_eq!(fs::read_to_string(&target).unwrap(), "photo", "{:?} did not create the file", mode);
+            assert!(source.exists(), "{:?} removed the source", mode);
+        }
+        assert!(fs::symlink_metadata(temp_dir.path().join("Symlink.jpg")).unwrap().file_type().is_symlink());
+    }
+}
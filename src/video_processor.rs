@@ -1,47 +1,33 @@
-use crate::organizer::organize_file;
-use crate::processing_mode::ProcessingMode;
+use crate::dates::DateResolver;
+use crate::metadata::video::{read_video_metadata, VideoMetadata};
+use crate::metadata::{date_folder, file_metadata, Embedded, Metadata};
 use crate::traits::processor::Processor;
-use std::fs;
-use std::path::PathBuf;
-use log::{debug, error};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-pub struct VideoProcessor;
+#[derive(Default)]
+pub struct VideoProcessor {
+    pub dates: Arc<DateResolver>,
+}
 
 impl Processor for VideoProcessor {
-    fn process(&self, path: &PathBuf, destination: &PathBuf, mode: &mut ProcessingMode) {
-        // Determine the destination directory without including the filename
-        let destination_dir = destination.join(self.get_destination_subfolder(path));
-
-
+    fn get_destination_subfolder(&self, _path: &Path, metadata: &Metadata) -> PathBuf {
+        PathBuf::from("Videos").join(date_folder(metadata))
+    }
 
-        // Now the destination path includes the filename correctly
-        let destination_path = destination_dir.join(path.file_name().unwrap());
+    fn category(&self) -> &str {
+        "Videos"
+    }
 
-        match mode {
-            ProcessingMode::DryRun(virtual_directory) => {
-                // In DryRun mode, simulate the action
-                debug!("Would move {} to {}", path.display(), destination_path.display());
-                let path_parts: Vec<String> = destination_path.iter().map(|s| s.to_string_lossy().to_string()).collect();
-                virtual_directory.add_path(&path_parts);
-            },
-            ProcessingMode::Live => {
-                // In Live mode, actually create the directory and move the file
-                // Ensure the directory structure exists
-                if let Err(e) = fs::create_dir_all(&destination_dir) {
-                    error!("Error creating destination directory: {}", e);
-                    return;
-                }
-                if let Err(e) = organize_file(path, &destination_path, mode) {
-                    error!("Failed to organize file: {}", e);
-                } else {
-                    debug!("Successfully moved file from {} to {}", path.display(), destination_path.display());
-                }
-            }
-        }
+    fn dates(&self) -> &DateResolver {
+        &self.dates
     }
 
-    fn get_destination_subfolder(&self, _path: &PathBuf) -> PathBuf {
-        PathBuf::from("Videos")
+    fn metadata(&self, path: &Path) -> Metadata {
+        let embedded = Embedded { video: read_video_metadata(path), ..Embedded::default() };
+        let mut metadata = file_metadata(path, &self.dates, &embedded);
+        metadata.extend(embedded.video.as_ref().map(VideoMetadata::fields).unwrap_or_default());
+        metadata
     }
 }
 
@@ -51,13 +37,17 @@ mod video_processor_tests {
 
 
     use super::*;
+    use crate::executor::execute;
+    use crate::organizer::OrganizeOptions;
+    use crate::processing_mode::ProcessingMode;
     use std::fs::{self, File};
     use std::io::Write;
     use tempfile::tempdir;
+    use chrono::{Datelike, Local};
 
     #[test]
     fn test_video_processor_live() {
-        let processor = VideoProcessor {};
+        let processor = VideoProcessor::default();
         let temp_dir = tempdir().unwrap();
         let source_dir = temp_dir.path().join("source");
         let destination_dir = temp_dir.path().join("destination");
@@ -68,11 +58,13 @@ mod video_processor_tests {
 
         let mut mode = ProcessingMode::Live;
 
-        processor.process(&video_file_path, &destination_dir, &mut mode);
+        execute(&processor.plan(&video_file_path, &destination_dir), &mut mode, &OrganizeOptions::default()).unwrap();
 
-        let expected_destination = destination_dir.join("Videos").join("test_video.mp4");
-        print!("{}", expected_destination.display());
+        // Without container metadata the modification time dates the video
+        let now = Local::now();
+        let expected_date_dir = format!("{}/{:02} - {}", now.year(), now.month(), now.format("%B"));
+        let expected_destination = destination_dir.join("Videos").join(expected_date_dir).join("test_video.mp4");
 
-        assert!(expected_destination.exists(), "Document was not moved to the correct destination in Live mode.");
+        assert!(expected_destination.exists(), "Video was not moved to the correct destination in Live mode.");
     }
 }
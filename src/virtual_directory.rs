@@ -1,12 +1,15 @@
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 #[derive(Default, Debug)]
 pub struct VirtualDirectory {
     files: Vec<String>,
     directories: HashMap<String, VirtualDirectory>,
+    sources: HashMap<String, PathBuf>, // Source file each planned file comes from, by file name
 }
 
 impl VirtualDirectory {
+    #[allow(dead_code)]
     pub fn add_path(&mut self, parts: &[String]) {
         if parts.is_empty() {
             return;
@@ -18,11 +21,41 @@ impl VirtualDirectory {
         } else {
             self.directories
                 .entry(first.clone())
-                .or_insert_with(VirtualDirectory::default)
+                .or_default()
                 .add_path(rest);
         }
     }
 
+    /// Adds a planned file and remembers which source file it comes from.
+    pub fn add_file(&mut self, parts: &[String], source: &Path) {
+        if parts.is_empty() {
+            return;
+        }
+
+        let (first, rest) = parts.split_first().unwrap();
+        if rest.is_empty() {
+            if !self.files.contains(first) {
+                self.files.push(first.clone());
+            }
+            self.sources.insert(first.clone(), source.to_path_buf());
+        } else {
+            self.directories
+                .entry(first.clone())
+                .or_default()
+                .add_file(rest, source);
+        }
+    }
+
+    /// Returns the source file of a file added with `add_file`.
+    pub fn source_of(&self, parts: &[String]) -> Option<&Path> {
+        let (first, rest) = parts.split_first()?;
+        if rest.is_empty() {
+            self.sources.get(first).map(PathBuf::as_path)
+        } else {
+            self.directories.get(first)?.source_of(rest)
+        }
+    }
+
     pub fn print(&self, prefix: &str) {
         let mut entries = self.directories.iter().collect::<Vec<_>>();
         entries.sort_by_key(|e| e.0);
@@ -62,7 +95,6 @@ impl VirtualDirectory {
         self.print("");
     }
 
-    #[allow(dead_code)]
     pub fn contains_file(&self, path: &[String]) -> bool {
         if path.is_empty() {
             return false;
@@ -86,6 +118,7 @@ impl VirtualDirectory {
 #[cfg(test)]
 mod tests {
 use crate::virtual_directory::VirtualDirectory;
+use std::path::Path;
 
     #[test]
     fn test_add_path_to_virtual_directory() {
@@ -111,4 +144,15 @@ use crate::virtual_directory::VirtualDirectory;
             "VirtualDirectory does not contain the expected path after direct addition.");
     }
 
+    #[test]
+    fn test_add_file_remembers_source() {
+        let mut virtual_dir = VirtualDirectory::default();
+        let path_components = vec!["destination".to_string(), "Images".to_string(), "photo.jpg".to_string()];
+        virtual_dir.add_file(&path_components, Path::new("source/photo.jpg"));
+        virtual_dir.add_file(&path_components, Path::new("other/photo.jpg"));
+
+        assert_eq!(virtual_dir.source_of(&path_components), Some(Path::new("other/photo.jpg")));
+        assert_eq!(virtual_dir.directories["destination"].directories["Images"].files.len(), 1);
+    }
+
 }
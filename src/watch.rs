@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use signal_hook::consts::{SIGINT, SIGTERM};
+
+use crate::executor::Placements;
+use crate::file_processor::{is_source_file, organize_files, source_files, ScanOptions};
+use crate::organizer::OrganizeOptions;
+use crate::plan::Fingerprint;
+use crate::processing_mode::ProcessingMode;
+use crate::sidecar::{find_primary, SidecarOptions};
+use crate::stability::{StabilityGate, Unsettled};
+use crate::summary::RunSummary;
+use crate::traits::ProcessorFactory;
+
+/// How often the watch loop checks for a shutdown request and for files that
+/// have settled, when no events arrive.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Collects the files that events were reported for until they have been
+/// quiet for a while. A file is ready once no event arrived for it during the
+/// quiet period and its size and modification time did not change meanwhile,
+/// so that files that are still being written are left alone.
+#[derive(Debug)]
+pub struct Debouncer {
+    quiet: Duration,
+    pending: BTreeMap<PathBuf, Pending>,
+}
+
+#[derive(Debug)]
+struct Pending {
+    last_event: Instant,
+    fingerprint: Option<Fingerprint>,
+}
+
+impl Debouncer {
+    pub fn new(quiet: Duration) -> Self {
+        Debouncer { quiet, pending: BTreeMap::new() }
+    }
+
+    /// Records an event for `path`, which restarts its quiet period.
+    pub fn touch(&mut self, path: PathBuf, now: Instant) {
+        let fingerprint = Fingerprint::of(&path).ok();
+        self.pending.insert(path, Pending { last_event: now, fingerprint });
+    }
+
+    /// Returns the files that have settled, in path order, and forgets them.
+    /// Files that changed without an event wait for another quiet period, and
+    /// files that disappeared are dropped.
+    pub fn ready(&mut self, now: Instant) -> Vec<PathBuf> {
+        let mut ready = Vec::new();
+        self.pending.retain(|path, pending| {
+            if now.duration_since(pending.last_event) < self.quiet {
+                return true;
+            }
+            match Fingerprint::of(path) {
+                Ok(fingerprint) if pending.fingerprint == Some(fingerprint) => {
+                    ready.push(path.clone());
+                    false
+                }
+                Ok(fingerprint) => {
+                    *pending = Pending { last_event: now, fingerprint: Some(fingerprint) };
+                    true
+                }
+                Err(_) => false,
+            }
+        });
+        ready
+    }
+
+    /// The number of files that have not settled yet.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+/// Organizes files as they arrive in the source directories until SIGTERM or
+/// SIGINT is received. Files go through the same planning and moves as those
+/// of a normal run. Sidecars follow their photo or video even if they arrive
+/// after it; `placements` holds where the files organized at startup went.
+/// A second signal ends the process right away.
+#[allow(clippy::too_many_arguments)]
+pub fn watch(
+    directories: &[PathBuf],
+    base_dest: &Path,
+    mode: &mut ProcessingMode,
+    factory: &dyn ProcessorFactory,
+    options: &OrganizeOptions,
+    scan: &ScanOptions,
+    quiet: Duration,
+    mut placements: Placements,
+    summary: &mut RunSummary,
+) -> Result<(), Box<dyn Error>> {
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&shutdown))?;
+        signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender)?;
+    let mut roots = Vec::new();
+    for directory in directories {
+        roots.push((directory, std::path::absolute(directory)?));
+        watcher.watch(directory, RecursiveMode::Recursive)
+            .map_err(|e| format!("Failed to watch {}: {}", directory.display(), e))?;
+    }
+    println!("Watching for new files, stop with Ctrl+C");
+
+    let mut debouncer = Debouncer::new(quiet);
+    let mut held = HashSet::new();
+    while !shutdown.load(Ordering::SeqCst) {
+        match receiver.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) if event.need_rescan() => {
+                // Events were lost, so every file may be new
+                for directory in directories {
+                    source_files(directory, scan).into_iter().for_each(|path| debouncer.touch(path, Instant::now()));
+                }
+            }
+            Ok(Ok(event)) => {
+                for path in arrived_files(&event) {
+                    // Events carry absolute paths, the rest of a run uses the source directories as given
+                    let source_path = roots.iter().find_map(|(directory, root)| {
+                        path.strip_prefix(root).ok().map(|relative| directory.join(relative))
+                    });
+                    debouncer.touch(source_path.unwrap_or(path), Instant::now());
+                }
+            }
+            Ok(Err(e)) => log::warn!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let quiet_files: Vec<PathBuf> = debouncer.ready(Instant::now())
+            .into_iter()
+            .filter(|path| directories.iter().any(|directory| is_source_file(directory, path, scan)))
+            .collect();
+        if quiet_files.is_empty() {
+            continue;
+        }
+        // Listing the files open for writing reads all of /proc, so only do it when there is something to check
+        let gate = StabilityGate::new(&scan.stability);
+        let mut ready = Vec::new();
+        for path in quiet_files {
+            match gate.check(&path, SystemTime::now()) {
+                None => ready.push(path),
+                // Downloads get their final name once they are complete, which is reported as a new file
+                Some(Unsettled::TemporaryName(_)) => {}
+                Some(_) => debouncer.touch(path, Instant::now()),
+            }
+        }
+        let ready = hold_lone_sidecars(ready, &placements, &scan.sidecars, &mut held, &mut debouncer, Instant::now());
+        if !ready.is_empty() {
+            println!("Organizing {} new file(s)", ready.len());
+            organize_files(&ready, base_dest, mode, factory, options, scan, &mut placements, summary);
+        }
+    }
+
+    if debouncer.pending() > 0 {
+        println!("Stopped watching, {} file(s) that were still changing are left in place", debouncer.pending());
+    }
+    Ok(())
+}
+
+/// Sends the sidecars among `ready` whose photo or video is neither ready nor
+/// organized yet back to the debouncer for one more quiet period, e.g. an
+/// `.xmp` that was saved before the photo finished copying. Sidecars that were
+/// held once are organized even if their file still has not turned up, and
+/// those of files organized earlier follow them through `placements`.
+fn hold_lone_sidecars(
+    ready: Vec<PathBuf>,
+    placements: &Placements,
+    sidecars: &SidecarOptions,
+    held: &mut HashSet<PathBuf>,
+    debouncer: &mut Debouncer,
+    now: Instant,
+) -> Vec<PathBuf> {
+    if !sidecars.enabled {
+        return ready;
+    }
+    let (lone, mut kept): (Vec<PathBuf>, Vec<PathBuf>) = ready.iter().cloned().partition(|path| {
+        sidecars.is_sidecar(path)
+            && find_primary(path, ready.iter().map(PathBuf::as_path)).is_none()
+            && find_primary(path, placements.keys().map(PathBuf::as_path)).is_none()
+    });
+    for path in &kept {
+        held.remove(path);
+    }
+    for path in lone {
+        if held.remove(&path) {
+            kept.push(path);
+        } else {
+            log::debug!("Waiting for the file that {} belongs to", path.display());
+            held.insert(path.clone());
+            debouncer.touch(path, now);
+        }
+    }
+    kept.sort();
+    kept
+}
+
+/// The files that an event says were created or written to. A directory that
+/// was created or moved in stands for all the files inside it.
+fn arrived_files(event: &Event) -> Vec<PathBuf> {
+    let arrived = match event.kind {
+        EventKind::Create(_) => event.paths.last(),
+        EventKind::Modify(ModifyKind::Data(_) | ModifyKind::Any) => event.paths.last(),
+        EventKind::Modify(ModifyKind::Name(RenameMode::To | RenameMode::Both | RenameMode::Any)) => event.paths.last(),
+        EventKind::Access(AccessKind::Close(AccessMode::Write)) => event.paths.last(),
+        _ => None,
+    };
+    match arrived {
+        Some(path) if path.is_dir() => source_files(path, &ScanOptions::default()),
+        Some(path) => vec![path.clone()],
+        None => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::traits::DefaultProcessorFactory;
+    use notify::event::CreateKind;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_debouncer_waits_until_files_settle() {
+        let temp_dir = tempdir().unwrap();
+        let settled = temp_dir.path().join("settled.jpg");
+        let growing = temp_dir.path().join("growing.mp4");
+        let deleted = temp_dir.path().join("deleted.txt");
+        for path in [&settled, &growing, &deleted] {
+            fs::write(path, "part").unwrap();
+        }
+
+        let quiet = Duration::from_secs(2);
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(quiet);
+        for path in [&settled, &growing, &deleted] {
+            debouncer.touch(path.clone(), start);
+        }
+        assert!(debouncer.ready(start + Duration::from_secs(1)).is_empty(), "Files must not be ready before the quiet period ends.");
+
+        fs::write(&growing, "partial content").unwrap();
+        fs::remove_file(&deleted).unwrap();
+        assert_eq!(debouncer.ready(start + quiet), vec![settled.clone()]);
+        assert_eq!(debouncer.pending(), 1, "A file that changed without an event must wait again, a deleted one is dropped.");
+
+        assert!(debouncer.ready(start + quiet + Duration::from_secs(1)).is_empty());
+        assert_eq!(debouncer.ready(start + quiet * 2), vec![growing]);
+        assert_eq!(debouncer.pending(), 0);
+    }
+
+    #[test]
+    fn test_arrived_files_expands_directories() {
+        let temp_dir = tempdir().unwrap();
+        let album = temp_dir.path().join("album");
+        fs::create_dir_all(album.join("day1")).unwrap();
+        fs::write(album.join("day1").join("a.jpg"), "a").unwrap();
+        fs::write(album.join("b.jpg"), "b").unwrap();
+
+        let created = Event::new(EventKind::Create(CreateKind::Folder)).add_path(album.clone());
+        let mut files = arrived_files(&created);
+        files.sort();
+        assert_eq!(files, vec![album.join("b.jpg"), album.join("day1").join("a.jpg")]);
+
+        let moved_away = Event::new(EventKind::Modify(ModifyKind::Name(RenameMode::From))).add_path(album.join("b.jpg"));
+        assert!(arrived_files(&moved_away).is_empty(), "Files that were moved away must be ignored.");
+    }
+
+    #[test]
+    fn test_sidecars_arriving_alone_follow_their_file() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("source");
+        let destination_dir = temp_dir.path().join("destination");
+        fs::create_dir_all(&source_dir).unwrap();
+        let photo = source_dir.join("IMG_0001.jpg");
+        let xmp = source_dir.join("IMG_0001.xmp");
+        let orphan = source_dir.join("IMG_0002.xmp");
+        for path in [&photo, &xmp, &orphan] {
+            fs::write(path, "content").unwrap();
+        }
+
+        let (scan, options, factory) = (ScanOptions::default(), OrganizeOptions::default(), DefaultProcessorFactory::default());
+        let (mut mode, mut summary) = (ProcessingMode::Live, RunSummary::default());
+        let mut placements = Placements::new();
+        let mut held = HashSet::new();
+        let start = Instant::now();
+        let mut debouncer = Debouncer::new(Duration::from_secs(1));
+
+        let ready = hold_lone_sidecars(vec![xmp.clone(), orphan.clone()], &placements, &scan.sidecars, &mut held, &mut debouncer, start);
+        assert!(ready.is_empty(), "Sidecars without their file must wait for it.");
+        assert_eq!(debouncer.pending(), 2);
+
+        organize_files(std::slice::from_ref(&photo), &destination_dir, &mut mode, &factory, &options, &scan, &mut placements, &mut summary);
+        let placed_photo = placements[&photo].clone();
+
+        let ready = debouncer.ready(start + Duration::from_secs(1));
+        let ready = hold_lone_sidecars(ready, &placements, &scan.sidecars, &mut held, &mut debouncer, start);
+        assert_eq!(ready, vec![xmp.clone(), orphan.clone()], "Sidecars are only held once.");
+        organize_files(&ready, &destination_dir, &mut mode, &factory, &options, &scan, &mut placements, &mut summary);
+
+        assert!(placed_photo.with_extension("xmp").exists(), "The sidecar must be placed next to its photo.");
+        assert!(destination_dir.join("Other_Files").join("IMG_0002.xmp").exists());
+        assert!(held.is_empty());
+    }
+}
@@ -0,0 +1,138 @@
+#!/usr/bin/env python3
+"""Writes the image fixtures next to this script.
+
+Each file is the smallest one of its format that carries EXIF data the way
+cameras and phones write it: a Make, a Model and a DateTimeOriginal. They
+hold no pixels. The capture dates are checked by the tests in
+src/metadata/image.rs, so keep the two in step when changing one.
+"""
+
+import os
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+ASCII, LONG = 2, 4
+
+
+def ifd_entries(entries, endian, data_offset):
+    """Packs an IFD holding `entries` (tag, type, value) whose values that do
+    not fit an entry start at `data_offset`. Returns the IFD and the data."""
+    ifd = struct.pack(endian + "H", len(entries))
+    data = b""
+    for tag, kind, value in sorted(entries):
+        if kind == ASCII:
+            raw = value.encode() + b"\0"
+            if len(raw) <= 4:
+                field = raw.ljust(4, b"\0")
+            else:
+                field = struct.pack(endian + "I", data_offset + len(data))
+                data += raw + (b"\0" if len(raw) % 2 else b"")
+            ifd += struct.pack(endian + "HHI", tag, kind, len(raw)) + field
+        else:
+            ifd += struct.pack(endian + "HHII", tag, kind, 1, value)
+    return ifd + struct.pack(endian + "I", 0), data
+
+
+def tiff(make, model, taken, big_endian=False, magic=None, extra_header=b""):
+    """A TIFF structure with IFD0 and an EXIF IFD holding the date. Raw
+    formats may use another `magic` number or add to the 8-byte header."""
+    endian = ">" if big_endian else "<"
+    order = b"MM" if big_endian else b"II"
+    magic = magic if magic is not None else struct.pack(endian + "H", 42)
+    header_size = 8 + len(extra_header)
+    header = order + magic + struct.pack(endian + "I", header_size) + extra_header
+
+    # IFD0, its data, then the EXIF IFD and its data
+    ifd0 = [(0x010F, ASCII, make), (0x0110, ASCII, model), (0x8769, LONG, 0)]
+    size0 = 2 + 12 * len(ifd0) + 4
+    _, data0 = ifd_entries(ifd0, endian, header_size + size0)
+    exif_offset = header_size + size0 + len(data0)
+    ifd0[-1] = (0x8769, LONG, exif_offset)
+    ifd, data0 = ifd_entries(ifd0, endian, header_size + size0)
+    exif = [(0x9003, ASCII, taken)]
+    exif_ifd, exif_data = ifd_entries(exif, endian, exif_offset + 2 + 12 * len(exif) + 4)
+    return header + ifd + data0 + exif_ifd + exif_data
+
+
+def single_ifd_tiff(entries):
+    """A TIFF structure with only IFD0, as in the IFD boxes of CR3."""
+    ifd, data = ifd_entries(entries, "<", 8 + 2 + 12 * len(entries) + 4)
+    return b"II*\0" + struct.pack("<I", 8) + ifd + data
+
+
+def box(kind, payload):
+    return struct.pack(">I", 8 + len(payload)) + kind + payload
+
+
+def full_box(kind, version, payload, flags=0):
+    return box(kind, struct.pack(">I", version << 24 | flags) + payload)
+
+
+def heif(brand, compatible, exif_tiff, in_idat):
+    """A HEIF file whose only item is the EXIF block, located in `mdat` by an
+    `iloc` version 0 or in `idat` by an `iloc` version 1."""
+    exif_item = struct.pack(">I", 6) + b"Exif\0\0" + exif_tiff
+    ftyp = box(b"ftyp", brand + struct.pack(">I", 0) + b"".join(compatible))
+    hdlr = full_box(b"hdlr", 0, struct.pack(">I", 0) + b"pict" + b"\0" * 12 + b"\0")
+    infe = full_box(b"infe", 2, struct.pack(">HH", 1, 0) + b"Exif" + b"\0")
+    iinf = full_box(b"iinf", 0, struct.pack(">H", 1) + infe)
+
+    def iloc(offset):
+        if in_idat:
+            entry = struct.pack(">HHHHII", 1, 1, 0, 1, offset, len(exif_item))
+            return full_box(b"iloc", 1, bytes([0x44, 0x00]) + struct.pack(">H", 1) + entry)
+        entry = struct.pack(">HHHII", 1, 0, 1, offset, len(exif_item))
+        return full_box(b"iloc", 0, bytes([0x44, 0x00]) + struct.pack(">H", 1) + entry)
+
+    if in_idat:
+        meta = full_box(b"meta", 0, hdlr + iinf + iloc(0) + box(b"idat", exif_item))
+        return ftyp + meta
+    meta_size = len(full_box(b"meta", 0, hdlr + iinf + iloc(0)))
+    offset = len(ftyp) + meta_size + 8
+    return ftyp + full_box(b"meta", 0, hdlr + iinf + iloc(offset)) + box(b"mdat", exif_item)
+
+
+def cr3(make, model, taken):
+    """A CR3 file: the IFDs are separate TIFF structures in the CMT boxes of
+    Canon's `uuid` box in `moov`."""
+    uuid = bytes.fromhex("85c0b687820f11e08111f4ce462b6a48")
+    cmt1 = box(b"CMT1", single_ifd_tiff([(0x010F, ASCII, make), (0x0110, ASCII, model)]))
+    cmt2 = box(b"CMT2", single_ifd_tiff([(0x9003, ASCII, taken)]))
+    canon = box(b"uuid", uuid + box(b"CNCV", b"CanonCR3_001/00.09.00/00.00.00") + cmt1 + cmt2)
+    ftyp = box(b"ftyp", b"crx " + struct.pack(">I", 1) + b"crx " + b"isom")
+    return ftyp + box(b"moov", canon)
+
+
+def raf(make, model, taken):
+    """A RAF file: a header pointing at a JPEG preview with the EXIF data."""
+    exif = b"Exif\0\0" + tiff(make, model, taken, big_endian=True)
+    jpeg = b"\xFF\xD8" + b"\xFF\xE1" + struct.pack(">H", 2 + len(exif)) + exif + b"\xFF\xD9"
+    header = b"FUJIFILMCCD-RAW 0201FF383501" + model.encode().ljust(32, b"\0")
+    header = header.ljust(60, b"\0") + b"0100" + b"\0" * 20
+    header += struct.pack(">II", 100, len(jpeg))
+    return header.ljust(100, b"\0") + jpeg
+
+
+def jpeg(make, model, taken):
+    exif = b"Exif\0\0" + tiff(make, model, taken)
+    return b"\xFF\xD8" + b"\xFF\xE1" + struct.pack(">H", 2 + len(exif)) + exif + b"\xFF\xD9"
+
+
+FIXTURES = {
+    "photo.jpg": jpeg("Apple", "iPhone 12", "2020:01:02 03:04:05"),
+    "photo.heic": heif(b"heic", [b"mif1", b"heic"], tiff("Apple", "iPhone 15 Pro", "2021:04:05 10:11:12", big_endian=True), False),
+    "photo.avif": heif(b"avif", [b"avif", b"mif1", b"miaf"], tiff("Google", "Pixel 8", "2022:06:07 08:09:10"), True),
+    "photo.cr3": cr3("Canon", "Canon EOS R5", "2023:03:03 13:14:15"),
+    "photo.cr2": tiff("Canon", "Canon EOS 5D Mark IV", "2019:07:08 09:10:11", extra_header=b"CR\x02\0" + b"\0" * 4),
+    "photo.nef": tiff("NIKON CORPORATION", "NIKON Z 6", "2018:02:03 04:05:06", big_endian=True),
+    "photo.arw": tiff("SONY", "ILCE-7M3", "2017:11:12 13:14:15"),
+    "photo.dng": tiff("Ricoh", "GR III", "2016:05:06 07:08:09"),
+    "photo.orf": tiff("OLYMPUS CORPORATION", "E-M1MarkIII", "2015:09:10 11:12:13", magic=b"RO"),
+    "photo.rw2": tiff("Panasonic", "DC-GH5", "2014:12:13 14:15:16", magic=b"U\0", extra_header=b"\0" * 16),
+    "photo.raf": raf("FUJIFILM", "X-T4", "2013:10:11 12:13:14"),
+}
+
+for name, contents in FIXTURES.items():
+    with open(os.path.join(HERE, name), "wb") as file:
+        file.write(contents)